@@ -0,0 +1,526 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::{
+    cpu::texture::{CpuTexture, Resample},
+    error::Result,
+    gpu::gpu::AdapterOptions,
+    spaceengineers::planet_generator_definition::HillParams,
+    spacelab::{
+        backend::BackendKind,
+        lutgen::{TerrainScale, CUBEMAP},
+        matcolormap::{MaterialBlend, MaterialView, PlanetMaterial},
+        oreoverlay_gpu::OreOverlayStyle,
+        orepalette::OrePalette,
+        cubeexport::{CubeExportOptions, CubeFormat},
+        projection::{Filter, Projection},
+        tiles::{TileOptions, TileScheme},
+    },
+};
+
+// SurfaceDetail transition of the game's planets, for material color maps made before it was kept
+const DEFAULT_TRANSITION: f32 = 10.0;
+
+#[derive(Debug, Parser)]
+#[command(name = "nextgen", about = "Space Engineers planet texture generator")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Generate albedo and normal maps for the selected planets
+    Generate(GenerateArgs),
+    /// List the planets found in the material color map
+    ListPlanets(LutArgs),
+    /// Print the material and ore definitions of a planet
+    Inspect(InspectArgs),
+    /// Add the planets of a `.sbc` file to the material color map
+    Import(ImportArgs),
+    /// Find the ore sites of the selected planets and write their planetmeta.json
+    Meta(MetaArgs),
+    /// Turn the six face images of a planet into one equirectangular, Mercator or polar map
+    Project(ProjectArgs),
+    /// Pack the six face images of a planet into one KTX2 or DDS cube map
+    Cubemap(CubemapArgs),
+    /// Cut the face images of a planet, or a projected map, into a z/x/y tile pyramid
+    Tiles(TilesArgs),
+    /// List the GPU adapters found, with their graphics API and limits
+    Adapters(AdapterArgs),
+    /// Open the 3D planet viewer
+    View,
+    /// Compare the CPU and GPU backends on every planet definition
+    Parity(ParityArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct LutArgs {
+    /// Folder containing matcolormap.json
+    #[arg(long, default_value = "../luts", env = "SPACELAB_LUTS")]
+    pub luts: PathBuf,
+    /// Ore colors, symbols and names, JSON or TOML. Defaults to orepalette.json in the luts folder.
+    #[arg(long, env = "SPACELAB_ORE_PALETTE")]
+    pub ore_palette: Option<PathBuf>,
+}
+
+impl LutArgs {
+    pub fn matcolormap_path(&self) -> PathBuf {
+        self.luts.join("matcolormap.json")
+    }
+    pub fn matfiles_path(&self) -> PathBuf {
+        self.luts.join("matfiles.json")
+    }
+    pub fn matcoloravg_path(&self) -> PathBuf {
+        self.luts.join("matcoloravg.json")
+    }
+
+    /// The given palette, else `orepalette.json` when the luts folder has one, else the built-in one.
+    pub fn ore_palette(&self) -> Result<OrePalette> {
+        match &self.ore_palette {
+            Some(path) => OrePalette::from_file(path),
+            None => {
+                let path = self.luts.join("orepalette.json");
+                if path.exists() {
+                    OrePalette::from_file(&path)
+                } else {
+                    Ok(OrePalette::default())
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct AdapterArgs {
+    /// GPU adapter to use, any part of its name. Defaults to the one picked by `--power`.
+    #[arg(long, env = "SPACELAB_ADAPTER")]
+    pub adapter: Option<String>,
+    /// Graphics APIs to look for adapters on, comma separated: vulkan, metal, dx12, dx11 or gl.
+    /// Defaults to all of them.
+    #[arg(long, env = "SPACELAB_GPU_API", value_parser = parse_backends)]
+    pub gpu_api: Option<wgpu::Backends>,
+    /// Adapter preferred when none is named: high (discrete) or low (integrated)
+    #[arg(long, default_value = "high", env = "SPACELAB_POWER", value_parser = parse_power_preference)]
+    pub power: wgpu::PowerPreference,
+    /// Use software rasterizers like LavaPipe, SwiftShader or WARP, for machines without a GPU
+    #[arg(long, env = "SPACELAB_ALLOW_SOFTWARE", value_parser = clap::builder::BoolishValueParser::new())]
+    pub allow_software: bool,
+}
+
+impl AdapterArgs {
+    pub fn options(&self) -> AdapterOptions {
+        AdapterOptions {
+            name: self.adapter.clone(),
+            backends: self.gpu_api.unwrap_or(wgpu::Backends::all()),
+            power_preference: self.power,
+            allow_software: self.allow_software,
+        }
+    }
+}
+
+fn parse_backends(s: &str) -> std::result::Result<wgpu::Backends, String> {
+    s.split(',')
+        .map(|api| match api.trim().to_lowercase().as_str() {
+            "vulkan" | "vk" => Ok(wgpu::Backends::VULKAN),
+            "metal" => Ok(wgpu::Backends::METAL),
+            "dx12" | "d3d12" => Ok(wgpu::Backends::DX12),
+            "dx11" | "d3d11" => Ok(wgpu::Backends::DX11),
+            "gl" | "gles" | "opengl" => Ok(wgpu::Backends::GL),
+            _ => Err(format!("Invalid graphics API {}, expected vulkan, metal, dx12, dx11 or gl", api)),
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map(|apis| apis.into_iter().fold(wgpu::Backends::empty(), |all, api| all | api))
+}
+
+fn parse_power_preference(s: &str) -> std::result::Result<wgpu::PowerPreference, String> {
+    match s.to_lowercase().as_str() {
+        "high" => Ok(wgpu::PowerPreference::HighPerformance),
+        "low" => Ok(wgpu::PowerPreference::LowPower),
+        _ => Err(format!("Invalid power preference {}, expected high or low", s)),
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct PlanetFilter {
+    /// Planet names to include, `*` and `?` wildcards allowed. Defaults to every planet.
+    #[arg(short, long = "planet")]
+    pub planets: Vec<String>,
+    /// Planet names to skip, `*` and `?` wildcards allowed. A planet named exactly with `--planet` is
+    /// never skipped.
+    #[arg(long = "exclude", default_value = "* - Lava")]
+    pub excludes: Vec<String>,
+}
+
+impl PlanetFilter {
+    pub fn matches(&self, name: &str) -> bool {
+        if self.planets.iter().any(|planet| planet == name) {
+            return true;
+        }
+        if self.excludes.iter().any(|pattern| glob_match(pattern, name)) {
+            return false;
+        }
+        self.planets.is_empty() || self.planets.iter().any(|pattern| glob_match(pattern, name))
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct GenerateArgs {
+    #[command(flatten)]
+    pub luts: LutArgs,
+    /// Root the planet `BaseFolder` paths are relative to
+    #[arg(long, default_value = "..", env = "SPACELAB_ASSETS")]
+    pub assets: PathBuf,
+    /// Folder where a sub folder per planet is written
+    #[arg(short, long, default_value = ".")]
+    pub output: PathBuf,
+    #[command(flatten)]
+    pub filter: PlanetFilter,
+    /// Faces to generate. Defaults to all six.
+    #[arg(long = "face", value_parser = clap::builder::PossibleValuesParser::new(CUBEMAP))]
+    pub faces: Vec<String>,
+    /// Size of the output images. The heightmaps and material maps are resampled to it and
+    /// every stage runs at that size. Defaults to the heightmap size.
+    #[arg(short, long)]
+    pub resolution: Option<u32>,
+    /// Filter resampling the heightmaps to `--resolution`: nearest, bilinear, bicubic or lanczos.
+    /// Material maps always use nearest, their values are ids.
+    #[arg(long, default_value = "bilinear", requires = "resolution")]
+    pub resample: Resample,
+    /// Image format of the outputs, picked by file extension
+    #[arg(short, long, default_value = "jpg")]
+    pub format: String,
+    #[arg(long, default_value = "auto", env = "SPACELAB_BACKEND")]
+    pub backend: BackendKind,
+    #[command(flatten)]
+    pub adapter: AdapterArgs,
+    /// Largest texture the backend gets, in texels. Larger faces are processed in tiles of
+    /// this size. Defaults to the backend's limit.
+    #[arg(long)]
+    pub tile_size: Option<u32>,
+    /// Compute the normals and slopes of every face on its own, leaving seams at the face edges
+    #[arg(long)]
+    pub no_seamless: bool,
+    /// Planet radius in metres. Scales slopes and normals to the real terrain, so the slope
+    /// ranges of the material rules match the game. Off by default: planet definitions carry no
+    /// size, the game picks it when the planet is spawned. Without it slopes are measured in
+    /// 8 bit height steps per texel, like the Python tools.
+    #[arg(long)]
+    pub radius: Option<f32>,
+    /// Hill min and max as fractions of the radius. Defaults to the planet's HillParams.
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["MIN", "MAX"],
+        allow_negative_numbers = true,
+        requires = "radius"
+    )]
+    pub hill_params: Option<Vec<f32>>,
+    /// Draw the material layers found this many metres under the surface
+    #[arg(long, default_value_t = 0.0)]
+    pub dig_depth: f32,
+    /// Slope in degrees above which the surface layer is worn away, showing the layer under it
+    #[arg(long)]
+    pub exposed_slope: Option<f32>,
+    /// Blend neighbouring material rules instead of cutting at their range edges
+    #[arg(long)]
+    pub blend: bool,
+    /// Latitude and slope transition in degrees. Defaults to the planet's SurfaceDetail transition.
+    #[arg(long, requires = "blend")]
+    pub blend_width: Option<f32>,
+    /// Height transition, in heightmap units from 0 to 1
+    #[arg(long, default_value_t = 0.02, requires = "blend")]
+    pub blend_height: f32,
+    /// Also write a transparent `<face>_ores.png` overlay with the ore deposits
+    #[arg(long)]
+    pub ores: bool,
+    /// Darken the edges of the deposits on the ore overlay
+    #[arg(long, requires = "ores")]
+    pub ore_outline: bool,
+    /// Radius in texels of the glow around the deposits on the ore overlay
+    #[arg(long, default_value_t = 0, requires = "ores")]
+    pub ore_glow: u32,
+    /// Regenerate every output, even the ones whose inputs haven't changed since the last run
+    #[arg(long)]
+    pub force: bool,
+    /// Write the time and bytes spent in every stage, per planet and face, to this JSON file.
+    /// Stages are also timed on the GPU when the adapter supports timestamp queries.
+    #[arg(long)]
+    pub profile: Option<PathBuf>,
+}
+
+impl GenerateArgs {
+    pub fn faces(&self) -> Vec<&str> {
+        if self.faces.is_empty() {
+            return CUBEMAP.to_vec();
+        }
+        CUBEMAP
+            .iter()
+            .filter(|face| self.faces.iter().any(|f| f == *face))
+            .copied()
+            .collect()
+    }
+
+    /// Loads an input texture, resampled to the output resolution.
+    pub fn load_input(
+        &self,
+        path: &Path,
+        format: wgpu::TextureFormat,
+        filter: Resample,
+    ) -> Result<CpuTexture> {
+        let texture = CpuTexture::from_file(&path.to_string_lossy(), format)?;
+        match self.resolution {
+            Some(size) => texture.resized(size, size, filter),
+            None => Ok(texture),
+        }
+    }
+
+    pub fn material_view(&self, planet: &PlanetMaterial) -> MaterialView {
+        let blend = self.blend.then(|| {
+            let width = self.blend_width.or(planet.transition).unwrap_or(DEFAULT_TRANSITION);
+            MaterialBlend::from_transition(width, self.blend_height)
+        });
+        MaterialView {
+            depth: self.dig_depth,
+            exposed_slope: self.exposed_slope,
+            blend,
+        }
+    }
+
+    pub fn terrain_scale(&self, planet: &PlanetMaterial) -> Option<TerrainScale> {
+        let radius = self.radius?;
+        let defaults = HillParams::default();
+        let [hill_min, hill_max] = match &self.hill_params {
+            Some(params) => [params[0], params[1]],
+            None => planet.hill_params.unwrap_or([defaults.min, defaults.max]),
+        };
+        Some(TerrainScale {
+            radius,
+            hill_min,
+            hill_max,
+        })
+    }
+
+    pub fn ore_overlay_style(&self) -> OreOverlayStyle {
+        OreOverlayStyle {
+            outline: self.ore_outline,
+            glow: self.ore_glow,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct InspectArgs {
+    #[command(flatten)]
+    pub luts: LutArgs,
+    /// Planet name, as listed by `list-planets`
+    pub planet: String,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    #[command(flatten)]
+    pub luts: LutArgs,
+    /// Planet generator definition file
+    pub sbc: PathBuf,
+    /// Folder holding the planet heightmaps and material maps, relative to `--assets` when generating
+    #[arg(long)]
+    pub base_folder: String,
+    /// Planet names to import, `*` and `?` wildcards allowed. Defaults to every planet in the file.
+    #[arg(short, long = "planet")]
+    pub planets: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct MetaArgs {
+    #[command(flatten)]
+    pub luts: LutArgs,
+    /// Root the planet `BaseFolder` paths are relative to
+    #[arg(long, default_value = "..", env = "SPACELAB_ASSETS")]
+    pub assets: PathBuf,
+    /// Folder the `<name>_planetmeta.json` files are written to
+    #[arg(short, long, default_value = "../planetmeta")]
+    pub output: PathBuf,
+    #[command(flatten)]
+    pub filter: PlanetFilter,
+}
+
+#[derive(Debug, Args)]
+pub struct FaceImages {
+    /// Folder holding the face images, like a planet folder written by `generate`
+    pub input: PathBuf,
+    /// File name of the face images, with `{face}` standing for the face name.
+    /// `{face}_normal.jpg` for normal maps, `{face}_ores.png` for ore overlays.
+    #[arg(long, default_value = "{face}.jpg")]
+    pub pattern: String,
+}
+
+impl FaceImages {
+    pub fn path(&self, face: &str) -> PathBuf {
+        self.input.join(self.pattern.replace("{face}", face))
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ProjectArgs {
+    #[command(flatten)]
+    pub faces: FaceImages,
+    /// Output image, format picked by file extension
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// equirectangular, mercator, polar-north or polar-south
+    #[arg(short, long, default_value = "equirectangular")]
+    pub projection: Projection,
+    /// Width of the map. The height follows from the projection.
+    #[arg(short, long, default_value_t = 4096)]
+    pub width: u32,
+    /// nearest or bilinear. Use nearest for material maps, where texels are ids.
+    #[arg(long, default_value = "bilinear")]
+    pub filter: Filter,
+    /// The faces are 16 bit heightmaps. The map is written as 16 bit grayscale to `.png`
+    /// and as floats to `.exr` and `.raw`.
+    #[arg(long)]
+    pub heightmap: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct CubemapArgs {
+    #[command(flatten)]
+    pub faces: FaceImages,
+    /// Output file, `.ktx2` or `.dds`
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// rgba8, or bc1 / bc3 for albedo and bc5 for normal maps
+    #[arg(short, long, default_value = "rgba8")]
+    pub format: CubeFormat,
+    /// Only write the full size faces
+    #[arg(long)]
+    pub no_mipmaps: bool,
+    /// Mark the texels as sRGB, for albedo maps. KTX2 only.
+    #[arg(long)]
+    pub srgb: bool,
+}
+
+impl CubemapArgs {
+    pub fn options(&self) -> CubeExportOptions {
+        CubeExportOptions {
+            format: self.format,
+            mipmaps: !self.no_mipmaps,
+            srgb: self.srgb,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct TilesArgs {
+    #[command(flatten)]
+    pub faces: FaceImages,
+    /// The input is a single map image, like one written by `project`, instead of a folder
+    /// of face images
+    #[arg(long, conflicts_with_all = ["scheme", "pattern"])]
+    pub map: bool,
+    /// Folder the tiles and manifest.json are written to
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// face for a quadtree per cube face, mercator for standard web map tiles
+    #[arg(long, default_value = "face")]
+    pub scheme: TileScheme,
+    /// Width and height of the tiles
+    #[arg(long, default_value_t = 256)]
+    pub tile_size: u32,
+    /// Deepest zoom level. Defaults to the level showing the source at full resolution.
+    #[arg(long)]
+    pub max_zoom: Option<u32>,
+    /// Image format of the tiles: png, jpg, or webp in builds with the `webp` feature
+    #[arg(short, long, default_value = "jpg")]
+    pub format: String,
+}
+
+impl TilesArgs {
+    pub fn options(&self) -> TileOptions {
+        TileOptions {
+            tile_size: self.tile_size,
+            max_zoom: self.max_zoom,
+            format: self.format.to_lowercase(),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ParityArgs {
+    #[command(flatten)]
+    pub luts: LutArgs,
+    #[command(flatten)]
+    pub filter: PlanetFilter,
+    #[command(flatten)]
+    pub adapter: AdapterArgs,
+    /// Size of the synthetic test faces
+    #[arg(long, default_value_t = 256)]
+    pub size: u32,
+    /// Largest per channel difference, in 8 bit steps, still considered equal
+    #[arg(long, default_value_t = 1)]
+    pub tolerance: u8,
+}
+
+/// Matches `name` against a pattern where `*` is any run of characters and `?` any single one.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = backtrack {
+            p = star_p + 1;
+            n = star_n + 1;
+            backtrack = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(planets: &[&str], excludes: &[&str]) -> PlanetFilter {
+        PlanetFilter {
+            planets: planets.iter().map(|s| s.to_string()).collect(),
+            excludes: excludes.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(glob_match("EarthLike", "EarthLike"));
+        assert!(!glob_match("EarthLike", "EarthLikeX"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("* - Lava", "Mars - Lava"));
+        assert!(!glob_match("* - Lava", "Mars"));
+        assert!(glob_match("Ea?th*", "EarthLike"));
+        assert!(!glob_match("Ea?th*", "Eath"));
+        assert!(glob_match("*a*a*", "banana"));
+        assert!(!glob_match("*x*", "banana"));
+        assert!(glob_match("**Like", "EarthLike"));
+    }
+
+    #[test]
+    fn exact_planet_overrides_exclude() {
+        let defaults = filter(&[], &["* - Lava"]);
+        assert!(defaults.matches("Mars"));
+        assert!(!defaults.matches("Mars - Lava"));
+
+        assert!(filter(&["Mars - Lava"], &["* - Lava"]).matches("Mars - Lava"));
+        assert!(!filter(&["Mars*"], &["* - Lava"]).matches("Mars - Lava"));
+        assert!(!filter(&["Mars"], &["* - Lava"]).matches("Moon"));
+    }
+}
@@ -0,0 +1,456 @@
+use std::{path::Path, str::FromStr};
+
+use image::{imageops, DynamicImage, ImageBuffer, Luma, Rgb, Rgba};
+use rayon::prelude::*;
+
+use crate::{
+    error::{check_data_len, check_layers, check_size, Error, Result},
+    gpu::texture::load_texels,
+};
+
+/// Filter used to resample input textures to the output resolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resample {
+    /// Closest texel, the only one that keeps ids like the material map values intact
+    Nearest,
+    Bilinear,
+    Bicubic,
+    Lanczos,
+}
+
+impl FromStr for Resample {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "nearest" => Ok(Resample::Nearest),
+            "bilinear" => Ok(Resample::Bilinear),
+            "bicubic" => Ok(Resample::Bicubic),
+            "lanczos" => Ok(Resample::Lanczos),
+            _ => Err(format!("Invalid filter {}, expected nearest, bilinear, bicubic or lanczos", s)),
+        }
+    }
+}
+
+impl Resample {
+    fn filter_type(&self) -> imageops::FilterType {
+        match self {
+            Resample::Nearest => imageops::FilterType::Nearest,
+            Resample::Bilinear => imageops::FilterType::Triangle,
+            Resample::Bicubic => imageops::FilterType::CatmullRom,
+            Resample::Lanczos => imageops::FilterType::Lanczos3,
+        }
+    }
+}
+
+/// In-memory counterpart of `gpu::texture::Texture`.
+///
+/// Texels are kept in the same raw layout the GPU uses for the texture format, so
+/// loads and stores quantize exactly like `textureLoad` / `textureStore` do.
+///
+/// Like GPU textures, a texture can hold several layers of the same size one after the
+/// other, the faces of a cube map for instance. Layered textures are put together with
+/// `from_layers` and taken apart with `layer`, everything else works on the first layer.
+#[derive(Debug, Clone)]
+pub struct CpuTexture {
+    width: u32,
+    height: u32,
+    layers: u32,
+    format: wgpu::TextureFormat,
+    data: Vec<u8>,
+}
+
+impl CpuTexture {
+    pub fn new(width: u32, height: u32, format: wgpu::TextureFormat) -> Result<Self> {
+        let texel_size = check_format(format)?;
+        Ok(CpuTexture {
+            width,
+            height,
+            layers: 1,
+            format,
+            data: vec![0; width as usize * height as usize * texel_size],
+        })
+    }
+
+    pub fn from_file(path: &str, format: wgpu::TextureFormat) -> Result<Self> {
+        let (width, height, texels) = load_texels(path)?;
+
+        CpuTexture::from_data(width, height, format, texels)
+    }
+
+    pub fn from_data(
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        data: Vec<u8>,
+    ) -> Result<Self> {
+        CpuTexture::from_layer_data(width, height, 1, format, data)
+    }
+
+    /// Same as `from_data`, with `layers` layers one after the other in `data`.
+    pub fn from_layer_data(
+        width: u32,
+        height: u32,
+        layers: u32,
+        format: wgpu::TextureFormat,
+        data: Vec<u8>,
+    ) -> Result<Self> {
+        let texel_size = check_format(format)?;
+        check_data_len("Texture data", width, height * layers, texel_size, data.len())?;
+        Ok(CpuTexture {
+            width,
+            height,
+            layers,
+            format,
+            data,
+        })
+    }
+
+    /// Stacks textures of the same size and format as the layers of a single texture.
+    pub fn from_layers(mut layers: Vec<CpuTexture>) -> Result<Self> {
+        if layers.len() == 1 {
+            return Ok(layers.pop().unwrap());
+        }
+        let first = layers.first().ok_or_else(|| Error::BadDefinition("No texture layers".to_owned()))?;
+        let (width, height, format) = (first.width, first.height, first.format);
+        let mut data = Vec::with_capacity(first.data.len() * layers.len());
+        for layer in &layers {
+            check_size("Texture layer", (width, height), (layer.width, layer.height))?;
+            if layer.format != format || layer.layers != 1 {
+                return Err(Error::UnsupportedFormat(format!(
+                    "stacking {:?} with {} layers as a layer of {:?}",
+                    layer.format, layer.layers, format
+                )));
+            }
+            data.extend_from_slice(&layer.data);
+        }
+        CpuTexture::from_layer_data(width, height, layers.len() as u32, format, data)
+    }
+
+    /// Copy of one of the layers.
+    pub fn layer(&self, layer: u32) -> CpuTexture {
+        let size = self.data.len() / self.layers as usize;
+        let start = layer as usize * size;
+        CpuTexture {
+            layers: 1,
+            data: self.data[start..start + size].to_vec(),
+            ..*self
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn layers(&self) -> u32 {
+        self.layers
+    }
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+    pub fn data(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+
+    /// Reads a texel, returning it the way `textureLoad` would.
+    pub fn load(&self, x: u32, y: u32) -> [f32; 4] {
+        let texel_size = texel_size(self.format);
+        let offset = (y as usize * self.width as usize + x as usize) * texel_size;
+        decode_texel(self.format, &self.data[offset..offset + texel_size])
+    }
+
+    /// Fills the texture in parallel, calling `f(x, y)` for every texel.
+    pub fn fill<F>(&mut self, f: F)
+    where
+        F: Fn(u32, u32) -> [f32; 4] + Sync,
+    {
+        let width = self.width as usize;
+        let format = self.format;
+        let texel_size = texel_size(format);
+        let layer_size = width * self.height as usize * texel_size;
+        self.data[..layer_size]
+            .par_chunks_mut(width * texel_size)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for (x, texel) in row.chunks_exact_mut(texel_size).enumerate() {
+                    encode_texel(format, f(x as u32, y as u32), texel);
+                }
+            });
+    }
+
+    /// Copies `source`, in the same format, into the texture with its top left corner at `(x, y)`.
+    pub fn paste(&mut self, source: &CpuTexture, x: u32, y: u32) -> Result<()> {
+        if source.format != self.format {
+            return Err(Error::UnsupportedFormat(format!(
+                "pasting {:?} into {:?}",
+                source.format, self.format
+            )));
+        }
+        let room = (self.width.saturating_sub(x), self.height.saturating_sub(y));
+        if source.width > room.0 || source.height > room.1 {
+            return Err(Error::SizeMismatch {
+                name: "Pasted texture".to_owned(),
+                expected: room,
+                found: (source.width, source.height),
+            });
+        }
+
+        let texel_size = texel_size(self.format);
+        let row_size = source.width as usize * texel_size;
+        for (row, source_row) in source.data.chunks_exact(row_size).enumerate() {
+            let offset = ((y as usize + row) * self.width as usize + x as usize) * texel_size;
+            self.data[offset..offset + row_size].copy_from_slice(source_row);
+        }
+        Ok(())
+    }
+
+    /// Copy of the texture resampled to `width` x `height`, in the same format.
+    pub fn resized(&self, width: u32, height: u32, filter: Resample) -> Result<CpuTexture> {
+        if (width, height) == (self.width, self.height) {
+            return Ok(self.clone());
+        }
+        // Resampled as floats, so every format goes through the same filters
+        let image = ImageBuffer::from_fn(self.width, self.height, |x, y| Rgba(self.load(x, y)));
+        let image = imageops::resize(&image, width, height, filter.filter_type());
+
+        let mut texture = CpuTexture::new(width, height, self.format)?;
+        texture.fill(|x, y| image.get_pixel(x, y).0);
+        Ok(texture)
+    }
+
+    /// The texels as an image, without losing precision: RGBA8 stays RGBA8 and float formats
+    /// become 32 bit float RGB, single channel ones repeated as gray. Only for single layer
+    /// textures, pick the others out with `layer`.
+    pub fn to_image(&self) -> Result<DynamicImage> {
+        check_layers("Image", 1, self.layers)?;
+        Ok(match self.format {
+            wgpu::TextureFormat::Rgba8Unorm => DynamicImage::ImageRgba8(
+                ImageBuffer::<Rgba<u8>, _>::from_raw(self.width, self.height, self.data.clone()).unwrap(),
+            ),
+            format => {
+                let mut samples = Vec::with_capacity(self.width as usize * self.height as usize * 3);
+                for y in 0..self.height {
+                    for x in 0..self.width {
+                        let [r, g, _, _] = self.load(x, y);
+                        match format {
+                            wgpu::TextureFormat::R32Float => samples.extend_from_slice(&[r, r, r]),
+                            _ => samples.extend_from_slice(&[r, g, 0.0]),
+                        }
+                    }
+                }
+                DynamicImage::ImageRgb32F(
+                    ImageBuffer::<Rgb<f32>, _>::from_raw(self.width, self.height, samples).unwrap(),
+                )
+            }
+        })
+    }
+
+    /// Writes the texture, picking the file format by extension. See `save_image`. Textures
+    /// with several layers have to be saved a layer at a time.
+    pub fn save_to_file(&self, path: &str) -> Result<()> {
+        check_layers(path, 1, self.layers)?;
+        if Path::new(path).extension().and_then(|ext| ext.to_str()) == Some("raw") {
+            // Texels as they are, with floats little endian
+            let mut data = self.data.clone();
+            if self.format != wgpu::TextureFormat::Rgba8Unorm {
+                for sample in data.chunks_exact_mut(4) {
+                    let value = f32::from_ne_bytes([sample[0], sample[1], sample[2], sample[3]]);
+                    sample.copy_from_slice(&value.to_le_bytes());
+                }
+            }
+            std::fs::write(path, data)?;
+            return Ok(());
+        }
+        save_image(&self.to_image()?, path)
+    }
+}
+
+/// Writes an image, picking the file format by extension:
+///
+/// - `.exr` keeps float images as 32 bit floats
+/// - `.png` writes float images as 16 bit
+/// - `.raw` writes the samples, floats as little endian `f32`
+/// - anything else goes through `image` as 8 bit
+///
+/// Float images with values outside 0..1, like the geo LUT's signed degrees, can only be
+/// written as `.exr` or `.raw`.
+pub fn save_image(image: &DynamicImage, path: &str) -> Result<()> {
+    let is_float = matches!(image, DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_));
+    let extension = Path::new(path).extension().and_then(|ext| ext.to_str());
+    if is_float && !matches!(extension, Some("exr") | Some("raw")) {
+        let in_range = image.to_rgba32f().iter().all(|sample| (0.0..=1.0).contains(sample));
+        if !in_range {
+            return Err(Error::UnsupportedFormat(format!(
+                "float values outside 0..1 in {}, write them as .exr or .raw",
+                path
+            )));
+        }
+    }
+    match extension {
+        Some("exr") => match image {
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => image.save(path)?,
+            _ => DynamicImage::ImageRgba32F(image.to_rgba32f()).save(path)?,
+        },
+        Some("png") if is_float => {
+            // Gray images stay gray, like the 16 bit heightmaps the game uses
+            let rgb = image.to_rgb32f();
+            if rgb.pixels().all(|p| p[0] == p[1] && p[1] == p[2]) {
+                let gray = ImageBuffer::from_fn(rgb.width(), rgb.height(), |x, y| {
+                    Luma([(rgb.get_pixel(x, y)[0] * 65535.0).round() as u16])
+                });
+                DynamicImage::ImageLuma16(gray).save(path)?;
+            } else {
+                DynamicImage::ImageRgb16(image.to_rgb16()).save(path)?;
+            }
+        }
+        Some("raw") => {
+            let data: Vec<u8> = if is_float {
+                image.to_rgb32f().iter().flat_map(|sample| sample.to_le_bytes()).collect()
+            } else {
+                image.as_bytes().to_vec()
+            };
+            std::fs::write(path, data)?;
+        }
+        _ if is_float => DynamicImage::ImageRgb8(image.to_rgb8()).save(path)?,
+        _ => image.save(path)?,
+    }
+    Ok(())
+}
+
+// Size in bytes of a texel of a supported format
+fn check_format(format: wgpu::TextureFormat) -> Result<usize> {
+    match format {
+        wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::R32Float => Ok(4),
+        wgpu::TextureFormat::Rg32Float => Ok(8),
+        _ => Err(Error::UnsupportedFormat(format!("CPU texture format {:?}", format))),
+    }
+}
+
+fn texel_size(format: wgpu::TextureFormat) -> usize {
+    check_format(format).unwrap()
+}
+
+fn decode_texel(format: wgpu::TextureFormat, texel: &[u8]) -> [f32; 4] {
+    match format {
+        wgpu::TextureFormat::Rgba8Unorm => [
+            texel[0] as f32 / 255.0,
+            texel[1] as f32 / 255.0,
+            texel[2] as f32 / 255.0,
+            texel[3] as f32 / 255.0,
+        ],
+        wgpu::TextureFormat::R32Float => {
+            let r = f32::from_ne_bytes([texel[0], texel[1], texel[2], texel[3]]);
+            [r, 0.0, 0.0, 1.0]
+        }
+        wgpu::TextureFormat::Rg32Float => {
+            let r = f32::from_ne_bytes([texel[0], texel[1], texel[2], texel[3]]);
+            let g = f32::from_ne_bytes([texel[4], texel[5], texel[6], texel[7]]);
+            [r, g, 0.0, 1.0]
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn encode_texel(format: wgpu::TextureFormat, value: [f32; 4], texel: &mut [u8]) {
+    match format {
+        wgpu::TextureFormat::Rgba8Unorm => {
+            for (c, v) in texel.iter_mut().zip(value.iter()) {
+                *c = (v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+            }
+        }
+        wgpu::TextureFormat::R32Float => texel.copy_from_slice(&value[0].to_ne_bytes()),
+        wgpu::TextureFormat::Rg32Float => {
+            texel[..4].copy_from_slice(&value[0].to_ne_bytes());
+            texel[4..].copy_from_slice(&value[1].to_ne_bytes());
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: u32, height: u32, format: wgpu::TextureFormat) -> CpuTexture {
+        let mut texture = CpuTexture::new(width, height, format).unwrap();
+        texture.fill(|x, y| [x as f32 / 8.0, y as f32 / 8.0, 0.25, 1.0]);
+        texture
+    }
+
+    #[test]
+    fn texels_round_trip_through_every_format() {
+        let rgba = gradient(4, 3, wgpu::TextureFormat::Rgba8Unorm);
+        assert_eq!(rgba.data().len(), 4 * 3 * 4);
+        // 8 bit channels round to the closest step
+        assert_eq!(rgba.load(2, 1), [64.0 / 255.0, 32.0 / 255.0, 64.0 / 255.0, 1.0]);
+
+        let r = gradient(4, 3, wgpu::TextureFormat::R32Float);
+        assert_eq!(r.load(3, 2), [0.375, 0.0, 0.0, 1.0]);
+        let rg = gradient(4, 3, wgpu::TextureFormat::Rg32Float);
+        assert_eq!(rg.data().len(), 4 * 3 * 8);
+        assert_eq!(rg.load(3, 2), [0.375, 0.25, 0.0, 1.0]);
+
+        assert!(CpuTexture::new(2, 2, wgpu::TextureFormat::Bgra8Unorm).is_err());
+        assert!(CpuTexture::from_data(2, 2, wgpu::TextureFormat::R32Float, vec![0; 15]).is_err());
+    }
+
+    #[test]
+    fn layers_stack_and_split() {
+        let a = gradient(2, 2, wgpu::TextureFormat::R32Float);
+        let mut b = CpuTexture::new(2, 2, wgpu::TextureFormat::R32Float).unwrap();
+        b.fill(|_, _| [7.0, 0.0, 0.0, 1.0]);
+
+        let stacked = CpuTexture::from_layers(vec![a.clone(), b.clone()]).unwrap();
+        assert_eq!(stacked.layers(), 2);
+        assert_eq!(stacked.layer(0).data(), a.data());
+        assert_eq!(stacked.layer(1).data(), b.data());
+
+        let other = CpuTexture::new(2, 2, wgpu::TextureFormat::Rgba8Unorm).unwrap();
+        assert!(CpuTexture::from_layers(vec![a.clone(), other]).is_err());
+        assert!(CpuTexture::from_layers(vec![a, gradient(3, 2, wgpu::TextureFormat::R32Float)]).is_err());
+        assert!(CpuTexture::from_layers(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn paste_places_the_source_and_checks_the_room() {
+        let mut target = CpuTexture::new(4, 4, wgpu::TextureFormat::R32Float).unwrap();
+        let source = gradient(2, 2, wgpu::TextureFormat::R32Float);
+        target.paste(&source, 2, 1).unwrap();
+        assert_eq!(target.load(3, 2), source.load(1, 1));
+        assert_eq!(target.load(1, 1), [0.0, 0.0, 0.0, 1.0]);
+
+        assert!(target.paste(&source, 3, 0).is_err());
+        let rgba = gradient(2, 2, wgpu::TextureFormat::Rgba8Unorm);
+        assert!(target.paste(&rgba, 0, 0).is_err());
+    }
+
+    #[test]
+    fn float_images_outside_0_1_only_save_as_exr_or_raw() {
+        let folder = std::env::temp_dir().join(format!("nextgen-texture-{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let path = |name: &str| folder.join(name).to_string_lossy().into_owned();
+
+        let mut degrees = CpuTexture::new(2, 2, wgpu::TextureFormat::Rg32Float).unwrap();
+        degrees.fill(|x, y| [x as f32 * 45.0 - 30.0, y as f32 * 90.0, 0.0, 1.0]);
+        assert!(matches!(degrees.save_to_file(&path("geo.png")), Err(Error::UnsupportedFormat(_))));
+        degrees.save_to_file(&path("geo.raw")).unwrap();
+        assert_eq!(std::fs::read(path("geo.raw")).unwrap().len(), 2 * 2 * 8);
+
+        // Gray floats in range become 16 bit gray PNGs
+        let heights = gradient(4, 4, wgpu::TextureFormat::R32Float);
+        heights.save_to_file(&path("heights.png")).unwrap();
+        let image = image::open(path("heights.png")).unwrap();
+        assert!(matches!(image, DynamicImage::ImageLuma16(_)));
+        assert_eq!(image.to_luma16().get_pixel(2, 0)[0], 16384);
+
+        // Cube maps are saved a face at a time
+        let cube = CpuTexture::from_layers(vec![heights.clone(), heights.clone()]).unwrap();
+        assert!(matches!(cube.save_to_file(&path("cube.png")), Err(Error::LayerMismatch { .. })));
+        assert!(cube.to_image().is_err());
+        cube.layer(1).save_to_file(&path("face.png")).unwrap();
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+use std::fmt;
+
+/// Everything that can go wrong while generating planet textures.
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Image(image::ImageError),
+    Json(serde_json::Error),
+    Xml(serde_xml_rs::Error),
+    XmlSyntax(quick_xml::Error),
+    /// No adapter was found, or the one found can't be used
+    NoAdapter(String),
+    RequestDevice(wgpu::RequestDeviceError),
+    BufferMap(wgpu::BufferAsyncError),
+    /// Shader compilation or pipeline validation failed
+    Shader(String),
+    SizeMismatch {
+        name: String,
+        expected: (u32, u32),
+        found: (u32, u32),
+    },
+    /// Textures going through a stage together hold different numbers of faces
+    LayerMismatch {
+        name: String,
+        expected: u32,
+        found: u32,
+    },
+    UnsupportedFormat(String),
+    InvalidFace(String),
+    /// The planet or material definition has missing or invalid values
+    BadDefinition(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Image(e) => write!(f, "image error: {}", e),
+            Error::Json(e) => write!(f, "JSON error: {}", e),
+            Error::Xml(e) => write!(f, "XML error: {}", e),
+            Error::XmlSyntax(e) => write!(f, "XML syntax error: {}", e),
+            Error::NoAdapter(reason) => write!(f, "no usable GPU adapter: {}", reason),
+            Error::RequestDevice(e) => write!(f, "failed to open GPU device: {}", e),
+            Error::BufferMap(e) => write!(f, "failed to read back GPU buffer: {}", e),
+            Error::Shader(e) => write!(f, "shader error: {}", e),
+            Error::SizeMismatch {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} is {}x{}, expected {}x{}",
+                name, found.0, found.1, expected.0, expected.1
+            ),
+            Error::LayerMismatch {
+                name,
+                expected,
+                found,
+            } => write!(f, "{} has {} layers, expected {}", name, found, expected),
+            Error::UnsupportedFormat(e) => write!(f, "unsupported format: {}", e),
+            Error::InvalidFace(face) => write!(f, "invalid cube face {}", face),
+            Error::BadDefinition(e) => write!(f, "bad definition: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Image(e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::Xml(e) => Some(e),
+            Error::XmlSyntax(e) => Some(e),
+            Error::RequestDevice(e) => Some(e),
+            Error::BufferMap(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(e: image::ImageError) -> Self {
+        Error::Image(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+impl From<serde_xml_rs::Error> for Error {
+    fn from(e: serde_xml_rs::Error) -> Self {
+        Error::Xml(e)
+    }
+}
+
+impl From<quick_xml::Error> for Error {
+    fn from(e: quick_xml::Error) -> Self {
+        Error::XmlSyntax(e)
+    }
+}
+
+impl From<wgpu::RequestDeviceError> for Error {
+    fn from(e: wgpu::RequestDeviceError) -> Self {
+        Error::RequestDevice(e)
+    }
+}
+
+impl From<wgpu::BufferAsyncError> for Error {
+    fn from(e: wgpu::BufferAsyncError) -> Self {
+        Error::BufferMap(e)
+    }
+}
+
+/// Checks that a texture has the size the stage expects.
+pub fn check_size(name: &str, expected: (u32, u32), found: (u32, u32)) -> Result<()> {
+    if expected != found {
+        return Err(Error::SizeMismatch {
+            name: name.to_owned(),
+            expected,
+            found,
+        });
+    }
+    Ok(())
+}
+
+/// Checks that a texture holds as many faces as the other inputs of the stage.
+pub fn check_layers(name: &str, expected: u32, found: u32) -> Result<()> {
+    if expected != found {
+        return Err(Error::LayerMismatch {
+            name: name.to_owned(),
+            expected,
+            found,
+        });
+    }
+    Ok(())
+}
+
+/// Checks that a buffer holds exactly `width * height` texels of `texel_size` bytes.
+pub fn check_data_len(name: &str, width: u32, height: u32, texel_size: usize, len: usize) -> Result<()> {
+    let expected = width as usize * height as usize * texel_size;
+    if len != expected {
+        let rows = height.max(1) as usize;
+        return Err(Error::SizeMismatch {
+            name: name.to_owned(),
+            expected: (width, height),
+            found: ((len / texel_size / rows) as u32, height),
+        });
+    }
+    Ok(())
+}
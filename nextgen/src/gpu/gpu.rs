@@ -1,3 +1,5 @@
+use crate::error::{Error, Result};
+
 pub struct Gpu {
     pub instance: wgpu::Instance,
     pub adapter: wgpu::Adapter,
@@ -5,39 +7,120 @@ pub struct Gpu {
     pub queue: wgpu::Queue,
 }
 
-pub async fn open_default() -> Option<Gpu> {
-        // Instantiates instance of WebGPU
-        let instance = wgpu::Instance::default();
-
-        // `request_adapter` instantiates the general connection to the GPU
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions::default())
-            .await?;
-
-        // `request_device` instantiates the feature specific connection to the GPU, defining some parameters,
-        //  `features` being the available features.
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    features: wgpu::Features::BUFFER_BINDING_ARRAY | wgpu::Features::STORAGE_RESOURCE_BINDING_ARRAY,
-                    limits: wgpu::Limits::downlevel_defaults(),
-                },
-                None,
-            )
-            .await
-            .unwrap();
+// Vendor id Mesa's software drivers (LavaPipe, llvmpipe) report
+const MESA_VENDOR: usize = 0x10005;
+
+/// Features the stages need. Every format they read or store is core WebGPU, so none.
+const REQUIRED_FEATURES: wgpu::Features = wgpu::Features::empty();
+
+/// Features used when the adapter has them: timestamp queries, to time the stages on the device.
+const OPTIONAL_FEATURES: wgpu::Features = wgpu::Features::TIMESTAMP_QUERY;
+
+/// How the adapter a `Gpu` runs on is picked.
+#[derive(Debug, Clone)]
+pub struct AdapterOptions {
+    /// Part of the adapter name, ignoring case. The first adapter matching it is used.
+    pub name: Option<String>,
+    /// Graphics APIs adapters are looked for on
+    pub backends: wgpu::Backends,
+    /// Which adapter to prefer when none is named
+    pub power_preference: wgpu::PowerPreference,
+    /// Software rasterizers are only used when allowed, they are much slower than the CPU backend
+    /// on most machines
+    pub allow_software: bool,
+}
+
+impl Default for AdapterOptions {
+    fn default() -> Self {
+        AdapterOptions {
+            name: None,
+            backends: wgpu::Backends::all(),
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            allow_software: false,
+        }
+    }
+}
+
+/// Whether the adapter rasterizes on the CPU, like LavaPipe, SwiftShader or WARP.
+pub fn is_software(info: &wgpu::AdapterInfo) -> bool {
+    let name = info.name.to_lowercase();
+    info.device_type == wgpu::DeviceType::Cpu
+        || info.vendor == MESA_VENDOR
+        || ["llvmpipe", "swiftshader", "basic render driver"]
+            .iter()
+            .any(|software| name.contains(software))
+}
+
+/// Every adapter found on `backends`, software ones included.
+pub fn list_adapters(backends: wgpu::Backends) -> Vec<wgpu::Adapter> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends,
+        ..Default::default()
+    });
+    instance.enumerate_adapters(backends).collect()
+}
 
-        let info = adapter.get_info();
-        // skip this on LavaPipe temporarily
-        if info.vendor == 0x10005 {
-            return None;
+pub async fn open(options: &AdapterOptions) -> Result<Gpu> {
+    // Instantiates instance of WebGPU
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: options.backends,
+        ..Default::default()
+    });
+
+    // `request_adapter` instantiates the general connection to the GPU
+    let adapter = match &options.name {
+        Some(name) => {
+            let name_lower = name.to_lowercase();
+            instance
+                .enumerate_adapters(options.backends)
+                .find(|adapter| adapter.get_info().name.to_lowercase().contains(&name_lower))
+                .ok_or_else(|| Error::NoAdapter(format!("no adapter named {}", name)))?
         }
+        None => instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: options.power_preference,
+                ..Default::default()
+            })
+            .await
+            .ok_or_else(|| Error::NoAdapter("no adapter found".to_owned()))?,
+    };
 
-        Some(Gpu {
-            instance,
-            adapter,
-            device,
-            queue,
-        })
-}
\ No newline at end of file
+    let info = adapter.get_info();
+    if is_software(&info) && !options.allow_software {
+        return Err(Error::NoAdapter(format!(
+            "{} is a software rasterizer, not used unless allowed",
+            info.name
+        )));
+    }
+
+    // `request_device` instantiates the feature specific connection to the GPU. Textures can
+    // be as large as the adapter allows, the faces are tiled to that size.
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: REQUIRED_FEATURES | (adapter.features() & OPTIONAL_FEATURES),
+                limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
+            },
+            None,
+        )
+        .await?;
+
+    Ok(Gpu {
+        instance,
+        adapter,
+        device,
+        queue,
+    })
+}
+
+/// Runs `f` with validation errors captured, so a bad shader or pipeline is
+/// reported as an error instead of bringing the process down.
+pub async fn validated<T>(device: &wgpu::Device, f: impl FnOnce() -> T) -> Result<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = f();
+    match device.pop_error_scope().await {
+        Some(e) => Err(Error::Shader(e.to_string())),
+        None => Ok(value),
+    }
+}
@@ -3,12 +3,19 @@ use std::num::NonZeroU32;
 use bevy::{prelude::Image, render::texture::TextureFormatPixelInfo};
 use image::{GenericImageView, ImageBuffer, Luma, Rgba, Rgb, DynamicImage};
 
+use crate::{
+    cpu::texture::CpuTexture,
+    error::{check_data_len, Error, Result},
+};
+
 use super::gpu;
 
 pub struct Texture {
     texture_view: wgpu::TextureView,
     format: wgpu::TextureFormat,
     texture: wgpu::Texture,
+    // Layers in use, the texture can have a spare one, see `allocated_layers`
+    layers: u32,
 }
 
 impl Texture {
@@ -18,13 +25,25 @@ impl Texture {
         height: u32,
         format: wgpu::TextureFormat,
         label: Option<&str>,
+    ) -> Self {
+        Texture::new_array(gpu_device, width, height, 1, format, label)
+    }
+
+    /// A texture of `layers` layers of `width` x `height` texels, written by the stages.
+    pub fn new_array(
+        gpu_device: &gpu::Gpu,
+        width: u32,
+        height: u32,
+        layers: u32,
+        format: wgpu::TextureFormat,
+        label: Option<&str>,
     ) -> Self {
         let texture = gpu_device.device.create_texture(&wgpu::TextureDescriptor {
             label,
             size: wgpu::Extent3d {
                 width,
                 height,
-                depth_or_array_layers: 1,
+                depth_or_array_layers: allocated_layers(gpu_device, layers),
             },
             mip_level_count: 1,
             sample_count: 1,
@@ -33,12 +52,23 @@ impl Texture {
             usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[],
         });
-        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Texture::with_view(texture, format, layers)
+    }
+
+    // Every texture is bound as an array, single layer ones included, so the stages
+    // take one face or all six alike
+    fn with_view(texture: wgpu::Texture, format: wgpu::TextureFormat, layers: u32) -> Self {
+        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
 
         Texture {
             texture_view,
             format,
             texture,
+            layers,
         }
     }
 
@@ -47,22 +77,42 @@ impl Texture {
         path: &str,
         format: wgpu::TextureFormat,
         label: Option<&str>,
-    ) -> Self {
-        let img_res = image::open(path);
-        let img = match img_res {
-            Ok(img) => img,
-            Err(e) => panic!("Failed to load texture {}: {}", path, e),
-        };
-        let dimensions = img.dimensions();
-        let width = dimensions.0;
-        let height = dimensions.1;
+    ) -> Result<Self> {
+        let (width, height, texels) = load_texels(path)?;
+
+        Texture::from_data(gpu_device, width, height, format, texels.as_slice(), label)
+    }
+
+    pub fn from_data(
+        gpu_device: &gpu::Gpu,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        data: &[u8],
+        label: Option<&str>,
+    ) -> Result<Self> {
+        Texture::from_layer_data(gpu_device, width, height, 1, format, data, label)
+    }
+
+    /// Same as `from_data`, with `layers` layers one after the other in `data`.
+    pub fn from_layer_data(
+        gpu_device: &gpu::Gpu,
+        width: u32,
+        height: u32,
+        layers: u32,
+        format: wgpu::TextureFormat,
+        data: &[u8],
+        label: Option<&str>,
+    ) -> Result<Self> {
+        let texel_size = format.describe().block_size as usize;
+        check_data_len(label.unwrap_or("Texture data"), width, height * layers, texel_size, data.len())?;
 
         let texture = gpu_device.device.create_texture(&wgpu::TextureDescriptor {
             label,
             size: wgpu::Extent3d {
                 width,
                 height,
-                depth_or_array_layers: 1,
+                depth_or_array_layers: allocated_layers(gpu_device, layers),
             },
             mip_level_count: 1,
             sample_count: 1,
@@ -71,29 +121,24 @@ impl Texture {
             usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[],
         });
-        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-        let texture = Texture {
-            texture_view,
-            format,
-            texture,
-        };
-        match img {
-            image::DynamicImage::ImageRgb8(img) => {
-                let v = convert_rgb8_to_rgba8(&img, width, height);
-                texture.upload_data(&gpu_device.queue, v.as_slice());
-            }
-            image::DynamicImage::ImageRgba8(img) => {
-                texture.upload_data(&gpu_device.queue, img.as_raw());
-            }
-            image::DynamicImage::ImageLuma16(img) => {
-                let v = convert_luma16_to_float(&img, width, height);
-                texture.upload_float_data(&gpu_device.queue, v.as_slice());
-            }
-            _ => panic!("Unsupported texture format for file {}", path),
-        };
+        let texture = Texture::with_view(texture, format, layers);
+        texture.upload_data(&gpu_device.queue, data);
 
-        texture
+        Ok(texture)
+    }
+
+    /// Uploads a texture from memory, with all its layers.
+    pub fn from_cpu(gpu_device: &gpu::Gpu, texture: &CpuTexture, label: Option<&str>) -> Result<Self> {
+        Texture::from_layer_data(
+            gpu_device,
+            texture.width(),
+            texture.height(),
+            texture.layers(),
+            texture.format(),
+            texture.data(),
+            label,
+        )
     }
 
     pub fn texture(&self) -> &wgpu::Texture {
@@ -111,10 +156,10 @@ impl Texture {
             data,
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: NonZeroU32::new(4 * self.texture.size().width),
+                bytes_per_row: NonZeroU32::new(self.texel_size() * self.texture.size().width),
                 rows_per_image: NonZeroU32::new(self.texture.size().height),
             },
-            self.texture.size(),
+            self.size(),
         );
     }
 
@@ -132,7 +177,7 @@ impl Texture {
                 bytes_per_row: NonZeroU32::new(4 * self.texture.size().width),
                 rows_per_image: NonZeroU32::new(self.texture.size().height),
             },
-            self.texture.size(),
+            self.size(),
         );
     }
 
@@ -144,7 +189,7 @@ impl Texture {
         wgpu::BindingType::StorageTexture {
             access,
             format: self.format,
-            view_dimension: wgpu::TextureViewDimension::D2,
+            view_dimension: wgpu::TextureViewDimension::D2Array,
         }
     }
 
@@ -154,12 +199,30 @@ impl Texture {
     pub fn height(&self) -> u32 {
         self.texture.size().height
     }
+    pub fn layers(&self) -> u32 {
+        self.layers
+    }
+
+    // Size of the layers in use
+    fn size(&self) -> wgpu::Extent3d {
+        wgpu::Extent3d {
+            width: self.width(),
+            height: self.height(),
+            depth_or_array_layers: self.layers,
+        }
+    }
 
-    pub async fn save_to_file(&self, gpu_device: &gpu::Gpu, path: &str) {
+    /// Records a copy of the texture into a buffer the CPU can read, to be finished with
+    /// `Readback::finish` once `encoder` is submitted.
+    pub fn record_readback(&self, gpu_device: &gpu::Gpu, encoder: &mut wgpu::CommandEncoder) -> Readback {
         let width = self.width();
         let height = self.height();
-        let u32_size = std::mem::size_of::<u32>() as u32;
-        let output_buffer_size = (u32_size * width * height) as wgpu::BufferAddress;
+        let layers = self.layers();
+        let row_size = self.texel_size() * width;
+        // Buffer copies need rows aligned to 256 bytes
+        let padded_row_size = row_size.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let output_buffer_size = (padded_row_size * height * layers) as wgpu::BufferAddress;
         let output_buffer_desc = wgpu::BufferDescriptor {
             size: output_buffer_size,
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
@@ -169,11 +232,8 @@ impl Texture {
 
         let output_buffer = gpu_device.device.create_buffer(&output_buffer_desc);
         let tex = &self.texture();
-        let texsize = tex.size();
+        let texsize = self.size();
 
-        let mut encoder = gpu_device.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Texture Exporter"),
-        });
         encoder.copy_texture_to_buffer(
             wgpu::ImageCopyTexture {
                 aspect: wgpu::TextureAspect::All,
@@ -185,17 +245,79 @@ impl Texture {
                 buffer: &output_buffer,
                 layout: wgpu::ImageDataLayout {
                     offset: 0,
-                    bytes_per_row: NonZeroU32::new(u32_size * width),
+                    bytes_per_row: NonZeroU32::new(padded_row_size),
                     rows_per_image: NonZeroU32::new(height),
                 },
             },
             texsize,
         );
-        gpu_device.queue.submit(Some(encoder.finish()));
+
+        Readback {
+            buffer: output_buffer,
+            width,
+            height,
+            layers,
+            format: self.format,
+            row_size,
+            padded_row_size,
+        }
+    }
+
+    /// Copies the texture back from the GPU, returning the raw texels row after row and
+    /// layer after layer, without the row padding the copy needs.
+    pub async fn read_data(&self, gpu_device: &gpu::Gpu) -> Result<Vec<u8>> {
+        let mut encoder = gpu_device.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Texture Exporter"),
+        });
+        let readback = self.record_readback(gpu_device, &mut encoder);
+        let submission = gpu_device.queue.submit(Some(encoder.finish()));
+        readback.read_data(gpu_device, submission).await
+    }
+
+    /// Copies the texture back from the GPU into memory.
+    pub async fn read_to_cpu(&self, gpu_device: &gpu::Gpu) -> Result<CpuTexture> {
+        let data = self.read_data(gpu_device).await?;
+        CpuTexture::from_layer_data(self.width(), self.height(), self.layers(), self.format, data)
+    }
+
+    /// Writes the texture, picking the file format by extension. See `CpuTexture::save_to_file`.
+    pub async fn save_to_file(&self, gpu_device: &gpu::Gpu, path: &str) -> Result<()> {
+        self.read_to_cpu(gpu_device).await?.save_to_file(path)
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    /// Size in bytes of one texel.
+    pub fn texel_size(&self) -> u32 {
+        self.format.describe().block_size as u32
+    }
+}
+
+/// A texture copy on its way back from the GPU, see `Texture::record_readback`.
+pub struct Readback {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    layers: u32,
+    format: wgpu::TextureFormat,
+    row_size: u32,
+    padded_row_size: u32,
+}
+
+impl Readback {
+    /// Waits for `submission`, the one holding the copy, and returns the raw texels like
+    /// `Texture::read_data`. Work submitted after it keeps running.
+    pub async fn read_data(
+        self,
+        gpu_device: &gpu::Gpu,
+        submission: wgpu::SubmissionIndex,
+    ) -> Result<Vec<u8>> {
         // We need to scope the mapping variables so that we can
         // unmap the buffer
-        {
-            let buffer_slice = output_buffer.slice(..);
+        let data = {
+            let buffer_slice = self.buffer.slice(..);
 
             // NOTE: We have to create the mapping THEN device.poll() before await
             // the future. Otherwise the application will freeze.
@@ -203,35 +325,80 @@ impl Texture {
             buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
                 tx.send(result).unwrap();
             });
-            gpu_device.device.poll(wgpu::Maintain::Wait);
-            rx.receive().await.unwrap().unwrap();
+            gpu_device.device.poll(wgpu::Maintain::WaitForSubmissionIndex(submission));
+            rx.receive().await.unwrap()?;
 
-            let data = buffer_slice.get_mapped_range();
+            let mapped = buffer_slice.get_mapped_range();
+            let mut data = Vec::with_capacity((self.row_size * self.height * self.layers) as usize);
+            for row in mapped.chunks_exact(self.padded_row_size as usize) {
+                data.extend_from_slice(&row[..self.row_size as usize]);
+            }
+            data
+        };
+        self.buffer.unmap();
 
-            let buffer = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, data).unwrap();
-            buffer.save(path).unwrap();
-        }
-        output_buffer.unmap();
+        Ok(data)
+    }
+
+    /// Same as `read_data`, as a texture in memory.
+    pub async fn finish(
+        self,
+        gpu_device: &gpu::Gpu,
+        submission: wgpu::SubmissionIndex,
+    ) -> Result<CpuTexture> {
+        let (width, height, layers, format) = (self.width, self.height, self.layers, self.format);
+        let data = self.read_data(gpu_device, submission).await?;
+        CpuTexture::from_layer_data(width, height, layers, format, data)
+    }
+}
+
+// OpenGL makes a texture of one layer a 2D texture and one of six a cube map, neither of
+// which binds as an array. Those get a spare layer there, never read or written.
+fn allocated_layers(gpu_device: &gpu::Gpu, layers: u32) -> u32 {
+    let gl = gpu_device.adapter.get_info().backend == wgpu::Backend::Gl;
+    if gl && (layers == 1 || layers == 6) {
+        layers + 1
+    } else {
+        layers
     }
 }
 
+/// Loads an image file as 4 byte texels, the layout used by every texture in the pipeline.
+/// 8 bit images become RGBA8 and 16 bit luma images (heightmaps) become f32.
+pub fn load_texels(path: &str) -> Result<(u32, u32, Vec<u8>)> {
+    let img = image::open(path)?;
+    let dimensions = img.dimensions();
+    let width = dimensions.0;
+    let height = dimensions.1;
+
+    let color = img.color();
+    let texels = match img {
+        image::DynamicImage::ImageRgb8(img) => convert_rgb8_to_rgba8(&img, width, height),
+        image::DynamicImage::ImageRgba8(img) => img.into_raw(),
+        image::DynamicImage::ImageLuma16(img) => {
+            let v = convert_luma16_to_float(&img, width, height);
+            bytemuck::cast_slice(v.as_slice()).to_vec()
+        }
+        _ => {
+            return Err(Error::UnsupportedFormat(format!(
+                "{:?} in file {}",
+                color,
+                path
+            )))
+        }
+    };
+
+    Ok((width, height, texels))
+}
+
 fn convert_luma16_to_float(luma: &ImageBuffer<Luma<u16>, Vec<u16>>, width: u32, height: u32) -> Vec<f32> {
     let mut rgba = Vec::with_capacity((width * height * 4) as usize);
-    let mut min_val = 1000000.0;
-    let mut max_val = -10000000.0;
     for pixel in luma.pixels() {
         let luma_value = (pixel[0] as f32) / 65535.0;
-        if luma_value < min_val {
-            min_val = luma_value;
-        }
-        if luma_value > max_val {
-            max_val = luma_value;
-        }
         rgba.push(luma_value);
         // let rgba_pixel = Rgba([luma_value, luma_value, luma_value, 1.0]);
         // rgba.extend_from_slice(&rgba_pixel.0);
     }
-    println!("min: {}, max: {}", min_val, max_val);
     rgba
 }
 
@@ -246,11 +413,11 @@ fn convert_rgb8_to_rgba8(rgb: &ImageBuffer<Rgb<u8>, Vec<u8>>, width: u32, height
 }
 
 
-pub fn image_from_bevy(bimg: &Image) -> DynamicImage { //
+pub fn image_from_bevy(bimg: &Image) -> Result<DynamicImage> {
     let width = bimg.texture_descriptor.size.width;
     let height = bimg.texture_descriptor.size.height;
     let pixel_size = bimg.texture_descriptor.format.pixel_size();
-    match pixel_size {
+    let img = match pixel_size {
         4 => {
             let img : ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::from_raw(width, height,bimg.data.clone()).unwrap();
             DynamicImage::ImageRgba8(img)
@@ -268,6 +435,7 @@ pub fn image_from_bevy(bimg: &Image) -> DynamicImage { //
             let img : ImageBuffer<Luma<u8>, Vec<u8>> = ImageBuffer::from_raw(width, height,bimg.data.clone()).unwrap();
             DynamicImage::ImageLuma8(img)
         }
-        _ => panic!("Invalid pixel size")
-    }
+        _ => return Err(Error::UnsupportedFormat(format!("{} bytes per pixel", pixel_size))),
+    };
+    Ok(img)
 }
\ No newline at end of file
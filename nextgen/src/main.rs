@@ -1,33 +1,65 @@
-use std::{fs::File, io::Read, ptr::null, time::SystemTime};
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    ptr::null,
+    time::SystemTime,
+};
 
 use bevy::{
-    pbr::wireframe::{Wireframe, WireframePlugin},
+    pbr::wireframe::WireframePlugin,
     prelude::*,
     render::{settings::WgpuSettings, RenderPlugin},
 };
 use bevy_flycam::prelude::*;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
+use clap::Parser;
+use cpu::texture::{CpuTexture, Resample};
+use cli::{
+    glob_match, AdapterArgs, Cli, Command, GenerateArgs, ImportArgs, InspectArgs, LutArgs, MetaArgs,
+    ParityArgs, CubemapArgs, FaceImages, ProjectArgs, TilesArgs,
+};
+use error::Result;
 use geom::cube::CubeSphere;
-use gpu::texture::Texture;
 use render::{
     multimaterialgroup::MultiMaterialGroup,
     planetplugin::{planet_update_system, PlanetData, PlanetSpec, PlanetBundle, default_mesh, PlanetPlugin},
 };
 use renderdoc::{RenderDoc, V110};
-use spacelab::{lutgen_gpu::gpu_generate_slope_inner, matcolormap::PlanetMaterials, material_gpu::generate_material_gpu};
-use wgpu::{Features, PrimitiveTopology};
+use spaceengineers::definitions::Definitions;
+use spacelab::{
+    backend::{compare_backends, BackendKind, CpuBackend, GpuBackend, PlanetBackend},
+    buildcache::{BuildCache, ContentHash},
+    coloravg::MatColorAverage,
+    cubeexport::export_cubemap,
+    matcolormap::{MaterialLayer, OreMap, PlanetMaterial, PlanetMaterials, VoxelMaterial},
+    matcolormapgen::planet_material_from_definition,
+    matfile::MatFile,
+    orepalette::OrePalette,
+    planetmeta::{generate_planet_meta, planet_meta_file_name},
+    profile::{PlanetProfile, ProfileReport, Profiler},
+    projection::reproject,
+    tiles::{tile_faces, tile_map},
+};
+use wgpu::Features;
 
 use crate::spacelab::{
-    lutgen::CUBEMAP, lutgen_gpu::gpu_generate_latlut_inner, normal::gpu_generate_normal_inner,
+    lutgen::CUBEMAP,
+    lutgen_gpu::face_to_num,
+    tiling::{crop, split_face, tile_input, FaceTile},
 };
 
+pub mod cli;
+pub mod cpu;
+pub mod error;
 pub mod geom;
 pub mod gpu;
 pub mod render;
 pub mod spaceengineers;
 pub mod spacelab;
 
-fn load_json_file<T>(path: &str) -> Result<T, Box<dyn std::error::Error>>
+fn load_json_file<T>(path: &str) -> Result<T>
 where
     T: serde::de::DeserializeOwned,
 {
@@ -162,137 +194,851 @@ fn setup(
     });
 }
 
-    //App::new()
-    //    .add_plugins(DefaultPlugins.set(RenderPlugin {
-    //        wgpu_settings: WgpuSettings {
-    //            features: Features::POLYGON_MODE_LINE,
-    //            ..default()
-    //        },
-    //    }))
-    //    .add_plugin(WorldInspectorPlugin::new())
-    //    .add_plugin(WireframePlugin)
-    //    .add_plugin(PlayerPlugin)
-    //    .insert_resource(MovementSettings {
-    //        sensitivity: 0.00010, // default: 0.00012
-    //        speed: 6.0, // default: 12.0
-    //    })
-    //    .add_plugin(PlanetPlugin)
-    //    .add_startup_system(setup)
-    //    .run();
+fn view_main() {
+    App::new()
+        .add_plugins(DefaultPlugins.set(RenderPlugin {
+            wgpu_settings: WgpuSettings {
+                features: Features::POLYGON_MODE_LINE,
+                ..default()
+            },
+        }))
+        .add_plugin(WorldInspectorPlugin::new())
+        .add_plugin(WireframePlugin)
+        .add_plugin(PlayerPlugin)
+        .insert_resource(MovementSettings {
+            sensitivity: 0.00010, // default: 0.00012
+            speed: 6.0, // default: 12.0
+        })
+        .add_plugin(PlanetPlugin)
+        .add_startup_system(setup)
+        .run();
+}
 
 fn main() {
-    gen_main();
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Generate(args) => gen_main(&args),
+        Command::ListPlanets(args) => list_planets_main(&args),
+        Command::Inspect(args) => inspect_main(&args),
+        Command::Import(args) => import_main(&args),
+        Command::Meta(args) => meta_main(&args),
+        Command::Project(args) => project_main(&args),
+        Command::Cubemap(args) => cubemap_main(&args),
+        Command::Tiles(args) => tiles_main(&args),
+        Command::Adapters(args) => adapters_main(&args),
+        Command::View => view_main(),
+        Command::Parity(args) => parity_main(&args),
+    }
+}
+
+fn load_planet_definitions(luts: &LutArgs) -> PlanetMaterials {
+    let path = luts.matcolormap_path();
+    match load_json_file(&path.to_string_lossy()) {
+        Ok(definitions) => definitions,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn load_ore_palette(luts: &LutArgs) -> OrePalette {
+    match luts.ore_palette() {
+        Ok(palette) => palette,
+        Err(e) => {
+            eprintln!("Failed to load the ore palette: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn open_gpu_backend(args: &AdapterArgs) -> Result<GpuBackend> {
+    let backend = futures::executor::block_on(gpu::gpu::open(&args.options())).and_then(GpuBackend::new)?;
+    let info = backend.gpu().adapter.get_info();
+    println!("Using GPU backend: {} ({:?})", info.name, info.backend);
+    Ok(backend)
+}
+
+// A file written for a face, with the key of the inputs it is generated from
+struct FaceOutput {
+    path: PathBuf,
+    key: String,
+}
+
+struct FaceJob<'a> {
+    face: &'a str,
+    normal: FaceOutput,
+    material: FaceOutput,
+    ores: Option<FaceOutput>,
 }
 
-fn gen_planet(planet_definitions: &PlanetMaterials, planet_name: String) {
+impl FaceJob<'_> {
+    fn outputs(&self) -> impl Iterator<Item = &FaceOutput> {
+        [&self.normal, &self.material].into_iter().chain(self.ores.as_ref())
+    }
+}
+
+// Hashes the input files, the planet definition and the arguments each output of the
+// selected faces depends on, for the build cache
+fn face_jobs<'a, B: PlanetBackend>(
+    backend: &B,
+    planet: &PlanetMaterial,
+    ore_palette: &OrePalette,
+    args: &'a GenerateArgs,
+    texture_folder: &Path,
+    output_folder: &Path,
+) -> Result<Vec<FaceJob<'a>>> {
+    let file_hash = |name: String| -> Result<String> {
+        Ok(ContentHash::new().update_file(&texture_folder.join(name))?.hex())
+    };
+    // Sorted keys, the definitions hold hash maps
+    let planet_json = serde_json::to_value(planet)?.to_string();
+    let palette_json = serde_json::to_value(&ore_palette.0)?.to_string();
+    let mut common = ContentHash::new();
+    common
+        .update_str(backend.name())
+        .update_str(&format!("{:?} {:?}", args.resolution, args.resample));
+    // Seamless normals read the adjacent faces too
+    let cube_heightmaps = if args.no_seamless {
+        None
+    } else {
+        let hashes: Result<Vec<String>> =
+            CUBEMAP.iter().map(|face| file_hash(format!("{}.png", face))).collect();
+        Some(hashes?.concat())
+    };
+
+    let mut jobs = Vec::new();
+    for face in args.faces() {
+        let heightmaps = match &cube_heightmaps {
+            Some(hashes) => hashes.clone(),
+            None => file_hash(format!("{}.png", face))?,
+        };
+        let materialmap = file_hash(format!("{}_mat.png", face))?;
+
+        let normal = common
+            .clone()
+            .update_str(&heightmaps)
+            .update_str(&format!("{} {:?}", args.no_seamless, args.terrain_scale(planet)))
+            .hex();
+        let material = ContentHash::new()
+            .update_str(&normal)
+            .update_str(&materialmap)
+            .update_str(&planet_json)
+            .update_str(&format!("{:?}", args.material_view(planet)))
+            .hex();
+        let ores = common
+            .clone()
+            .update_str(&materialmap)
+            .update_str(&planet_json)
+            .update_str(&palette_json)
+            .update_str(&format!("{:?}", args.ore_overlay_style()))
+            .hex();
+
+        jobs.push(FaceJob {
+            face,
+            normal: FaceOutput {
+                path: output_folder.join(format!("{}_normal.{}", face, args.format)),
+                key: normal,
+            },
+            material: FaceOutput {
+                path: output_folder.join(format!("{}.{}", face, args.format)),
+                key: material,
+            },
+            ores: args.ores.then(|| FaceOutput {
+                path: output_folder.join(format!("{}_ores.png", face)),
+                key: ores,
+            }),
+        });
+    }
+    Ok(jobs)
+}
+
+// An output of the faces going through the backend together, one per layer, with the reads
+// of its tiles still coming back
+struct PendingOutput<'a, R> {
+    // The faces in the layers, comma separated, as they are profiled
+    faces: String,
+    // The file of every layer and its texels, None where it is up to date
+    layers: Vec<Option<(&'a FaceOutput, CpuTexture)>>,
+    tiles: Vec<(R, u32, u32)>,
+}
+
+impl<'a, R> PendingOutput<'a, R> {
+    // None when every layer is up to date
+    fn new(
+        faces: &str,
+        outputs: Vec<Option<&'a FaceOutput>>,
+        width: u32,
+        height: u32,
+    ) -> Result<Option<Self>> {
+        if outputs.iter().all(Option::is_none) {
+            return Ok(None);
+        }
+        let layers: Result<Vec<_>> = outputs
+            .into_iter()
+            .map(|output| {
+                let texture = |output| {
+                    Ok((output, CpuTexture::new(width, height, wgpu::TextureFormat::Rgba8Unorm)?))
+                };
+                output.map(texture).transpose()
+            })
+            .collect();
+        Ok(Some(PendingOutput {
+            faces: faces.to_owned(),
+            layers: layers?,
+            tiles: Vec::new(),
+        }))
+    }
+
+    fn start_read<B>(
+        &mut self,
+        backend: &B,
+        profiler: &Profiler,
+        tile_output: &B::Texture,
+        tile: &FaceTile,
+    ) -> Result<()>
+    where
+        B: PlanetBackend<Readback = R>,
+    {
+        let readback = profiler.time(&self.faces, "readback", || backend.start_read(tile_output))?;
+        self.tiles.push((readback, tile.x, tile.y));
+        Ok(())
+    }
+}
+
+// Stitches the tiles of every output of the faces and saves them
+fn finish_faces<B: PlanetBackend>(
+    backend: &B,
+    outputs: Vec<PendingOutput<'_, B::Readback>>,
+    cache: &mut BuildCache,
+    profiler: &Profiler,
+) -> Result<()> {
+    for mut pending in outputs {
+        let faces = pending.faces.as_str();
+        for (readback, x, y) in pending.tiles {
+            let texture = profiler.time(faces, "readback", || backend.finish_read(readback))?;
+            profiler.add_read_back(faces, "readback", texture.data().len());
+            for (layer, output) in pending.layers.iter_mut().enumerate() {
+                if let Some((_, output)) = output {
+                    output.paste(&texture.layer(layer as u32), x, y)?;
+                }
+            }
+        }
+        for (output, texture) in pending.layers.into_iter().flatten() {
+            profiler.time(faces, "save", || texture.save_to_file(&output.path.to_string_lossy()))?;
+            cache.record(&output.path, &output.key);
+        }
+    }
+    // After every face, so an interrupted run keeps what it finished
+    cache.save()
+}
+
+fn gen_planet<B: PlanetBackend>(
+    backend: &B,
+    planet_definitions: &PlanetMaterials,
+    ore_palette: &OrePalette,
+    planet_name: &str,
+    args: &GenerateArgs,
+) -> Result<PlanetProfile> {
     let mut rd: Option<RenderDoc<V110>> = RenderDoc::new().ok();
 
-    let gpu_device = futures::executor::block_on(gpu::gpu::open_default()).unwrap();
-    if rd.is_some() {
-        rd.as_mut().unwrap().start_frame_capture(null(), null());
+    if let Some(rd) = rd.as_mut() {
+        rd.start_frame_capture(null(), null());
     }
     let start = SystemTime::now();
+    let profiler = Profiler::new();
 
-    let texture_folder_name = planet_definitions.0[&planet_name].base_path.clone();
+    let planet = &planet_definitions.0[planet_name];
+    let texture_folder = args.assets.join(&planet.base_path);
+    let output_folder = args.output.join(planet_name);
 
     // Create dir if not exists
-    std::fs::create_dir_all(planet_name.clone()).unwrap();
-
-    for face in CUBEMAP.iter() {
-        let latlut =
-            futures::executor::block_on(gpu_generate_latlut_inner(&gpu_device, face, 2048, 2048))
-                .unwrap();
-        let heightmap = Texture::from_file(
-            &gpu_device,
-            format!("../{}/{}.png", texture_folder_name, face).as_str(),
-            wgpu::TextureFormat::R32Float,
-            Some("HeightMap"),
-        );
-        let slope = futures::executor::block_on(gpu_generate_slope_inner(&gpu_device, &heightmap))
-            .unwrap();
-
-        let materialmap = Texture::from_file(
-            &gpu_device,
-            format!(
-                "../{}/{}_mat.png",
-                texture_folder_name,
-                face
+    std::fs::create_dir_all(&output_folder)?;
+
+    let scale = args.terrain_scale(planet);
+    let view = args.material_view(planet);
+    let style = args.ore_overlay_style();
+    let mut cache = BuildCache::open(&output_folder, args.force);
+    let jobs = face_jobs(backend, planet, ore_palette, args, &texture_folder, &output_folder)?;
+    let skipped: Vec<&str> = jobs
+        .iter()
+        .filter(|job| job.outputs().all(|output| cache.is_fresh(&output.path, &output.key)))
+        .map(|job| job.face)
+        .collect();
+    if !skipped.is_empty() {
+        println!("Up to date: {}", skipped.join(", "));
+    }
+
+    let load_heightmap = |face: &str| {
+        let path = texture_folder.join(format!("{}.png", face));
+        args.load_input(&path, wgpu::TextureFormat::R32Float, args.resample)
+    };
+    let heightmaps_needed = jobs
+        .iter()
+        .flat_map(|job| [&job.normal, &job.material])
+        .any(|output| !cache.is_fresh(&output.path, &output.key));
+    // Normals and slopes read past the face edges, into the adjacent faces. Only loaded when
+    // a face has normals or albedo to regenerate.
+    let cube_heightmaps = if args.no_seamless || !heightmaps_needed {
+        None
+    } else {
+        let faces: Result<Vec<CpuTexture>> = profiler.time(&CUBEMAP.join(","), "load", || {
+            CUBEMAP.iter().map(|face| load_heightmap(face)).collect()
+        });
+        Some(faces?)
+    };
+
+    let max_size = args.tile_size.unwrap_or_else(|| backend.max_texture_size());
+
+    // The six faces go through the stages together, as the layers of every texture, when they
+    // fit without tiles. Otherwise a face at a time.
+    let stale: Vec<&FaceJob<'_>> = jobs.iter().filter(|job| !skipped.contains(&job.face)).collect();
+    let face_size = |face: &str| -> Result<(u32, u32)> {
+        match args.resolution {
+            Some(size) => Ok((size, size)),
+            None => Ok(image::image_dimensions(texture_folder.join(format!("{}_mat.png", face)))?),
+        }
+    };
+    let whole_cube = stale.len() == CUBEMAP.len() && {
+        let sizes: Vec<(u32, u32)> = stale.iter().map(|job| face_size(job.face)).collect::<Result<_>>()?;
+        let (width, height) = sizes[0];
+        sizes.iter().all(|size| *size == sizes[0])
+            && split_face(width, height, max_size, !args.no_seamless, style.reach())?.len() == 1
+    };
+    let groups: Vec<&[&FaceJob<'_>]> = if whole_cube {
+        vec![&stale[..]]
+    } else {
+        stale.chunks(1).collect()
+    };
+
+    // Every group is submitted as a whole, then read back while the next one runs
+    let mut pending: Option<Vec<PendingOutput<'_, B::Readback>>> = None;
+    for group in groups {
+        let faces: Vec<&str> = group.iter().map(|job| job.face).collect();
+        let group_faces = faces.join(",");
+        if args.profile.is_some() {
+            backend.time_stages(&group_faces);
+        }
+        let face_nums: Vec<u32> = faces.iter().map(|face| face_to_num(face)).collect::<Result<_>>()?;
+        let stale_output = |output: &FaceOutput| !cache.is_fresh(&output.path, &output.key);
+        let normals: Vec<Option<&FaceOutput>> =
+            group.iter().map(|job| Some(&job.normal).filter(|output| stale_output(output))).collect();
+        let materials: Vec<Option<&FaceOutput>> =
+            group.iter().map(|job| Some(&job.material).filter(|output| stale_output(output))).collect();
+        let ores: Vec<Option<&FaceOutput>> =
+            group.iter().map(|job| job.ores.as_ref().filter(|output| stale_output(output))).collect();
+        let heightmaps_needed = normals.iter().chain(&materials).any(Option::is_some);
+
+        let load_materialmap = |face: &&str| {
+            args.load_input(
+                &texture_folder.join(format!("{}_mat.png", face)),
+                wgpu::TextureFormat::Rgba8Unorm,
+                Resample::Nearest,
             )
-            .as_str(),
-            wgpu::TextureFormat::Rgba8Unorm,
-            Some("MaterialMap"),
-        );
+        };
+        let materialmaps: Vec<CpuTexture> = profiler.time(&group_faces, "load", || {
+            faces.iter().map(load_materialmap).collect::<Result<_>>()
+        })?;
+        // Without the adjacent faces, `heightmaps` only holds the faces of the group
+        let heightmaps = match &cube_heightmaps {
+            Some(faces) => Cow::Borrowed(faces.as_slice()),
+            None if heightmaps_needed => Cow::Owned(profiler.time(&group_faces, "load", || {
+                faces.iter().map(|face| load_heightmap(face)).collect::<Result<_>>()
+            })?),
+            None => Cow::Owned(Vec::new()),
+        };
+        let heightmap_index = |layer: usize| if args.no_seamless { layer } else { face_nums[layer] as usize };
+        // Uploads a texture made for every face of the group, as its layers
+        let upload_layers = |layer_texture: &dyn Fn(usize) -> Result<CpuTexture>, label| {
+            let layers: Result<Vec<CpuTexture>> = (0..group.len()).map(layer_texture).collect();
+            let texture = CpuTexture::from_layers(layers?)?;
+            profiler.add_uploaded(&group_faces, "upload", texture.data().len());
+            profiler.time(&group_faces, "upload", || backend.upload_texture(&texture, Some(label)))
+        };
+        // Runs a stage, timed as run on the faces of the group
+        let stage = |stage, f: &dyn Fn() -> Result<B::Texture>| profiler.time(&group_faces, stage, f);
 
-        let normal =
-            futures::executor::block_on(gpu_generate_normal_inner(&gpu_device, &heightmap))
-                .unwrap();
+        // Faces larger than the backend's textures go through it a tile at a time, stitched
+        // back together on the CPU
+        let (width, height) = (materialmaps[0].width(), materialmaps[0].height());
+        let tiles = split_face(width, height, max_size, !args.no_seamless, style.reach())?;
+        if tiles.len() > 1 {
+            println!("Processing {} in {} tiles", faces[0], tiles.len());
+        }
+        let mut normal_output = PendingOutput::new(&group_faces, normals, width, height)?;
+        let mut material_output = PendingOutput::new(&group_faces, materials, width, height)?;
+        let mut ores_output = PendingOutput::new(&group_faces, ores, width, height)?;
 
-        futures::executor::block_on(
-            normal.save_to_file(&gpu_device, format!("{}/{}_normal.jpg", planet_name, face).as_str()),
-        );
+        for tile in &tiles {
+            if heightmaps_needed {
+                let heightmap_tile = upload_layers(
+                    &|layer| {
+                        let halo_faces = if args.no_seamless {
+                            std::slice::from_ref(&heightmaps[layer])
+                        } else {
+                            &heightmaps[..]
+                        };
+                        tile_input(halo_faces, face_nums[layer], tile)
+                    },
+                    "HeightMap",
+                )?;
+                let slope =
+                    stage("slope", &|| backend.generate_slope_tile(&heightmap_tile, tile, scale.as_ref()))?;
+                let normal =
+                    stage("normal", &|| backend.generate_normal_tile(&heightmap_tile, tile, scale.as_ref()))?;
 
-        let material = futures::executor::block_on(generate_material_gpu(
-            &gpu_device,
-            &materialmap,
-            &heightmap,
-            &latlut,
-            &normal,
-            &slope,
-            &planet_definitions.0[&planet_name],
-        ));
-
-        futures::executor::block_on(
-            material
-                .unwrap()
-                .save_to_file(&gpu_device, format!("{}/{}.jpg", planet_name, face).as_str()),
-        );
+                if let Some(output) = material_output.as_mut() {
+                    let inner = tile.with_halo(0);
+                    let latlut = stage("latlut", &|| {
+                        if group.len() == CUBEMAP.len() {
+                            backend.generate_cube_latlut_tile(&inner)
+                        } else {
+                            backend.generate_latlut_tile(faces[0], &inner)
+                        }
+                    })?;
+                    let materialmap_tile =
+                        upload_layers(&|layer| crop(&materialmaps[layer], &inner, 0), "MaterialMap")?;
+                    let heightmap_crop = upload_layers(
+                        &|layer| crop(&heightmaps[heightmap_index(layer)], &inner, 0),
+                        "HeightMap",
+                    )?;
+                    let material = stage("material", &|| {
+                        backend.generate_material(
+                            &materialmap_tile,
+                            &heightmap_crop,
+                            &latlut,
+                            &normal,
+                            &slope,
+                            planet,
+                            &view,
+                        )
+                    })?;
+                    output.start_read(backend, &profiler, &material, tile)?;
+                }
+                if let Some(output) = normal_output.as_mut() {
+                    output.start_read(backend, &profiler, &normal, tile)?;
+                }
+            }
+
+            if let Some(output) = ores_output.as_mut() {
+                // The overlay doesn't cross the face edges, a whole face needs no halo
+                let ore_tile = tile.with_halo(if tile.is_whole() { 0 } else { style.reach() });
+                let materialmap_tile = upload_layers(
+                    &|layer| crop(&materialmaps[layer], &ore_tile, ore_tile.halo),
+                    "MaterialMap",
+                )?;
+                let overlay = stage("ore overlay", &|| {
+                    backend.generate_ore_overlay_tile(
+                        &materialmap_tile,
+                        &ore_tile,
+                        planet,
+                        ore_palette,
+                        &style,
+                    )
+                })?;
+                output.start_read(backend, &profiler, &overlay, tile)?;
+            }
+        }
+        profiler.time(&group_faces, "flush", || backend.flush());
+
+        if let Some(outputs) = pending.take() {
+            finish_faces(backend, outputs, &mut cache, &profiler)?;
+        }
+        pending = Some([normal_output, material_output, ores_output].into_iter().flatten().collect());
+    }
+    if let Some(outputs) = pending {
+        finish_faces(backend, outputs, &mut cache, &profiler)?;
+    }
+    if args.profile.is_some() {
+        profiler.add_device_times(backend.take_device_times()?);
     }
     let delta = SystemTime::now().duration_since(start).unwrap();
 
     println!(
-        "GPU Compute Time elapsed: {}.{:03} seconds",
+        "{} Compute Time elapsed: {}.{:03} seconds",
+        backend.name().to_uppercase(),
         delta.as_secs(),
         delta.subsec_millis()
     );
-    if rd.is_some() {
-        rd.unwrap().end_frame_capture(null(), null());
+    if let Some(mut rd) = rd {
+        rd.end_frame_capture(null(), null());
     }
+    Ok(profiler.finish(planet_name, delta))
 }
 
-// Old main from generator
-fn gen_main() {
-    // println!("Hello, world!");
-    // let file = File::open("../assets/Ares/Planet Agaris.sbc").expect("Failed to open XML file");
-    // let reader = BufReader::new(file);
-    // // Deserialize XML data into Definitions struct
-    // let definitions: Definitions = from_reader(reader).expect("Failed to deserialize XML data");
+// Generates every selected planet, returning how many failed
+fn gen_planets<B: PlanetBackend>(
+    backend: &B,
+    planet_definitions: &PlanetMaterials,
+    ore_palette: &OrePalette,
+    args: &GenerateArgs,
+    profiles: &mut Vec<PlanetProfile>,
+) -> usize {
+    let mut planets: Vec<&String> = planet_definitions
+        .0
+        .keys()
+        .filter(|name| args.filter.matches(name))
+        .collect();
+    planets.sort();
+
+    if planets.is_empty() {
+        println!("No planet matches the given filters");
+    }
 
-    // Process the definitions object as needed
-    // println!("{:#?}", definitions);
+    let mut failed = 0;
+    for planet in planets {
+        println!("Planet: {}", planet);
+        match gen_planet(backend, planet_definitions, ore_palette, planet, args) {
+            Ok(profile) => profiles.push(profile),
+            Err(e) => {
+                eprintln!("Failed to generate {}: {}", planet, e);
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
 
-    // let matfiles: MatFile = load_json_file("../luts/matfiles.json").unwrap();
-    // println!("{:#?} matFiles", matfiles);
-    // let matcoloravg: MatColorAverage = load_json_file("../luts/matcoloravg.json").unwrap();
-    // println!("{:#?} matColorAvg", matcoloravg);
-    let planet_definitions: PlanetMaterials = load_json_file("../luts/matcolormap.json").unwrap();
+fn gen_main(args: &GenerateArgs) {
+    let planet_definitions = load_planet_definitions(&args.luts);
+    let ore_palette = load_ore_palette(&args.luts);
 
-    let planets = planet_definitions.0.keys();
+    let gpu_backend = match args.backend {
+        BackendKind::Cpu => None,
+        BackendKind::Gpu => match open_gpu_backend(&args.adapter) {
+            Ok(backend) => Some(backend),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        BackendKind::Auto => open_gpu_backend(&args.adapter).ok(),
+    };
 
-    for planet in planets {
-        // Skip planets that end on - Lava
-        if planet.ends_with(" - Lava") {
+    let mut profiles = Vec::new();
+    let (failed, report) = match gpu_backend {
+        Some(backend) => {
+            let failed = gen_planets(&backend, &planet_definitions, &ore_palette, args, &mut profiles);
+            let report = ProfileReport {
+                backend: backend.name(),
+                adapter: Some(backend.gpu().adapter.get_info().name),
+                gpu_timestamps: backend.has_timestamps(),
+                planets: profiles,
+            };
+            (failed, report)
+        }
+        None => {
+            println!("Using CPU backend");
+            let failed = gen_planets(&CpuBackend, &planet_definitions, &ore_palette, args, &mut profiles);
+            let report = ProfileReport {
+                backend: CpuBackend.name(),
+                adapter: None,
+                gpu_timestamps: false,
+                planets: profiles,
+            };
+            (failed, report)
+        }
+    };
+    if let Some(path) = &args.profile {
+        if let Err(e) = report.save(path) {
+            eprintln!("Failed to write the profile to {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+    if failed > 0 {
+        eprintln!("{} planet(s) failed", failed);
+        std::process::exit(1);
+    }
+}
+
+fn list_planets_main(args: &LutArgs) {
+    let planet_definitions = load_planet_definitions(args);
+    let mut planets: Vec<(&String, &PlanetMaterial)> = planet_definitions.0.iter().collect();
+    planets.sort_by(|a, b| a.0.cmp(b.0));
+
+    for (name, planet) in planets {
+        println!("{}\t{}", name, planet.base_path);
+    }
+}
+
+fn inspect_main(args: &InspectArgs) {
+    let planet_definitions = load_planet_definitions(&args.luts);
+    let ore_palette = load_ore_palette(&args.luts);
+    let planet = match planet_definitions.0.get(&args.planet) {
+        Some(planet) => planet,
+        None => {
+            eprintln!("Planet {} not found", args.planet);
+            std::process::exit(1);
+        }
+    };
+
+    println!("Name: {}", planet.name);
+    println!("Base folder: {}", planet.base_path);
+    let default = &planet.default_material;
+    println!(
+        "Default material: {} ({}, {}, {})",
+        default.material, default.r, default.g, default.b
+    );
+
+    let mut simple: Vec<(&String, &MaterialLayer)> = planet.simple_materials.iter().collect();
+    simple.sort_by_key(|(id, _)| id.parse::<u32>().unwrap_or(u32::MAX));
+    println!("Simple materials: {}", simple.len());
+    for (id, layer) in simple {
+        println!("  {:>3} {} ({}, {}, {})", id, layer.material, layer.r, layer.g, layer.b);
+    }
+
+    let mut complex: Vec<&VoxelMaterial> = planet.complex_materials.values().collect();
+    complex.sort_by_key(|material| material.id);
+    println!("Complex materials: {}", complex.len());
+    for material in complex {
+        println!("  {:>3} {} ({} rules)", material.id, material.name, material.rules.len());
+        for rule in &material.rules {
+            let layers: Vec<&str> = rule.layers.iter().map(|layer| layer.material.as_str()).collect();
+            println!(
+                "      height {}..{} latitude {}..{} slope {}..{}: {}",
+                rule.min_height,
+                rule.max_height,
+                rule.latitude_min,
+                rule.latitude_max,
+                rule.slope_min,
+                rule.slope_max,
+                layers.join(", ")
+            );
+        }
+    }
+
+    let mut ores: Vec<&OreMap> = planet.ores.values().collect();
+    ores.sort_by_key(|ore| ore.value);
+    println!("Ores: {}", ores.len());
+    for ore in ores {
+        let ore_type = ore.ore_type.as_deref().unwrap_or("?");
+        let info = ore_palette.get(ore_type);
+        println!(
+            "  {:>3} {} [{} {}] (start {}, depth {})",
+            ore.value.unwrap_or_default(),
+            ore_type,
+            info.symbol,
+            info.name,
+            ore.start.unwrap_or_default(),
+            ore.depth.unwrap_or_default()
+        );
+    }
+}
+
+fn import_planets(args: &ImportArgs) -> Result<usize> {
+    let matfiles: MatFile = load_json_file(&args.luts.matfiles_path().to_string_lossy())?;
+    let matcoloravg: MatColorAverage =
+        load_json_file(&args.luts.matcoloravg_path().to_string_lossy())?;
+
+    let matcolormap_path = args.luts.matcolormap_path();
+    let mut planet_definitions = if matcolormap_path.exists() {
+        load_json_file(&matcolormap_path.to_string_lossy())?
+    } else {
+        PlanetMaterials(Default::default())
+    };
+
+    let definitions = Definitions::from_file(&args.sbc)?;
+    let names: Vec<&str> = definitions.names().collect();
+    println!("Found {} planet(s): {}", names.len(), names.join(", "));
+    for warning in &definitions.warnings {
+        println!("Warning: {}", warning);
+    }
+
+    let mut imported = 0;
+    for definition in &definitions.planets {
+        let name = &definition.id.subtype_id;
+        if !args.planets.is_empty() && !args.planets.iter().any(|pattern| glob_match(pattern, name)) {
             continue;
         }
-        println!("Planet: {}", planet);
-        // Gen only Planet Agaris
-        //if planet != "Planet Agaris" {
-        //    continue;
-        //}
-        // if planet != "EarthLike" {
-        //     continue;
-        // }
-        gen_planet(&planet_definitions, planet.clone());
+        println!("Processing {}", name);
+        let planet =
+            planet_material_from_definition(definition, &args.base_folder, &matfiles, &matcoloravg)?;
+        planet_definitions.0.insert(name.clone(), planet);
+        imported += 1;
+    }
+
+    std::fs::write(&matcolormap_path, serde_json::to_string_pretty(&planet_definitions)?)?;
+    Ok(imported)
+}
+
+fn import_main(args: &ImportArgs) {
+    match import_planets(args) {
+        Ok(0) => {
+            eprintln!("No matching planet in {}", args.sbc.display());
+            std::process::exit(1);
+        }
+        Ok(imported) => println!("Imported {} planet(s)", imported),
+        Err(e) => {
+            eprintln!("Failed to import {}: {}", args.sbc.display(), e);
+            std::process::exit(1);
+        }
     }
+}
+
+fn write_planet_meta(planet: &PlanetMaterial, args: &MetaArgs) -> Result<()> {
+    let meta = generate_planet_meta(planet, &args.assets.join(&planet.base_path))?;
+    std::fs::create_dir_all(&args.output)?;
+    let path = args.output.join(planet_meta_file_name(&planet.name));
+    std::fs::write(path, serde_json::to_string_pretty(&meta)?)?;
+    Ok(())
+}
 
+fn meta_main(args: &MetaArgs) {
+    let planet_definitions = load_planet_definitions(&args.luts);
+    let mut planets: Vec<(&String, &PlanetMaterial)> = planet_definitions
+        .0
+        .iter()
+        .filter(|(name, _)| args.filter.matches(name))
+        .collect();
+    planets.sort_by(|a, b| a.0.cmp(b.0));
 
+    let mut failed = 0;
+    for (name, planet) in planets {
+        println!("Processing {}...", name);
+        if let Err(e) = write_planet_meta(planet, args) {
+            eprintln!("Failed to process {}: {}", name, e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        eprintln!("{} planet(s) failed", failed);
+        std::process::exit(1);
+    }
+}
+
+// The six faces, in `CUBEMAP` order
+fn load_faces(images: &FaceImages, format: wgpu::TextureFormat) -> Result<Vec<CpuTexture>> {
+    CUBEMAP
+        .iter()
+        .map(|face| CpuTexture::from_file(&images.path(face).to_string_lossy(), format))
+        .collect()
+}
+
+fn project(args: &ProjectArgs) -> Result<()> {
+    let format = if args.heightmap {
+        wgpu::TextureFormat::R32Float
+    } else {
+        wgpu::TextureFormat::Rgba8Unorm
+    };
+    let faces = load_faces(&args.faces, format)?;
+
+    let map = reproject(&faces, args.projection, args.width, args.filter)?;
+    map.save_to_file(&args.output.to_string_lossy())
+}
+
+fn project_main(args: &ProjectArgs) {
+    match project(args) {
+        Ok(()) => println!("Wrote {}", args.output.display()),
+        Err(e) => {
+            eprintln!("Failed to project {}: {}", args.faces.input.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn cubemap_main(args: &CubemapArgs) {
+    let result = load_faces(&args.faces, wgpu::TextureFormat::Rgba8Unorm)
+        .and_then(|faces| export_cubemap(&faces, &args.output, &args.options()));
+    match result {
+        Ok(()) => println!("Wrote {}", args.output.display()),
+        Err(e) => {
+            eprintln!("Failed to export {}: {}", args.faces.input.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn tiles(args: &TilesArgs) -> Result<()> {
+    let format = wgpu::TextureFormat::Rgba8Unorm;
+    let manifest = if args.map {
+        let map = CpuTexture::from_file(&args.faces.input.to_string_lossy(), format)?;
+        tile_map(&map, &args.output, &args.options())?
+    } else {
+        let faces = load_faces(&args.faces, format)?;
+        tile_faces(&faces, &args.output, args.scheme, &args.options())?
+    };
+    std::fs::write(args.output.join("manifest.json"), serde_json::to_string_pretty(&manifest)?)?;
+    Ok(())
+}
+
+fn tiles_main(args: &TilesArgs) {
+    match tiles(args) {
+        Ok(()) => println!("Wrote tiles to {}", args.output.display()),
+        Err(e) => {
+            eprintln!("Failed to write tiles: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn adapters_main(args: &AdapterArgs) {
+    let adapters = gpu::gpu::list_adapters(args.options().backends);
+    if adapters.is_empty() {
+        println!("No GPU adapter found");
+    }
+
+    for adapter in adapters {
+        let info = adapter.get_info();
+        let limits = adapter.limits();
+        let software = if gpu::gpu::is_software(&info) { ", software" } else { "" };
+        println!("{} ({:?}, {:?}{})", info.name, info.backend, info.device_type, software);
+        if !info.driver.is_empty() {
+            println!("    driver: {} {}", info.driver, info.driver_info);
+        }
+        println!(
+            "    max texture size {}, max storage textures per stage {}, max workgroup invocations {}",
+            limits.max_texture_dimension_2d,
+            limits.max_storage_textures_per_shader_stage,
+            limits.max_compute_invocations_per_workgroup
+        );
+    }
+}
+
+fn parity_main(args: &ParityArgs) {
+    let planet_definitions = load_planet_definitions(&args.luts);
+    let gpu_backend = match open_gpu_backend(&args.adapter) {
+        Ok(backend) => backend,
+        Err(e) => {
+            println!("Skipping parity check, {}", e);
+            return;
+        }
+    };
+
+    let mut failed = false;
+    let mut planets: Vec<(&String, &PlanetMaterial)> = planet_definitions
+        .0
+        .iter()
+        .filter(|(name, _)| args.filter.matches(name))
+        .collect();
+    planets.sort_by(|a, b| a.0.cmp(b.0));
+
+    for (name, planet) in planets {
+        for face in CUBEMAP.iter() {
+            let stages = match compare_backends(&gpu_backend, face, args.size, planet, args.tolerance) {
+                Ok(stages) => stages,
+                Err(e) => {
+                    eprintln!("{} {}: {}", name, face, e);
+                    failed = true;
+                    continue;
+                }
+            };
+            for stage in stages {
+                println!(
+                    "{} {} {}: max difference {}, {}/{} texels over tolerance, {} rule edge flips",
+                    name,
+                    face,
+                    stage.stage,
+                    stage.max_difference,
+                    stage.mismatched_texels,
+                    stage.texels,
+                    stage.edge_flips
+                );
+                failed |= !stage.passed();
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
 }
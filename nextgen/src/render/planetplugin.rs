@@ -104,7 +104,7 @@ pub fn planet_update_system(
         let hm = data
             .heightmap
             .iter()
-            .map(|x| Some(image_from_bevy(images.get(x).unwrap())))
+            .map(|x| image_from_bevy(images.get(x).unwrap()).ok())
             .collect::<Vec<Option<DynamicImage>>>();
 
         // Create cube sphere to update meshes
@@ -147,7 +147,7 @@ pub fn planet_spec_change(
         let hm = data
             .heightmap
             .iter()
-            .map(|x| Some(image_from_bevy(images.get(x).unwrap())))
+            .map(|x| image_from_bevy(images.get(x).unwrap()).ok())
             .collect::<Vec<Option<DynamicImage>>>();
 
         // Create cube sphere to update meshes
@@ -1,7 +1,7 @@
 use super::{material::Textures, vector::Vector3F};
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Default)]
 pub struct Atmosphere {
     #[serde(rename = "Breathable")]
     pub breathable: Option<bool>,
@@ -15,7 +15,7 @@ pub struct Atmosphere {
     pub max_wind_speed: Option<u32>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Default)]
 pub struct AtmosphereSettings {
     #[serde(rename = "RayleighScattering")]
     pub rayleigh_scattering: Option<Vector3F>,
@@ -42,10 +42,10 @@ pub struct AtmosphereSettings {
     #[serde(rename = "FogIntensity")]
     pub fog_intensity: Option<f32>,
     #[serde(rename = "Scale")]
-    pub scale: Option<u32>,
+    pub scale: Option<f32>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Default)]
 pub struct CloudLayers {
     #[serde(rename = "CloudLayer")]
     pub cloud_layer: Option<Vec<CloudLayer>>,
@@ -0,0 +1,192 @@
+use std::{collections::HashSet, path::Path};
+
+use quick_xml::events::{BytesStart, Event};
+use serde::Deserialize;
+
+use crate::error::Result;
+
+use super::planet_generator_definition::PlanetGeneratorDefinition;
+
+/// Every planet generator definition of a `.sbc` file.
+///
+/// Both layouts are accepted:
+/// - `<Definitions><PlanetGeneratorDefinitions><PlanetGeneratorDefinition>`, used by the vanilla files
+/// - `<Definitions><Definition xsi:type="PlanetGeneratorDefinition">`, used by most mods
+///
+/// `Definition` elements of any other type are skipped.
+#[derive(Debug)]
+pub struct Definitions {
+    pub planets: Vec<PlanetGeneratorDefinition>,
+    /// Elements and attributes that were not understood and got ignored
+    pub warnings: Vec<String>,
+}
+
+// A single definition, wrapped in the root element so namespace prefixes stay bound
+#[derive(Deserialize)]
+struct SingleDefinition {
+    #[serde(rename = "PlanetGeneratorDefinition", alias = "Definition")]
+    definition: PlanetGeneratorDefinition,
+}
+
+impl Definitions {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Definitions::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(xml: &str) -> Result<Self> {
+        let mut reader = quick_xml::Reader::from_str(xml);
+        // Start tag and name of the root element
+        let mut root: Option<(&str, String)> = None;
+        let mut depth = 0;
+        // Byte offset and depth of the planet definition being read
+        let mut current: Option<(usize, usize)> = None;
+        let mut planets = Vec::new();
+        let mut warnings = Vec::new();
+
+        loop {
+            let position = reader.buffer_position();
+            match reader.read_event()? {
+                Event::Start(e) => {
+                    depth += 1;
+                    match depth {
+                        1 => {
+                            let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                            root = Some((&xml[position..reader.buffer_position()], name));
+                        }
+                        2 if is_planet_definition(&e)? => current = Some((position, depth)),
+                        3 if current.is_none() && e.name().as_ref() == b"PlanetGeneratorDefinition" => {
+                            current = Some((position, depth))
+                        }
+                        _ => {}
+                    }
+                }
+                Event::End(_) => {
+                    if let Some((start, start_depth)) = current {
+                        if start_depth == depth {
+                            let element = &xml[start..reader.buffer_position()];
+                            let (root_tag, root_name) = root.as_ref().unwrap();
+                            planets.push(parse_definition(root_tag, root_name, element, &mut warnings)?);
+                            current = None;
+                        }
+                    }
+                    depth -= 1;
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        Ok(Definitions { planets, warnings })
+    }
+
+    /// Looks a planet up by its `Id.SubtypeId`.
+    pub fn get(&self, subtype_id: &str) -> Option<&PlanetGeneratorDefinition> {
+        self.planets.iter().find(|planet| planet.id.subtype_id == subtype_id)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.planets.iter().map(|planet| planet.id.subtype_id.as_str())
+    }
+}
+
+// `<Definition xsi:type="PlanetGeneratorDefinition">`, with or without the `MyObjectBuilder_` prefix
+fn is_planet_definition(e: &BytesStart) -> Result<bool> {
+    if e.name().as_ref() != b"Definition" {
+        return Ok(false);
+    }
+    for attribute in e.attributes() {
+        let attribute = attribute.map_err(quick_xml::Error::from)?;
+        if attribute.key.local_name().as_ref() == b"type" {
+            let value = attribute.unescape_value()?;
+            return Ok(value.ends_with("PlanetGeneratorDefinition"));
+        }
+    }
+    Ok(false)
+}
+
+// Paths of the `xsi:type` attributes in `element`, like `Definition.MesherPostprocessing.type`.
+// serde_xml_rs drops the `xsi:` prefix, so the path serde_ignored gives can't tell them apart
+// from a `type` element.
+fn xsi_type_paths(element: &str) -> Result<HashSet<String>> {
+    let mut reader = quick_xml::Reader::from_str(element);
+    let mut names: Vec<String> = Vec::new();
+    let mut paths = HashSet::new();
+    loop {
+        let (e, empty) = match reader.read_event()? {
+            Event::Start(e) => (e, false),
+            Event::Empty(e) => (e, true),
+            Event::End(_) => {
+                names.pop();
+                continue;
+            }
+            Event::Eof => return Ok(paths),
+            _ => continue,
+        };
+        let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+        for attribute in e.attributes() {
+            let attribute = attribute.map_err(quick_xml::Error::from)?;
+            if attribute.key.as_ref() == b"xsi:type" {
+                let mut path = names.clone();
+                path.extend([name.clone(), "type".to_owned()]);
+                paths.insert(path.join("."));
+            }
+        }
+        if !empty {
+            names.push(name);
+        }
+    }
+}
+
+// A serde_ignored path without the indices of the sequences it goes through
+fn without_indices(path: &str) -> String {
+    let segments: Vec<&str> =
+        path.split('.').filter(|segment| !segment.chars().all(|c| c.is_ascii_digit())).collect();
+    segments.join(".")
+}
+
+fn parse_definition(
+    root_tag: &str,
+    root_name: &str,
+    element: &str,
+    warnings: &mut Vec<String>,
+) -> Result<PlanetGeneratorDefinition> {
+    let xml = format!("{}{}</{}>", root_tag, element, root_name);
+    let mut deserializer = serde_xml_rs::Deserializer::new_from_reader(xml.as_bytes());
+    let xsi_types = xsi_type_paths(element)?;
+    let mut ignored = Vec::new();
+    let single: SingleDefinition = serde_ignored::deserialize(&mut deserializer, |path| {
+        // Drop the `?` serde_ignored prints for every `Option`
+        let path = path.to_string().replace(".?", "");
+        // `xsi:type` only tells the game which builder to use
+        if !xsi_types.contains(&without_indices(&path)) {
+            ignored.push(path);
+        }
+    })?;
+
+    let name = &single.definition.id.subtype_id;
+    warnings.extend(ignored.into_iter().map(|path| format!("{}: ignored {}", name, path)));
+    Ok(single.definition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xsi_type_paths_only_holds_attributes() {
+        let element = r#"<Definition xsi:type="PlanetGeneratorDefinition">
+            <Mesher xsi:type="Builder"/>
+            <SurfaceDetail><type>x</type></SurfaceDetail>
+        </Definition>"#;
+        let paths = xsi_type_paths(element).unwrap();
+        let expected: HashSet<String> =
+            ["Definition.type", "Definition.Mesher.type"].iter().map(|path| path.to_string()).collect();
+        assert_eq!(paths, expected);
+    }
+
+    #[test]
+    fn without_indices_drops_sequence_positions() {
+        assert_eq!(without_indices("Definition.OreMappings.3.type"), "Definition.OreMappings.type");
+        assert_eq!(without_indices("Definition.type"), "Definition.type");
+    }
+}
@@ -26,7 +26,7 @@ pub struct ItemDetail {
     pub modifier_id: Option<String>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Default)]
 pub struct DefaultSurfaceMaterial {
     #[serde(rename = "Material")]
     pub material: Option<String>,
@@ -34,45 +34,45 @@ pub struct DefaultSurfaceMaterial {
     pub max_depth: Option<u32>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Default)]
 pub struct DefaultSubSurfaceMaterial {
     #[serde(rename = "Material")]
     pub material: Option<String>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Default)]
 pub struct EnvironmentItems {
-    #[serde(rename = "Item")]
+    #[serde(rename = "Item", default)]
     pub item: Vec<Item>,
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct Item {
-    #[serde(rename = "Biomes")]
+    #[serde(rename = "Biomes", default)]
     pub biomes: Biomes,
-    #[serde(rename = "Materials")]
+    #[serde(rename = "Materials", default)]
     pub materials: Materials,
-    #[serde(rename = "Items")]
+    #[serde(rename = "Items", default)]
     pub items: Items,
-    #[serde(rename = "Rule")]
+    #[serde(rename = "Rule", default)]
     pub rule: Rule,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Default)]
 pub struct Biomes {
     #[serde(rename = "Biome")]
     pub biome: Option<Vec<u32>>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Default)]
 pub struct Materials {
     #[serde(rename = "Material")]
     pub material: Option<Vec<String>>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Default)]
 pub struct Items {
-    #[serde(rename = "Item")]
+    #[serde(rename = "Item", default)]
     pub item: Vec<ItemAttributes>,
 }
 
@@ -90,7 +90,7 @@ pub struct ItemAttributes {
     pub density: Option<f32>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Default)]
 pub struct Rule {
     #[serde(rename = "Height")]
     pub height: Option<MinMax>,
@@ -1,7 +1,7 @@
 use serde::Deserialize;
 
 use super::vector::{Height, Latitude, Slope};
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Default)]
 pub struct ComplexMaterials {
     #[serde(rename = "MaterialGroup")]
     pub material_groups: Option<Vec<MaterialGroup>>,
@@ -20,7 +20,7 @@ pub struct MaterialGroup {
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct Rule {
     #[serde(rename = "Layers")]
-    pub layers: Option<Vec<Layer>>,
+    pub layers: Option<Layers>,
     #[serde(rename = "Height")]
     pub height: Option<Height>,
     #[serde(rename = "Latitude")]
@@ -29,6 +29,12 @@ pub struct Rule {
     pub slope: Option<Slope>,
 }
 
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct Layers {
+    #[serde(rename = "Layer")]
+    pub layers: Option<Vec<Layer>>,
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct Layer {
     #[serde(rename = "Material")]
@@ -37,6 +43,22 @@ pub struct Layer {
     pub depth: Option<u32>,
 }
 
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct CustomMaterialTable {
+    #[serde(rename = "Material")]
+    pub materials: Option<Vec<CustomMaterial>>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct CustomMaterial {
+    #[serde(rename = "Material")]
+    pub material: Option<String>,
+    #[serde(rename = "Value")]
+    pub value: Option<u32>,
+    #[serde(rename = "MaxDepth")]
+    pub max_depth: Option<u32>,
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct Textures {
     #[serde(rename = "Texture")]
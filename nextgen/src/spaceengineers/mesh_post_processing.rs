@@ -32,7 +32,7 @@ pub struct PostprocessingSteps {
     pub step: Option<Step>,
 }
 
-#[derive(Deserialize, Debug, PartialEq)]
+#[derive(Deserialize, Debug, PartialEq, Default)]
 pub struct MesherPostprocessing {
     #[serde(rename = "PostprocessingSteps")]
     pub postprocessing_steps: Option<PostprocessingSteps>,
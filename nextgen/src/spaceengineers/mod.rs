@@ -1,6 +1,7 @@
 /** Space Engineers Library **/
 
 pub mod atmosphere;
+pub mod definitions;
 pub mod environment;
 pub mod id;
 pub mod material;
@@ -10,46 +10,70 @@ use super::sound::SoundRules;
 use super::vector::*;
 use super::weather::*;
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "PascalCase")]
-#[serde(deny_unknown_fields)]
-pub struct Definitions {
-    pub planet_generator_definitions: PlanetGeneratorDefinitions,
-}
-
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "PascalCase")]
-pub struct PlanetGeneratorDefinitions {
-    pub planet_generator_definition: PlanetGeneratorDefinition,
-}
-
+/// Only `Id` is required, every other section falls back to the vanilla value
+/// when a definition leaves it out.
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct PlanetGeneratorDefinition {
     pub id: Id,
+    #[serde(default)]
     pub planet_maps: PlanetMaps,
-    #[serde(rename = "MesherPostprocessing")]
+    #[serde(rename = "MesherPostprocessing", default)]
     pub mesher_postprocessing: MesherPostprocessing,
+    #[serde(default)]
     pub surface_detail: SurfaceDetail,
+    #[serde(default)]
     pub ore_mappings: OreMappings,
+    #[serde(default)]
     pub sound_rules: SoundRules,
+    #[serde(default)]
     pub complex_materials: ComplexMaterials,
+    pub custom_material_table: Option<CustomMaterialTable>,
+    #[serde(default)]
     pub environment_items: EnvironmentItems,
+    #[serde(default)]
     pub default_surface_material: DefaultSurfaceMaterial,
+    #[serde(default)]
     pub default_sub_surface_material: DefaultSubSurfaceMaterial,
+    #[serde(default)]
     pub has_atmosphere: bool,
+    #[serde(default)]
     pub atmosphere: Atmosphere,
+    #[serde(default)]
     pub atmosphere_settings: AtmosphereSettings,
+    #[serde(default)]
     pub cloud_layers: CloudLayers,
+    #[serde(default = "default_weather_frequency_min")]
     pub weather_frequency_min: u32,
+    #[serde(default = "default_weather_frequency_max")]
     pub weather_frequency_max: u32,
+    #[serde(default)]
     pub weather_generators: WeatherGenerators,
+    #[serde(default = "default_minimum_surface_layer_depth")]
     pub minimum_surface_layer_depth: u32,
-    pub surface_gravity: u32,
+    #[serde(default = "default_surface_gravity")]
+    pub surface_gravity: f32,
+    #[serde(default)]
     pub materials_max_depth: MaterialsMaxDepth,
+    #[serde(default)]
     pub materials_min_depth: MaterialsMinDepth,
+    #[serde(default)]
     pub hill_params: HillParams,
 }
+
+fn default_weather_frequency_min() -> u32 {
+    2
+}
+fn default_weather_frequency_max() -> u32 {
+    5
+}
+fn default_minimum_surface_layer_depth() -> u32 {
+    4
+}
+fn default_surface_gravity() -> f32 {
+    1.0
+}
+
 #[derive(Deserialize, Debug, PartialEq)]
 pub struct PlanetMaps {
     #[serde(rename = "Material")]
@@ -98,3 +122,45 @@ pub struct SurfaceDetail {
     #[serde(rename = "Transition")]
     pub transition: f64,
 }
+
+impl Default for PlanetMaps {
+    fn default() -> Self {
+        PlanetMaps {
+            material: true,
+            ores: true,
+            biome: true,
+            occlusion: true,
+        }
+    }
+}
+
+impl Default for MaterialsMaxDepth {
+    fn default() -> Self {
+        MaterialsMaxDepth { min: 5000, max: 5000 }
+    }
+}
+
+impl Default for MaterialsMinDepth {
+    fn default() -> Self {
+        MaterialsMinDepth { min: 99, max: 100 }
+    }
+}
+
+// EarthLike values
+impl Default for HillParams {
+    fn default() -> Self {
+        HillParams { min: -0.1, max: 0.12 }
+    }
+}
+
+impl Default for SurfaceDetail {
+    fn default() -> Self {
+        SurfaceDetail {
+            texture: "Data/PlanetDataFiles/Extra/material_detail_4.dds".to_owned(),
+            size: 384,
+            scale: 5,
+            slope: Range { min: 5.0, max: 35.0 },
+            transition: 10.0,
+        }
+    }
+}
@@ -14,8 +14,8 @@ pub struct SoundRule {
     pub environment_sound: Option<String>,
 }
 
-#[derive(Deserialize, Debug, PartialEq)]
+#[derive(Deserialize, Debug, PartialEq, Default)]
 pub struct SoundRules {
-    #[serde(rename = "SoundRule")]
+    #[serde(rename = "SoundRule", default)]
     pub sound_rule: Vec<SoundRule>,
 }
@@ -1,22 +1,22 @@
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Default)]
 pub struct WeatherGenerators {
-    #[serde(rename = "WeatherGenerator")]
+    #[serde(rename = "WeatherGenerator", default)]
     pub weather_generator: Vec<WeatherGenerator>,
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct WeatherGenerator {
-    #[serde(rename = "Voxel")]
+    #[serde(rename = "Voxel", default)]
     pub voxel: String,
-    #[serde(rename = "Weathers")]
+    #[serde(rename = "Weathers", default)]
     pub weathers: Weathers,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Default)]
 pub struct Weathers {
-    #[serde(rename = "Weather")]
+    #[serde(rename = "Weather", default)]
     pub weather: Vec<Weather>,
 }
 
@@ -0,0 +1,1090 @@
+use std::{
+    cell::{OnceCell, RefCell},
+    rc::Rc,
+    str::FromStr,
+};
+
+use crate::{
+    cpu::texture::CpuTexture,
+    error::{check_layers, Error, Result},
+    gpu::{
+        gpu::Gpu,
+        texture::{Readback, Texture},
+    },
+};
+
+use super::{
+    lutgen::{lat_lon_to_texel, TerrainScale, CUBEMAP},
+    lutgen_cpu::{cpu_generate_geolut, cpu_generate_latlut, cpu_generate_slope},
+    lutgen_gpu::{face_to_num, gpu_generate_geolut_inner, gpu_generate_latlut_inner, gpu_generate_slope_inner},
+    matcolormap::{MaterialBlend, MaterialView, PlanetMaterial},
+    material_cpu::generate_material_cpu,
+    material_gpu::{generate_material_gpu, GPUMaterialRule},
+    normal::gpu_generate_normal_inner,
+    normal_cpu::cpu_generate_normal,
+    oreoverlay_cpu::generate_ore_overlay_cpu,
+    oreoverlay_gpu::{generate_ore_overlay_gpu, OreOverlayStyle},
+    orepalette::OrePalette,
+    pipeline_gpu::PipelineContext,
+    profile::DeviceTime,
+    tiling::{split_face, tile_input, FaceTile},
+};
+
+/// A place where the planet texture pipeline can run.
+///
+/// Every stage takes and returns the backend's own texture type, so a whole face can be
+/// generated without leaving the backend. Both backends produce the same images.
+///
+/// Textures can hold several faces as layers (see `CpuTexture::from_layers`). Stages run on
+/// every layer, each on its own, and the inputs of a stage hold the same faces.
+pub trait PlanetBackend {
+    type Texture;
+    /// A texture on its way back to memory, see `start_read`.
+    type Readback;
+
+    fn name(&self) -> &'static str;
+
+    fn load_texture(&self, path: &str, format: wgpu::TextureFormat, label: Option<&str>) -> Result<Self::Texture>;
+    fn texture_from_data(
+        &self,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        data: Vec<u8>,
+        label: Option<&str>,
+    ) -> Result<Self::Texture>;
+    /// Uploads a texture from memory, with all its layers.
+    fn upload_texture(&self, texture: &CpuTexture, label: Option<&str>) -> Result<Self::Texture>;
+
+    fn generate_latlut(&self, face: &str, width: u32, height: u32) -> Result<Self::Texture> {
+        self.generate_latlut_tile(face, &FaceTile::whole(width, height))
+    }
+    fn generate_latlut_tile(&self, face: &str, tile: &FaceTile) -> Result<Self::Texture>;
+    /// The latitude LUT of the six faces, as the layers of one texture in `CUBEMAP` order.
+    fn generate_cube_latlut_tile(&self, tile: &FaceTile) -> Result<Self::Texture>;
+    /// Signed latitude and longitude in degrees of every texel, as a `Rg32Float` texture
+    fn generate_geolut(&self, face: &str, width: u32, height: u32) -> Result<Self::Texture>;
+    /// `border` texels on each side of the heightmap come from the adjacent faces (see
+    /// `cubemap::bordered_face`), the output leaves them out. Without a border the face edges
+    /// get seams. Without a scale heights are unitless, one step per texel.
+    fn generate_slope(
+        &self,
+        heightmap: &Self::Texture,
+        border: u32,
+        scale: Option<&TerrainScale>,
+    ) -> Result<Self::Texture> {
+        let tile = FaceTile::bordered(self.width(heightmap), self.height(heightmap), border);
+        self.generate_slope_tile(heightmap, &tile, scale)
+    }
+    fn generate_normal(
+        &self,
+        heightmap: &Self::Texture,
+        border: u32,
+        scale: Option<&TerrainScale>,
+    ) -> Result<Self::Texture> {
+        let tile = FaceTile::bordered(self.width(heightmap), self.height(heightmap), border);
+        self.generate_normal_tile(heightmap, &tile, scale)
+    }
+    /// Same on a tile of the face, the heightmap holding the tile and its halo (see
+    /// `tiling::tile_input`). The output is the size of the tile.
+    fn generate_slope_tile(
+        &self,
+        heightmap: &Self::Texture,
+        tile: &FaceTile,
+        scale: Option<&TerrainScale>,
+    ) -> Result<Self::Texture>;
+    fn generate_normal_tile(
+        &self,
+        heightmap: &Self::Texture,
+        tile: &FaceTile,
+        scale: Option<&TerrainScale>,
+    ) -> Result<Self::Texture>;
+    #[allow(clippy::too_many_arguments)]
+    fn generate_material(
+        &self,
+        materialmap: &Self::Texture,
+        heightmap: &Self::Texture,
+        latlut: &Self::Texture,
+        normalmap: &Self::Texture,
+        slopemap: &Self::Texture,
+        materials: &PlanetMaterial,
+        view: &MaterialView,
+    ) -> Result<Self::Texture>;
+    fn generate_ore_overlay(
+        &self,
+        materialmap: &Self::Texture,
+        materials: &PlanetMaterial,
+        palette: &OrePalette,
+        style: &OreOverlayStyle,
+    ) -> Result<Self::Texture> {
+        let tile = FaceTile::whole(self.width(materialmap), self.height(materialmap));
+        self.generate_ore_overlay_tile(materialmap, &tile, materials, palette, style)
+    }
+    fn generate_ore_overlay_tile(
+        &self,
+        materialmap: &Self::Texture,
+        tile: &FaceTile,
+        materials: &PlanetMaterial,
+        palette: &OrePalette,
+        style: &OreOverlayStyle,
+    ) -> Result<Self::Texture>;
+
+    /// Largest width and height of a texture the stages can take. Larger faces have to be
+    /// split with `tiling::split_face`.
+    fn max_texture_size(&self) -> u32;
+    fn width(&self, texture: &Self::Texture) -> u32;
+    fn height(&self, texture: &Self::Texture) -> u32;
+    fn layers(&self, texture: &Self::Texture) -> u32;
+    /// Starts copying a texture back to memory, finished by `finish_read`. Stages run after
+    /// `flush` can overlap with the copy, on backends that record stages before running them.
+    fn start_read(&self, texture: &Self::Texture) -> Result<Self::Readback>;
+    fn finish_read(&self, readback: Self::Readback) -> Result<CpuTexture>;
+    /// Starts running the stages and reads recorded so far, without waiting for them.
+    fn flush(&self);
+    fn read_texture(&self, texture: &Self::Texture) -> Result<CpuTexture> {
+        self.finish_read(self.start_read(texture)?)
+    }
+    fn save_texture(&self, texture: &Self::Texture, path: &str) -> Result<()>;
+
+    /// Times the stages run from now on on the device, as run on `faces`, on backends that
+    /// can. Nothing is timed before the first call.
+    fn time_stages(&self, _faces: &str) {}
+    /// Device time of every stage timed since the last call. Waits for them to finish.
+    fn take_device_times(&self) -> Result<Vec<DeviceTime>> {
+        Ok(Vec::new())
+    }
+}
+
+// Stages recorded since the last submission
+struct Batch {
+    encoder: wgpu::CommandEncoder,
+    submission: Rc<OnceCell<wgpu::SubmissionIndex>>,
+}
+
+// Timestamps written before and after a stage, resolved where the CPU can read them
+struct StageTimestamps {
+    faces: String,
+    stage: &'static str,
+    buffer: wgpu::Buffer,
+}
+
+impl StageTimestamps {
+    const SIZE: wgpu::BufferAddress = 2 * std::mem::size_of::<u64>() as wgpu::BufferAddress;
+
+    async fn read(self, gpu: &Gpu) -> Result<DeviceTime> {
+        let buffer_slice = self.buffer.slice(..);
+        let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+            tx.send(result).unwrap();
+        });
+        gpu.device.poll(wgpu::Maintain::Wait);
+        rx.receive().await.unwrap()?;
+
+        let ticks = {
+            let mapped = buffer_slice.get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&mapped);
+            timestamps[1].wrapping_sub(timestamps[0])
+        };
+        self.buffer.unmap();
+        Ok(DeviceTime {
+            faces: self.faces,
+            stage: self.stage,
+            milliseconds: ticks as f64 * gpu.queue.get_timestamp_period() as f64 / 1_000_000.0,
+        })
+    }
+}
+
+/// Runs the stages with the pipelines of a `PipelineContext`, recording them into one command
+/// encoder until a texture is read back or `flush` submits them.
+pub struct GpuBackend {
+    gpu: Gpu,
+    pipelines: PipelineContext,
+    batch: RefCell<Option<Batch>>,
+    // Faces the stages are timed as, None until `time_stages`
+    timed_faces: RefCell<Option<String>>,
+    timestamps: RefCell<Vec<StageTimestamps>>,
+}
+
+/// A texture copy of `GpuBackend`, with the submission it waits for once its batch is flushed.
+pub struct GpuReadback {
+    readback: Readback,
+    submission: Rc<OnceCell<wgpu::SubmissionIndex>>,
+}
+
+impl GpuBackend {
+    /// Compiles every stage for `gpu`.
+    pub fn new(gpu: Gpu) -> Result<Self> {
+        let pipelines = futures::executor::block_on(PipelineContext::new(&gpu))?;
+        Ok(GpuBackend {
+            gpu,
+            pipelines,
+            batch: RefCell::new(None),
+            timed_faces: RefCell::new(None),
+            timestamps: RefCell::new(Vec::new()),
+        })
+    }
+
+    pub fn gpu(&self) -> &Gpu {
+        &self.gpu
+    }
+
+    /// Whether the device can time the stages, see `PlanetBackend::time_stages`.
+    pub fn has_timestamps(&self) -> bool {
+        self.gpu.device.features().contains(wgpu::Features::TIMESTAMP_QUERY)
+    }
+
+    // Runs `f` on the batch being recorded, starting one if needed. `stage` names it in the
+    // device times.
+    fn record<T>(&self, stage: &'static str, f: impl FnOnce(&mut Batch) -> T) -> T {
+        let mut batch = self.batch.borrow_mut();
+        let batch = batch.get_or_insert_with(|| Batch {
+            encoder: self.gpu.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Planet Encoder"),
+            }),
+            submission: Rc::new(OnceCell::new()),
+        });
+        let faces = match self.timed_faces.borrow().as_ref() {
+            Some(faces) if self.has_timestamps() => faces.clone(),
+            _ => return f(batch),
+        };
+
+        let device = &self.gpu.device;
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some(stage),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2,
+        });
+        batch.encoder.write_timestamp(&query_set, 0);
+        let value = f(batch);
+        batch.encoder.write_timestamp(&query_set, 1);
+
+        // Queries resolve straight into a mappable buffer
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(stage),
+            size: StageTimestamps::SIZE,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        batch.encoder.resolve_query_set(&query_set, 0..2, &buffer, 0);
+        self.timestamps.borrow_mut().push(StageTimestamps {
+            faces,
+            stage,
+            buffer,
+        });
+        value
+    }
+}
+
+impl PlanetBackend for GpuBackend {
+    type Texture = Texture;
+    type Readback = GpuReadback;
+
+    fn name(&self) -> &'static str {
+        "gpu"
+    }
+
+    fn load_texture(&self, path: &str, format: wgpu::TextureFormat, label: Option<&str>) -> Result<Texture> {
+        Texture::from_file(&self.gpu, path, format, label)
+    }
+
+    fn texture_from_data(
+        &self,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        data: Vec<u8>,
+        label: Option<&str>,
+    ) -> Result<Texture> {
+        Texture::from_data(&self.gpu, width, height, format, data.as_slice(), label)
+    }
+
+    fn upload_texture(&self, texture: &CpuTexture, label: Option<&str>) -> Result<Texture> {
+        Texture::from_cpu(&self.gpu, texture, label)
+    }
+
+    fn generate_latlut_tile(&self, face: &str, tile: &FaceTile) -> Result<Texture> {
+        self.record("latlut", |batch| {
+            gpu_generate_latlut_inner(&self.gpu, &self.pipelines, &mut batch.encoder, face, 1, tile)
+        })
+    }
+
+    fn generate_cube_latlut_tile(&self, tile: &FaceTile) -> Result<Texture> {
+        let faces = CUBEMAP.len() as u32;
+        self.record("latlut", |batch| {
+            gpu_generate_latlut_inner(&self.gpu, &self.pipelines, &mut batch.encoder, CUBEMAP[0], faces, tile)
+        })
+    }
+
+    fn generate_geolut(&self, face: &str, width: u32, height: u32) -> Result<Texture> {
+        self.record("geolut", |batch| {
+            gpu_generate_geolut_inner(&self.gpu, &self.pipelines, &mut batch.encoder, face, width, height)
+        })
+    }
+
+    fn generate_slope_tile(
+        &self,
+        heightmap: &Texture,
+        tile: &FaceTile,
+        scale: Option<&TerrainScale>,
+    ) -> Result<Texture> {
+        self.record("slope", |batch| {
+            gpu_generate_slope_inner(&self.gpu, &self.pipelines, &mut batch.encoder, heightmap, tile, scale)
+        })
+    }
+
+    fn generate_normal_tile(
+        &self,
+        heightmap: &Texture,
+        tile: &FaceTile,
+        scale: Option<&TerrainScale>,
+    ) -> Result<Texture> {
+        self.record("normal", |batch| {
+            gpu_generate_normal_inner(&self.gpu, &self.pipelines, &mut batch.encoder, heightmap, tile, scale)
+        })
+    }
+
+    fn generate_material(
+        &self,
+        materialmap: &Texture,
+        heightmap: &Texture,
+        latlut: &Texture,
+        normalmap: &Texture,
+        slopemap: &Texture,
+        materials: &PlanetMaterial,
+        view: &MaterialView,
+    ) -> Result<Texture> {
+        self.record("material", |batch| {
+            generate_material_gpu(
+                &self.gpu,
+                &self.pipelines,
+                &mut batch.encoder,
+                materialmap,
+                heightmap,
+                latlut,
+                normalmap,
+                slopemap,
+                materials,
+                view,
+            )
+        })
+    }
+
+    fn generate_ore_overlay_tile(
+        &self,
+        materialmap: &Texture,
+        tile: &FaceTile,
+        materials: &PlanetMaterial,
+        palette: &OrePalette,
+        style: &OreOverlayStyle,
+    ) -> Result<Texture> {
+        self.record("ore overlay", |batch| {
+            generate_ore_overlay_gpu(
+                &self.gpu,
+                &self.pipelines,
+                &mut batch.encoder,
+                materialmap,
+                tile,
+                materials,
+                palette,
+                style,
+            )
+        })
+    }
+
+    fn max_texture_size(&self) -> u32 {
+        self.gpu.device.limits().max_texture_dimension_2d
+    }
+
+    fn width(&self, texture: &Texture) -> u32 {
+        texture.width()
+    }
+
+    fn height(&self, texture: &Texture) -> u32 {
+        texture.height()
+    }
+
+    fn layers(&self, texture: &Texture) -> u32 {
+        texture.layers()
+    }
+
+    fn start_read(&self, texture: &Texture) -> Result<GpuReadback> {
+        Ok(self.record("readback", |batch| GpuReadback {
+            readback: texture.record_readback(&self.gpu, &mut batch.encoder),
+            submission: batch.submission.clone(),
+        }))
+    }
+
+    fn finish_read(&self, readback: GpuReadback) -> Result<CpuTexture> {
+        // Still in the batch being recorded
+        if readback.submission.get().is_none() {
+            self.flush();
+        }
+        let submission = readback.submission.get().unwrap().clone();
+        futures::executor::block_on(readback.readback.finish(&self.gpu, submission))
+    }
+
+    fn flush(&self) {
+        if let Some(batch) = self.batch.borrow_mut().take() {
+            let submission = self.gpu.queue.submit(Some(batch.encoder.finish()));
+            // Every batch gets a new cell, only set here
+            batch.submission.set(submission).unwrap();
+        }
+    }
+
+    fn save_texture(&self, texture: &Texture, path: &str) -> Result<()> {
+        self.read_texture(texture)?.save_to_file(path)
+    }
+
+    fn time_stages(&self, faces: &str) {
+        *self.timed_faces.borrow_mut() = Some(faces.to_owned());
+    }
+
+    fn take_device_times(&self) -> Result<Vec<DeviceTime>> {
+        self.flush();
+        let timestamps = std::mem::take(&mut *self.timestamps.borrow_mut());
+        timestamps
+            .into_iter()
+            .map(|timestamps| futures::executor::block_on(timestamps.read(&self.gpu)))
+            .collect()
+    }
+}
+
+/// Pure Rust implementation of the pipeline, parallelized with rayon.
+/// Used where no usable GPU adapter exists (headless CI, software only machines).
+pub struct CpuBackend;
+
+impl PlanetBackend for CpuBackend {
+    type Texture = CpuTexture;
+    type Readback = CpuTexture;
+
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+
+    fn load_texture(&self, path: &str, format: wgpu::TextureFormat, _label: Option<&str>) -> Result<CpuTexture> {
+        CpuTexture::from_file(path, format)
+    }
+
+    fn texture_from_data(
+        &self,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        data: Vec<u8>,
+        _label: Option<&str>,
+    ) -> Result<CpuTexture> {
+        CpuTexture::from_data(width, height, format, data)
+    }
+
+    fn upload_texture(&self, texture: &CpuTexture, _label: Option<&str>) -> Result<CpuTexture> {
+        Ok(texture.clone())
+    }
+
+    fn generate_latlut_tile(&self, face: &str, tile: &FaceTile) -> Result<CpuTexture> {
+        cpu_generate_latlut(face, tile)
+    }
+
+    fn generate_cube_latlut_tile(&self, tile: &FaceTile) -> Result<CpuTexture> {
+        let faces: Result<Vec<CpuTexture>> = CUBEMAP.iter().map(|face| cpu_generate_latlut(face, tile)).collect();
+        CpuTexture::from_layers(faces?)
+    }
+
+    fn generate_geolut(&self, face: &str, width: u32, height: u32) -> Result<CpuTexture> {
+        cpu_generate_geolut(face, width, height)
+    }
+
+    fn generate_slope_tile(
+        &self,
+        heightmap: &CpuTexture,
+        tile: &FaceTile,
+        scale: Option<&TerrainScale>,
+    ) -> Result<CpuTexture> {
+        for_each_layer([heightmap], |[heightmap]| cpu_generate_slope(heightmap, tile, scale))
+    }
+
+    fn generate_normal_tile(
+        &self,
+        heightmap: &CpuTexture,
+        tile: &FaceTile,
+        scale: Option<&TerrainScale>,
+    ) -> Result<CpuTexture> {
+        for_each_layer([heightmap], |[heightmap]| cpu_generate_normal(heightmap, tile, scale))
+    }
+
+    fn generate_material(
+        &self,
+        materialmap: &CpuTexture,
+        heightmap: &CpuTexture,
+        latlut: &CpuTexture,
+        normalmap: &CpuTexture,
+        slopemap: &CpuTexture,
+        materials: &PlanetMaterial,
+        view: &MaterialView,
+    ) -> Result<CpuTexture> {
+        for_each_layer(
+            [materialmap, heightmap, latlut, normalmap, slopemap],
+            |[materialmap, heightmap, latlut, normalmap, slopemap]| {
+                generate_material_cpu(materialmap, heightmap, latlut, normalmap, slopemap, materials, view)
+            },
+        )
+    }
+
+    fn generate_ore_overlay_tile(
+        &self,
+        materialmap: &CpuTexture,
+        tile: &FaceTile,
+        materials: &PlanetMaterial,
+        palette: &OrePalette,
+        style: &OreOverlayStyle,
+    ) -> Result<CpuTexture> {
+        for_each_layer([materialmap], |[materialmap]| {
+            generate_ore_overlay_cpu(materialmap, tile, materials, palette, style)
+        })
+    }
+
+    fn max_texture_size(&self) -> u32 {
+        u32::MAX
+    }
+
+    fn width(&self, texture: &CpuTexture) -> u32 {
+        texture.width()
+    }
+
+    fn height(&self, texture: &CpuTexture) -> u32 {
+        texture.height()
+    }
+
+    fn layers(&self, texture: &CpuTexture) -> u32 {
+        texture.layers()
+    }
+
+    fn start_read(&self, texture: &CpuTexture) -> Result<CpuTexture> {
+        Ok(texture.clone())
+    }
+
+    fn finish_read(&self, readback: CpuTexture) -> Result<CpuTexture> {
+        Ok(readback)
+    }
+
+    // Stages run when they are called
+    fn flush(&self) {}
+
+    fn save_texture(&self, texture: &CpuTexture, path: &str) -> Result<()> {
+        texture.save_to_file(path)
+    }
+}
+
+// Runs a CPU stage on every layer of its inputs, stacking the outputs back into layers
+fn for_each_layer<const N: usize>(
+    inputs: [&CpuTexture; N],
+    stage: impl Fn([&CpuTexture; N]) -> Result<CpuTexture>,
+) -> Result<CpuTexture> {
+    let layers = inputs[0].layers();
+    for input in inputs {
+        check_layers("Stage input", layers, input.layers())?;
+    }
+    if layers == 1 {
+        return stage(inputs);
+    }
+    let outputs: Result<Vec<CpuTexture>> = (0..layers)
+        .map(|layer| stage(inputs.map(|input| input.layer(layer)).each_ref()))
+        .collect();
+    CpuTexture::from_layers(outputs?)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    /// GPU when an adapter is available, CPU otherwise
+    Auto,
+    Cpu,
+    Gpu,
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(BackendKind::Auto),
+            "cpu" => Ok(BackendKind::Cpu),
+            "gpu" => Ok(BackendKind::Gpu),
+            _ => Err(format!("Invalid backend {}, expected auto, cpu or gpu", s)),
+        }
+    }
+}
+
+/// Result of running one stage on both backends with the same inputs.
+#[derive(Debug)]
+pub struct StageParity {
+    pub stage: &'static str,
+    /// Largest difference found in any channel, in 8 bit steps (1/255 for float textures)
+    pub max_difference: u8,
+    /// Texels where some channel differs by more than the tolerance
+    pub mismatched_texels: usize,
+    /// Texels over the tolerance whose inputs already differed between the backends, a step
+    /// of slope or latitude being enough to cross the edge of a material rule. Not mismatches.
+    pub edge_flips: usize,
+    pub texels: usize,
+}
+
+impl StageParity {
+    pub fn passed(&self) -> bool {
+        self.mismatched_texels == 0
+    }
+}
+
+fn compare_textures(
+    stage: &'static str,
+    a: &CpuTexture,
+    b: &CpuTexture,
+    border: u32,
+    tolerance: u8,
+) -> StageParity {
+    compare_chained(stage, a, b, &[], border, tolerance)
+}
+
+// Largest difference between two texels in any channel, in 8 bit steps
+fn texel_difference(a: &CpuTexture, b: &CpuTexture, x: u32, y: u32) -> u8 {
+    a.load(x, y)
+        .iter()
+        .zip(b.load(x, y))
+        .map(|(ca, cb)| ((ca - cb).abs() * 255.0).round().min(255.0) as u8)
+        .max()
+        .unwrap()
+}
+
+// Same as `compare_textures` on outputs each backend made from its own `inputs`, given in
+// pairs. Texels over the tolerance whose inputs differ count as edge flips.
+fn compare_chained(
+    stage: &'static str,
+    a: &CpuTexture,
+    b: &CpuTexture,
+    inputs: &[(&CpuTexture, &CpuTexture)],
+    border: u32,
+    tolerance: u8,
+) -> StageParity {
+    let mut max_difference = 0;
+    let mut mismatched_texels = 0;
+    let mut edge_flips = 0;
+    let mut texels = 0;
+
+    for y in border..a.height() - border {
+        for x in border..a.width() - border {
+            let diff = texel_difference(a, b, x, y);
+            max_difference = max_difference.max(diff);
+            if diff > tolerance {
+                if inputs.iter().any(|(ia, ib)| texel_difference(ia, ib, x, y) > 0) {
+                    edge_flips += 1;
+                } else {
+                    mismatched_texels += 1;
+                }
+            }
+            texels += 1;
+        }
+    }
+
+    StageParity {
+        stage,
+        max_difference,
+        mismatched_texels,
+        edge_flips,
+        texels,
+    }
+}
+
+/// Checks that `lat_lon_to_texel` takes every texel of a geo LUT back to where it came from.
+/// `max_difference` is the farthest it lands, in texels.
+fn check_geolut_inverse(face: &str, geolut: &CpuTexture) -> Result<StageParity> {
+    let face_num = face_to_num(face)?;
+    let (width, height) = (geolut.width(), geolut.height());
+    let mut max_difference = 0;
+    let mut mismatched_texels = 0;
+
+    for y in 0..height {
+        for x in 0..width {
+            let [latitude, longitude, _, _] = geolut.load(x, y);
+            let (found_face, found_x, found_y) = lat_lon_to_texel(latitude, longitude, width, height);
+            let diff = if found_face == face_num {
+                found_x.abs_diff(x).max(found_y.abs_diff(y)).min(255) as u8
+            } else {
+                u8::MAX
+            };
+            max_difference = max_difference.max(diff);
+            if diff > 0 {
+                mismatched_texels += 1;
+            }
+        }
+    }
+
+    Ok(StageParity {
+        stage: "geolut inverse",
+        max_difference,
+        mismatched_texels,
+        edge_flips: 0,
+        texels: (width * height) as usize,
+    })
+}
+
+/// Runs every stage on the CPU and on the GPU with a synthetic heightmap and material map,
+/// and compares the outputs. Each stage gets the CPU output of the previous stages as input,
+/// so a difference is reported only on the stage that introduced it. The chained material
+/// stage is the exception, running on each backend's own slopes and latitudes: texels picking
+/// another rule because of them are reported as edge flips.
+///
+/// The GPU gives back undefined values for out of bounds loads, so the outermost texel ring
+/// is left out of the borderless normal map comparison.
+pub fn compare_backends(
+    gpu: &GpuBackend,
+    face: &str,
+    size: u32,
+    materials: &PlanetMaterial,
+    tolerance: u8,
+) -> Result<Vec<StageParity>> {
+    let cpu = CpuBackend;
+
+    let mut ids: Vec<u32> = materials
+        .complex_materials_to_gpu(&MaterialView::default())
+        .iter()
+        .chain(materials.simple_materials_to_gpu()?.iter())
+        .map(|rule| rule.id)
+        .filter(|id| *id < 256)
+        .collect();
+    ids.push(0);
+    let mut ores: Vec<u32> = materials.ores.values().filter_map(|ore| ore.value).collect();
+    ores.push(0);
+
+    let height_at = |x: i32, y: i32| {
+        let (fx, fy) = (x as f32 / size as f32, y as f32 / size as f32);
+        0.5 + 0.3 * (fx * 17.0).sin() * (fy * 11.0).cos() + 0.1 * (fx * fy * 40.0).sin()
+    };
+    // Same heights with a one texel border, standing in for the adjacent faces
+    let bordered_heights: Vec<f32> = (-1..size as i32 + 1)
+        .flat_map(|y| (-1..size as i32 + 1).map(move |x| (x, y)))
+        .map(|(x, y)| height_at(x, y))
+        .collect();
+
+    let mut heights: Vec<f32> = Vec::with_capacity((size * size) as usize);
+    let mut material_texels: Vec<u8> = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            heights.push(height_at(x as i32, y as i32));
+            let id = ids[((x / 16 + y / 16) as usize) % ids.len()];
+            let ore = ores[((x / 8 + y / 24) as usize) % ores.len()];
+            material_texels.extend_from_slice(&[id as u8, 0, ore as u8, 255]);
+        }
+    }
+    let height_texels: Vec<u8> = bytemuck::cast_slice(heights.as_slice()).to_vec();
+    let bordered_texels: Vec<u8> = bytemuck::cast_slice(bordered_heights.as_slice()).to_vec();
+
+    let hm_format = wgpu::TextureFormat::R32Float;
+    let mat_format = wgpu::TextureFormat::Rgba8Unorm;
+    let cpu_heightmap = cpu.texture_from_data(size, size, hm_format, height_texels.clone(), None)?;
+    let gpu_heightmap = gpu.texture_from_data(size, size, hm_format, height_texels, Some("HeightMap"))?;
+    let bordered_size = size + 2;
+    let cpu_bordered =
+        cpu.texture_from_data(bordered_size, bordered_size, hm_format, bordered_texels.clone(), None)?;
+    let gpu_bordered =
+        gpu.texture_from_data(bordered_size, bordered_size, hm_format, bordered_texels, Some("HeightMap"))?;
+    let cpu_materialmap = cpu.texture_from_data(size, size, mat_format, material_texels.clone(), None)?;
+    let gpu_materialmap =
+        gpu.texture_from_data(size, size, mat_format, material_texels, Some("MaterialMap"))?;
+
+    let cpu_latlut = cpu.generate_latlut(face, size, size)?;
+    let gpu_latlut = gpu.generate_latlut(face, size, size)?;
+    let cpu_geolut = cpu.generate_geolut(face, size, size)?;
+    // OpenGL adapters can't store the geolut's format, the other stages are still compared
+    let gpu_geolut = match gpu.generate_geolut(face, size, size) {
+        Ok(geolut) => Some(geolut),
+        Err(Error::UnsupportedFormat(e)) => {
+            println!("Skipping the geolut stage, unsupported {}", e);
+            None
+        }
+        Err(e) => return Err(e),
+    };
+    let cpu_slope = cpu.generate_slope(&cpu_heightmap, 0, None)?;
+    let gpu_slope = gpu.generate_slope(&gpu_heightmap, 0, None)?;
+    let cpu_normal = cpu.generate_normal(&cpu_heightmap, 0, None)?;
+    let gpu_normal = gpu.generate_normal(&gpu_heightmap, 0, None)?;
+    // Earth-like planet, with seams and a scale so every path is covered
+    let scale = Some(TerrainScale {
+        radius: 60000.0,
+        hill_min: -0.1,
+        hill_max: 0.12,
+    });
+    let cpu_seamless_slope = cpu.generate_slope(&cpu_bordered, 1, scale.as_ref())?;
+    let gpu_seamless_slope = gpu.generate_slope(&gpu_bordered, 1, scale.as_ref())?;
+    let cpu_seamless_normal = cpu.generate_normal(&cpu_bordered, 1, scale.as_ref())?;
+    let gpu_seamless_normal = gpu.generate_normal(&gpu_bordered, 1, scale.as_ref())?;
+
+    let mut results = vec![
+        compare_textures("latlut", &cpu_latlut, &gpu.read_texture(&gpu_latlut)?, 0, tolerance),
+        check_geolut_inverse(face, &cpu_geolut)?,
+        compare_textures("slope", &cpu_slope, &gpu.read_texture(&gpu_slope)?, 0, tolerance),
+        compare_textures("normal", &cpu_normal, &gpu.read_texture(&gpu_normal)?, 1, tolerance),
+        compare_textures(
+            "seamless slope",
+            &cpu_seamless_slope,
+            &gpu.read_texture(&gpu_seamless_slope)?,
+            0,
+            tolerance,
+        ),
+        compare_textures(
+            "seamless normal",
+            &cpu_seamless_normal,
+            &gpu.read_texture(&gpu_seamless_normal)?,
+            0,
+            tolerance,
+        ),
+    ];
+
+    if let Some(gpu_geolut) = &gpu_geolut {
+        let geolut = compare_textures("geolut", &cpu_geolut, &gpu.read_texture(gpu_geolut)?, 0, tolerance);
+        results.insert(1, geolut);
+    }
+
+    let upload = |texture: &CpuTexture| {
+        gpu.texture_from_data(
+            texture.width(),
+            texture.height(),
+            texture.format(),
+            texture.data().to_vec(),
+            None,
+        )
+    };
+    // The same stages in tiles, stitched back together, have to match the single pass
+    let tiles = split_face(size, size, size / 2 + 2, false, 0)?;
+    let tiled = |reference: &CpuTexture,
+                 stage: &dyn Fn(&Texture, &FaceTile) -> Result<Texture>|
+     -> Result<CpuTexture> {
+        let mut output = CpuTexture::new(size, size, reference.format())?;
+        for tile in &tiles {
+            let input = upload(&tile_input(std::slice::from_ref(&cpu_heightmap), 0, tile)?)?;
+            output.paste(&gpu.read_texture(&stage(&input, tile)?)?, tile.x, tile.y)?;
+        }
+        Ok(output)
+    };
+    let cpu_scaled_normal = cpu.generate_normal(&cpu_heightmap, 0, scale.as_ref())?;
+    let tiled_slope = tiled(&cpu_slope, &|heightmap, tile| gpu.generate_slope_tile(heightmap, tile, None))?;
+    let tiled_normal = tiled(&cpu_scaled_normal, &|heightmap, tile| {
+        gpu.generate_normal_tile(heightmap, tile, scale.as_ref())
+    })?;
+    results.push(compare_textures("tiled slope", &cpu_slope, &tiled_slope, 0, tolerance));
+    results.push(compare_textures("tiled normal", &cpu_scaled_normal, &tiled_normal, 1, tolerance));
+    // Dig in and wear steep slopes so the layer picking is covered too
+    let hard = MaterialView {
+        depth: 2.0,
+        exposed_slope: Some(30.0),
+        blend: None,
+    };
+    let blended = MaterialView {
+        blend: Some(MaterialBlend::from_transition(10.0, 0.02)),
+        ..MaterialView::default()
+    };
+    let uploaded_latlut = upload(&cpu_latlut)?;
+    let uploaded_normal = upload(&cpu_normal)?;
+    let uploaded_slope = upload(&cpu_slope)?;
+    for (stage, view) in [("material", &hard), ("blended material", &blended)] {
+        let cpu_material = cpu.generate_material(
+            &cpu_materialmap,
+            &cpu_heightmap,
+            &cpu_latlut,
+            &cpu_normal,
+            &cpu_slope,
+            materials,
+            view,
+        )?;
+        let gpu_material = gpu.generate_material(
+            &gpu_materialmap,
+            &gpu_heightmap,
+            &uploaded_latlut,
+            &uploaded_normal,
+            &uploaded_slope,
+            materials,
+            view,
+        )?;
+        results.push(compare_textures(
+            stage,
+            &cpu_material,
+            &gpu.read_texture(&gpu_material)?,
+            0,
+            tolerance,
+        ));
+    }
+
+    // The material stage on the slopes and latitudes each backend made itself, as `generate`
+    // runs it. Those can be a step apart, picking another rule on its edges.
+    let cpu_chained = cpu.generate_material(
+        &cpu_materialmap,
+        &cpu_heightmap,
+        &cpu_latlut,
+        &cpu_normal,
+        &cpu_slope,
+        materials,
+        &hard,
+    )?;
+    let gpu_chained = gpu.generate_material(
+        &gpu_materialmap,
+        &gpu_heightmap,
+        &gpu_latlut,
+        &gpu_normal,
+        &gpu_slope,
+        materials,
+        &hard,
+    )?;
+    let chained_inputs = [
+        (&cpu_latlut, &gpu.read_texture(&gpu_latlut)?),
+        (&cpu_slope, &gpu.read_texture(&gpu_slope)?),
+    ];
+    results.push(compare_chained(
+        "chained material",
+        &cpu_chained,
+        &gpu.read_texture(&gpu_chained)?,
+        &chained_inputs,
+        0,
+        tolerance,
+    ));
+
+    // Texels on the edges of the rules, and a step to either side, where a slope or latitude
+    // rounded differently picks another layer
+    let rules = materials.complex_materials_to_gpu(&hard);
+    let rules: Vec<&GPUMaterialRule> = rules.iter().filter(|rule| rule.id < 256).collect();
+    if !rules.is_empty() {
+        let edge_texel = |x: u32, y: u32| {
+            let i = (y * size + x) as usize;
+            let rule = rules[i % rules.len()];
+            let side = (i / rules.len()) % 2;
+            let middle = |range: [f32; 2]| (range[0] + range[1]) * 0.5;
+            let (mut h, mut lat, mut slope) = (middle(rule.height), middle(rule.latitude), middle(rule.slope));
+            match (i / rules.len() / 2) % 3 {
+                0 => h = rule.height[side],
+                1 => lat = rule.latitude[side],
+                _ => slope = rule.slope[side],
+            }
+            let nudge = ((i / rules.len() / 6) % 3) as f32 - 1.0;
+            let step = |degrees: f32| ((degrees / 90.0 * 255.0).round() + nudge).clamp(0.0, 255.0) / 255.0;
+            (rule.id, h, step(lat), step(slope))
+        };
+        let edge_texture = |format, texel: &(dyn Fn(u32, u32) -> [f32; 4] + Sync)| -> Result<CpuTexture> {
+            let mut texture = CpuTexture::new(size, size, format)?;
+            texture.fill(texel);
+            Ok(texture)
+        };
+        let edge_materialmap = edge_texture(mat_format, &|x, y| [edge_texel(x, y).0 as f32 / 255.0, 0.0, 0.0, 1.0])?;
+        let edge_heightmap = edge_texture(hm_format, &|x, y| [edge_texel(x, y).1, 0.0, 0.0, 0.0])?;
+        let edge_latlut = edge_texture(mat_format, &|x, y| {
+            let lat = edge_texel(x, y).2;
+            [lat, lat, lat, 1.0]
+        })?;
+        let edge_slope = edge_texture(mat_format, &|x, y| [edge_texel(x, y).3, 0.0, 0.0, 1.0])?;
+        let cpu_material = cpu.generate_material(
+            &edge_materialmap,
+            &edge_heightmap,
+            &edge_latlut,
+            &cpu_normal,
+            &edge_slope,
+            materials,
+            &hard,
+        )?;
+        let gpu_material = gpu.generate_material(
+            &upload(&edge_materialmap)?,
+            &upload(&edge_heightmap)?,
+            &upload(&edge_latlut)?,
+            &uploaded_normal,
+            &upload(&edge_slope)?,
+            materials,
+            &hard,
+        )?;
+        results.push(compare_textures(
+            "material on rule edges",
+            &cpu_material,
+            &gpu.read_texture(&gpu_material)?,
+            0,
+            0,
+        ));
+    }
+
+    let palette = OrePalette::default();
+    let style = OreOverlayStyle { outline: true, glow: 3 };
+    let cpu_ores = cpu.generate_ore_overlay(&cpu_materialmap, materials, &palette, &style)?;
+    let gpu_ores = gpu.generate_ore_overlay(&gpu_materialmap, materials, &palette, &style)?;
+    results.push(compare_textures("ore overlay", &cpu_ores, &gpu.read_texture(&gpu_ores)?, 0, tolerance));
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        gpu::gpu::{open, AdapterOptions},
+        spacelab::matcolormap::test_planet,
+    };
+
+    use super::*;
+
+    #[test]
+    fn backends_agree_on_every_stage() {
+        // Software rasterizers are fine here, only the results are compared
+        let options = AdapterOptions {
+            allow_software: true,
+            ..AdapterOptions::default()
+        };
+        let gpu = match futures::executor::block_on(open(&options)) {
+            Ok(gpu) => GpuBackend::new(gpu).unwrap(),
+            Err(Error::NoAdapter(e)) => {
+                println!("Skipping the backend parity test, {}", e);
+                return;
+            }
+            Err(e) => panic!("{}", e),
+        };
+
+        let planet = test_planet();
+        for face in CUBEMAP {
+            let stages = compare_backends(&gpu, face, 64, &planet, 1).unwrap();
+            assert!(stages.len() >= 10, "{:?}", stages);
+            for stage in stages {
+                assert!(stage.passed(), "{} {}: {:?}", face, stage.stage, stage);
+            }
+        }
+    }
+
+    #[test]
+    fn compare_textures_counts_texels_over_tolerance() {
+        let mut a = CpuTexture::new(4, 4, wgpu::TextureFormat::Rgba8Unorm).unwrap();
+        a.fill(|_, _| [0.5, 0.5, 0.5, 1.0]);
+        let mut b = a.clone();
+        b.fill(|x, y| match (x, y) {
+            (0, 0) => [0.5 + 3.0 / 255.0, 0.5, 0.5, 1.0],
+            (2, 1) => [0.5, 0.5 - 1.0 / 255.0, 0.5, 1.0],
+            _ => [0.5, 0.5, 0.5, 1.0],
+        });
+
+        let parity = compare_textures("test", &a, &b, 0, 1);
+        assert_eq!((parity.max_difference, parity.mismatched_texels, parity.texels), (3, 1, 16));
+        assert!(!parity.passed());
+        // The border ring, where (0, 0) is, can be left out
+        let inner = compare_textures("test", &a, &b, 1, 0);
+        assert_eq!((inner.max_difference, inner.mismatched_texels, inner.texels), (1, 1, 4));
+    }
+
+    #[test]
+    fn differences_with_differing_inputs_are_edge_flips() {
+        let mut a = CpuTexture::new(4, 4, wgpu::TextureFormat::Rgba8Unorm).unwrap();
+        a.fill(|_, _| [0.5, 0.5, 0.5, 1.0]);
+        let mut b = a.clone();
+        b.fill(|x, y| match (x, y) {
+            (0, 0) | (3, 2) => [0.2, 0.5, 0.5, 1.0],
+            _ => [0.5, 0.5, 0.5, 1.0],
+        });
+        // Only the input of (0, 0) is a step apart
+        let input_a = a.clone();
+        let mut input_b = a.clone();
+        input_b.fill(|x, y| match (x, y) {
+            (0, 0) => [0.5 + 1.0 / 255.0, 0.5, 0.5, 1.0],
+            _ => [0.5, 0.5, 0.5, 1.0],
+        });
+
+        let parity = compare_chained("test", &a, &b, &[(&input_a, &input_b)], 0, 1);
+        assert_eq!((parity.max_difference, parity.mismatched_texels, parity.edge_flips), (77, 1, 1));
+        assert!(!parity.passed());
+    }
+}
@@ -0,0 +1,258 @@
+// BC1, BC3 and BC5 block compression of RGBA8 images, for the cube map exporters
+
+/// Block compressed formats. Every format stores 4x4 texel blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockFormat {
+    /// Opaque RGB, 8 bytes per block
+    Bc1,
+    /// RGB with alpha, 16 bytes per block
+    Bc3,
+    /// Two channels (red and green), 16 bytes per block, for normal maps
+    Bc5,
+}
+
+impl BlockFormat {
+    pub fn block_size(&self) -> usize {
+        match self {
+            BlockFormat::Bc1 => 8,
+            BlockFormat::Bc3 | BlockFormat::Bc5 => 16,
+        }
+    }
+}
+
+/// Compresses a `width` x `height` RGBA8 image. Blocks running past the edge of the image
+/// repeat its last row and column, so the image must not be empty.
+pub fn compress(format: BlockFormat, width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let (blocks_x, blocks_y) = (width.div_ceil(4), height.div_ceil(4));
+    let mut out = Vec::with_capacity(blocks_x * blocks_y * format.block_size());
+
+    for by in 0..blocks_y {
+        for bx in 0..blocks_x {
+            let mut block = [[0u8; 4]; 16];
+            for (i, texel) in block.iter_mut().enumerate() {
+                let x = (bx * 4 + i % 4).min(width - 1);
+                let y = (by * 4 + i / 4).min(height - 1);
+                let offset = (y * width + x) * 4;
+                texel.copy_from_slice(&rgba[offset..offset + 4]);
+            }
+
+            match format {
+                BlockFormat::Bc1 => out.extend_from_slice(&encode_color_block(&block)),
+                BlockFormat::Bc3 => {
+                    out.extend_from_slice(&encode_channel_block(&block.map(|t| t[3])));
+                    out.extend_from_slice(&encode_color_block(&block));
+                }
+                BlockFormat::Bc5 => {
+                    out.extend_from_slice(&encode_channel_block(&block.map(|t| t[0])));
+                    out.extend_from_slice(&encode_channel_block(&block.map(|t| t[1])));
+                }
+            }
+        }
+    }
+    out
+}
+
+fn to_565(c: [f32; 3]) -> u16 {
+    let r = (c[0].clamp(0.0, 255.0) * 31.0 / 255.0).round() as u16;
+    let g = (c[1].clamp(0.0, 255.0) * 63.0 / 255.0).round() as u16;
+    let b = (c[2].clamp(0.0, 255.0) * 31.0 / 255.0).round() as u16;
+    (r << 11) | (g << 5) | b
+}
+
+fn from_565(c: u16) -> [f32; 3] {
+    let r = ((c >> 11) & 31) as f32 * 255.0 / 31.0;
+    let g = ((c >> 5) & 63) as f32 * 255.0 / 63.0;
+    let b = (c & 31) as f32 * 255.0 / 31.0;
+    [r, g, b]
+}
+
+fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]) * (a[c] - b[c])).sum()
+}
+
+// BC1 color block, endpoints on the principal axis of the block colors
+fn encode_color_block(block: &[[u8; 4]; 16]) -> [u8; 8] {
+    let colors = block.map(|t| [t[0] as f32, t[1] as f32, t[2] as f32]);
+    let mut mean = [0.0; 3];
+    for color in &colors {
+        for c in 0..3 {
+            mean[c] += color[c] / 16.0;
+        }
+    }
+
+    let mut covariance = [[0.0f32; 3]; 3];
+    for color in &colors {
+        let d = [color[0] - mean[0], color[1] - mean[1], color[2] - mean[2]];
+        for i in 0..3 {
+            for j in 0..3 {
+                covariance[i][j] += d[i] * d[j];
+            }
+        }
+    }
+    // A few rounds of power iteration are enough to find the axis. Starting from the channel
+    // that varies most, a fixed start like gray would miss axes orthogonal to it (red to blue).
+    let widest = (0..3).max_by(|a, b| covariance[*a][*a].total_cmp(&covariance[*b][*b])).unwrap();
+    let mut axis = covariance[widest];
+    for _ in 0..8 {
+        let next = [0, 1, 2].map(|i| (0..3).map(|j| covariance[i][j] * axis[j]).sum::<f32>());
+        let length = distance(next, [0.0; 3]).sqrt();
+        if length < 1e-6 {
+            break;
+        }
+        axis = next.map(|c| c / length);
+    }
+
+    let project = |color: &[f32; 3]| (0..3).map(|c| (color[c] - mean[c]) * axis[c]).sum::<f32>();
+    let (min, max) = colors
+        .iter()
+        .map(project)
+        .fold((f32::MAX, f32::MIN), |(min, max), t| (min.min(t), max.max(t)));
+    // Pull the endpoints in a little, the interpolated colors cover the ends well enough
+    let inset = (max - min) / 16.0;
+    let endpoint = |t: f32| [0, 1, 2].map(|c| mean[c] + axis[c] * t);
+    let mut c0 = to_565(endpoint(max - inset));
+    let mut c1 = to_565(endpoint(min + inset));
+    if c0 < c1 {
+        std::mem::swap(&mut c0, &mut c1);
+    }
+
+    let mut indices = 0u32;
+    if c0 != c1 {
+        let (e0, e1) = (from_565(c0), from_565(c1));
+        let palette = [
+            e0,
+            e1,
+            [0, 1, 2].map(|c| (2.0 * e0[c] + e1[c]) / 3.0),
+            [0, 1, 2].map(|c| (e0[c] + 2.0 * e1[c]) / 3.0),
+        ];
+        for (i, color) in colors.iter().enumerate() {
+            let index = (0..4)
+                .min_by(|a, b| distance(*color, palette[*a]).total_cmp(&distance(*color, palette[*b])))
+                .unwrap();
+            indices |= (index as u32) << (i * 2);
+        }
+    }
+
+    let mut out = [0u8; 8];
+    out[0..2].copy_from_slice(&c0.to_le_bytes());
+    out[2..4].copy_from_slice(&c1.to_le_bytes());
+    out[4..8].copy_from_slice(&indices.to_le_bytes());
+    out
+}
+
+// BC4 block of a single channel, used for the BC3 alpha and both BC5 channels
+fn encode_channel_block(values: &[u8; 16]) -> [u8; 8] {
+    let a0 = *values.iter().max().unwrap();
+    let a1 = *values.iter().min().unwrap();
+
+    let mut indices = 0u64;
+    if a0 != a1 {
+        // Index 0 and 1 are the endpoints, 2..7 the six steps between them
+        let mut palette = [a0 as f32, a1 as f32, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        for (i, value) in palette.iter_mut().enumerate().skip(2) {
+            *value = ((8 - i) as f32 * a0 as f32 + (i - 1) as f32 * a1 as f32) / 7.0;
+        }
+        for (i, value) in values.iter().enumerate() {
+            let value = *value as f32;
+            let index = (0..8)
+                .min_by(|a, b| (value - palette[*a]).abs().total_cmp(&(value - palette[*b]).abs()))
+                .unwrap();
+            indices |= (index as u64) << (i * 3);
+        }
+    }
+
+    let mut out = [0u8; 8];
+    out[0] = a0;
+    out[1] = a1;
+    out[2..8].copy_from_slice(&indices.to_le_bytes()[0..6]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_color_block(block: &[u8]) -> [[u8; 3]; 16] {
+        let c0 = u16::from_le_bytes([block[0], block[1]]);
+        let c1 = u16::from_le_bytes([block[2], block[3]]);
+        let (e0, e1) = (from_565(c0), from_565(c1));
+        let palette = if c0 > c1 {
+            [
+                e0,
+                e1,
+                [0, 1, 2].map(|c| (2.0 * e0[c] + e1[c]) / 3.0),
+                [0, 1, 2].map(|c| (e0[c] + 2.0 * e1[c]) / 3.0),
+            ]
+        } else {
+            [e0, e1, [0, 1, 2].map(|c| (e0[c] + e1[c]) / 2.0), [0.0; 3]]
+        };
+        let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+        std::array::from_fn(|i| palette[(indices >> (i * 2)) as usize & 3].map(|c| c.round() as u8))
+    }
+
+    fn decode_channel_block(block: &[u8]) -> [u8; 16] {
+        let (a0, a1) = (block[0] as f32, block[1] as f32);
+        let mut palette = [a0, a1, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        for (i, value) in palette.iter_mut().enumerate().skip(2) {
+            *value = if a0 > a1 {
+                ((8 - i) as f32 * a0 + (i - 1) as f32 * a1) / 7.0
+            } else if i < 6 {
+                ((6 - i) as f32 * a0 + (i - 1) as f32 * a1) / 5.0
+            } else if i == 6 {
+                0.0
+            } else {
+                255.0
+            };
+        }
+        let mut bits = [0u8; 8];
+        bits[0..6].copy_from_slice(&block[2..8]);
+        let indices = u64::from_le_bytes(bits);
+        std::array::from_fn(|i| palette[(indices >> (i * 3)) as usize & 7].round() as u8)
+    }
+
+    // 4x4 RGBA8 image, the left half `a` and the right half `b`
+    fn halves(a: [u8; 4], b: [u8; 4]) -> Vec<u8> {
+        (0..16).flat_map(|i| if i % 4 < 2 { a } else { b }).collect()
+    }
+
+    #[test]
+    fn bc1_round_trips_solid_and_two_color_blocks() {
+        // Colors 5:6:5 stores exactly
+        let (red, blue) = ([255, 0, 0, 255], [0, 0, 255, 255]);
+        let solid = compress(BlockFormat::Bc1, 4, 4, &halves(red, red));
+        assert_eq!(solid.len(), 8);
+        assert_eq!(decode_color_block(&solid), [[255, 0, 0]; 16]);
+
+        let decoded = decode_color_block(&compress(BlockFormat::Bc1, 4, 4, &halves(red, blue)));
+        for (i, texel) in decoded.iter().enumerate() {
+            let expected: [u8; 3] = if i % 4 < 2 { [255, 0, 0] } else { [0, 0, 255] };
+            let error: i32 = (0..3).map(|c| (texel[c] as i32 - expected[c] as i32).abs()).max().unwrap();
+            // The endpoints are pulled in a sixteenth of the way
+            assert!(error <= 24, "texel {} is {:?}, expected {:?}", i, texel, expected);
+            assert_eq!(texel[0] > texel[2], i % 4 < 2);
+        }
+    }
+
+    #[test]
+    fn bc4_round_trips_solid_and_two_value_blocks() {
+        let solid = compress(BlockFormat::Bc5, 4, 4, &halves([77, 200, 0, 0], [77, 200, 0, 0]));
+        assert_eq!(solid.len(), 16);
+        assert_eq!(decode_channel_block(&solid[0..8]), [77; 16]);
+        assert_eq!(decode_channel_block(&solid[8..16]), [200; 16]);
+
+        let two = compress(BlockFormat::Bc3, 4, 4, &halves([0, 0, 0, 10], [0, 0, 0, 250]));
+        let expected: Vec<u8> = (0..16).map(|i| if i % 4 < 2 { 10 } else { 250 }).collect();
+        assert_eq!(decode_channel_block(&two[0..8]).to_vec(), expected);
+    }
+
+    #[test]
+    fn partial_blocks_repeat_the_edge() {
+        let rgba: Vec<u8> = [[0, 0, 0, 0], [0, 0, 0, 255]].concat();
+        let block = compress(BlockFormat::Bc3, 2, 1, &rgba);
+        assert_eq!(block.len(), 16);
+        let alpha = decode_channel_block(&block[0..8]);
+        let expected: Vec<u8> = (0..16).map(|i| if i % 4 == 0 { 0 } else { 255 }).collect();
+        assert_eq!(alpha.to_vec(), expected);
+    }
+}
@@ -0,0 +1,183 @@
+// Content hashes of the inputs of every generated file, so unchanged outputs aren't generated again
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+
+/// Name of the manifest written in every planet output folder.
+pub const MANIFEST_FILE_NAME: &str = "buildcache.json";
+
+// Everything the backends run: the shaders of the GPU backend and their CPU ports
+const STAGES: [&str; 11] = [
+    include_str!("compute_normal.wgsl"),
+    include_str!("geolutgen.wgsl"),
+    include_str!("latlutgen.wgsl"),
+    include_str!("materialgen.wgsl"),
+    include_str!("oregen.wgsl"),
+    include_str!("slopegen.wgsl"),
+    include_str!("lutgen_cpu.rs"),
+    include_str!("material_cpu.rs"),
+    include_str!("normal_cpu.rs"),
+    include_str!("oreoverlay_cpu.rs"),
+    include_str!("../cpu/texture.rs"),
+];
+
+/// 64 bit FNV-1a hash. Not cryptographic, only meant to notice edited inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentHash(u64);
+
+impl Default for ContentHash {
+    fn default() -> Self {
+        ContentHash(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl ContentHash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hashes `bytes` preceded by their length, so consecutive updates can't run into
+    /// each other.
+    pub fn update(&mut self, bytes: &[u8]) -> &mut Self {
+        for byte in (bytes.len() as u64).to_le_bytes().iter().chain(bytes) {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+        self
+    }
+
+    pub fn update_str(&mut self, s: &str) -> &mut Self {
+        self.update(s.as_bytes())
+    }
+
+    pub fn update_file(&mut self, path: &Path) -> Result<&mut Self> {
+        Ok(self.update(&std::fs::read(path)?))
+    }
+
+    pub fn hex(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+/// Hash of the crate version and the stages of both backends, changing whenever the
+/// generated images might.
+pub fn stage_version() -> String {
+    let mut hash = ContentHash::new();
+    hash.update_str(env!("CARGO_PKG_VERSION"));
+    for stage in STAGES {
+        hash.update_str(stage);
+    }
+    hash.hex()
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    stage_version: String,
+    /// Output file name to the key of the inputs it was generated from
+    outputs: BTreeMap<String, String>,
+}
+
+/// The manifest of one output folder. Outputs are fresh when they exist and were generated
+/// with the same key, a hash of everything they are computed from.
+#[derive(Debug)]
+pub struct BuildCache {
+    path: PathBuf,
+    manifest: Manifest,
+    force: bool,
+}
+
+impl BuildCache {
+    /// Reads the manifest of `folder`. A missing or unreadable manifest is an empty cache,
+    /// and `force` treats every output as stale.
+    pub fn open(folder: &Path, force: bool) -> Self {
+        let path = folder.join(MANIFEST_FILE_NAME);
+        let manifest = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str::<Manifest>(&contents).ok())
+            .filter(|manifest| manifest.stage_version == stage_version())
+            .unwrap_or_else(|| Manifest {
+                stage_version: stage_version(),
+                outputs: BTreeMap::new(),
+            });
+        BuildCache { path, manifest, force }
+    }
+
+    fn entry_name(output: &Path) -> String {
+        output.file_name().unwrap_or_default().to_string_lossy().into_owned()
+    }
+
+    pub fn is_fresh(&self, output: &Path, key: &str) -> bool {
+        !self.force
+            && output.exists()
+            && self.manifest.outputs.get(&Self::entry_name(output)).map(String::as_str) == Some(key)
+    }
+
+    /// Remembers `output` was just generated from `key`.
+    pub fn record(&mut self, output: &Path, key: &str) {
+        self.manifest.outputs.insert(Self::entry_name(output), key.to_owned());
+    }
+
+    pub fn save(&self) -> Result<()> {
+        std::fs::write(&self.path, serde_json::to_string_pretty(&self.manifest)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("nextgen-buildcache-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    #[test]
+    fn content_hash_is_stable() {
+        assert_eq!(ContentHash::new().hex(), "cbf29ce484222325");
+        assert_eq!(ContentHash::new().update_str("abc").hex(), "c11ab6d2519bc2b2");
+        assert_eq!(ContentHash::new().update(b"abc").hex(), ContentHash::new().update_str("abc").hex());
+        // The length prefix keeps split inputs apart
+        assert_ne!(
+            ContentHash::new().update_str("ab").update_str("c").hex(),
+            ContentHash::new().update_str("a").update_str("bc").hex()
+        );
+    }
+
+    #[test]
+    fn changed_inputs_invalidate_outputs() {
+        let folder = temp_folder("invalidate");
+        let output = folder.join("front.png");
+        std::fs::write(&output, b"image").unwrap();
+        let key = |input: &str| ContentHash::new().update_str(input).hex();
+
+        let mut cache = BuildCache::open(&folder, false);
+        assert!(!cache.is_fresh(&output, &key("heightmap")));
+        cache.record(&output, &key("heightmap"));
+        cache.save().unwrap();
+
+        let cache = BuildCache::open(&folder, false);
+        assert!(cache.is_fresh(&output, &key("heightmap")));
+        assert!(!cache.is_fresh(&output, &key("edited heightmap")));
+        assert!(!BuildCache::open(&folder, true).is_fresh(&output, &key("heightmap")));
+
+        // A manifest written by other stages is dropped
+        let stale = Manifest {
+            stage_version: "old".to_owned(),
+            outputs: cache.manifest.outputs.clone(),
+        };
+        std::fs::write(folder.join(MANIFEST_FILE_NAME), serde_json::to_string(&stale).unwrap()).unwrap();
+        assert!(!BuildCache::open(&folder, false).is_fresh(&output, &key("heightmap")));
+
+        std::fs::remove_file(&output).unwrap();
+        assert!(!cache.is_fresh(&output, &key("heightmap")));
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
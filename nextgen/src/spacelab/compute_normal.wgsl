@@ -1,21 +1,69 @@
 
-@group(0) @binding(0) var height_map: texture_2d<f32>;
-@group(0) @binding(1) var texture: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(0) var height_map: texture_2d_array<f32>;
+@group(0) @binding(1) var texture: texture_storage_2d_array<rgba8unorm, write>;
+
+struct TerrainScale {
+    physical: u32,
+    // Metres between heightmap values 0 and 1
+    height_range: f32,
+    radius: f32,
+    pad: f32,
+};
+
+@group(0) @binding(2) var<uniform> scale: TerrainScale;
+
+// Metres covered by a texel of the face along x and y. The cube projection squeezes
+// texels toward the face edges.
+fn texel_size(x: i32, y: i32, size: vec2<u32>) -> vec2<f32> {
+    let u = (f32(x) + 0.5) / f32(size.x) * 2.0 - 1.0;
+    let v = (f32(y) + 0.5) / f32(size.y) * 2.0 - 1.0;
+    let d = 1.0 + u * u + v * v;
+    return vec2<f32>(
+        scale.radius * 2.0 / f32(size.x) * sqrt(1.0 + v * v) / d,
+        scale.radius * 2.0 / f32(size.y) * sqrt(1.0 + u * u) / d,
+    );
+}
+
+// Where the output sits on the face, see `tiling::FaceTile`
+struct FaceTile {
+    origin: vec2<u32>,
+    face_size: vec2<u32>,
+    // Texels of the height map around the output
+    halo: u32,
+    // The halo holds the adjacent faces, rather than wrapping around the face
+    seamless: u32,
+};
+
+@group(0) @binding(3) var<uniform> tile: FaceTile;
+
+// Height of a texel of the face in a layer of the height map. Without the adjacent faces,
+// the face edges are clamped to.
+fn height_at(p: vec2<i32>, layer: i32) -> f32 {
+    var texel = p;
+    if (tile.seamless == 0u) {
+        texel = clamp(texel, vec2<i32>(0, 0), vec2<i32>(tile.face_size) - vec2<i32>(1, 1));
+    }
+    let halo = i32(tile.halo);
+    return abs(textureLoad(height_map, texel - vec2<i32>(tile.origin) + vec2<i32>(halo, halo), layer, 0).r);
+}
 
 @compute
 @workgroup_size(8,8)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let coord: vec2<i32> = vec2<i32>(global_id.xy);
+    let center: vec2<i32> = coord + vec2<i32>(tile.origin);
+    // Every layer is a face of its own
+    let layer = i32(global_id.z);
 
     // Sampling around the current texel for Sobel filter
-    let tl: f32 = abs(textureLoad(height_map, coord + vec2<i32>(-1, -1), 0).r);
-    let  l: f32 = abs(textureLoad(height_map, coord + vec2<i32>(-1,  0), 0).r);
-    let bl: f32 = abs(textureLoad(height_map, coord + vec2<i32>(-1,  1), 0).r);
-    let  t: f32 = abs(textureLoad(height_map, coord + vec2<i32>( 0, -1), 0).r);
-    let  b: f32 = abs(textureLoad(height_map, coord + vec2<i32>( 0,  1), 0).r);
-    let tr: f32 = abs(textureLoad(height_map, coord + vec2<i32>( 1, -1), 0).r);
-    let  r: f32 = abs(textureLoad(height_map, coord + vec2<i32>( 1,  0), 0).r);
-    let br: f32 = abs(textureLoad(height_map, coord + vec2<i32>( 1,  1), 0).r);
+    let tl: f32 = height_at(center + vec2<i32>(-1, -1), layer);
+    let  l: f32 = height_at(center + vec2<i32>(-1,  0), layer);
+    let bl: f32 = height_at(center + vec2<i32>(-1,  1), layer);
+    let  t: f32 = height_at(center + vec2<i32>( 0, -1), layer);
+    let  b: f32 = height_at(center + vec2<i32>( 0,  1), layer);
+    let tr: f32 = height_at(center + vec2<i32>( 1, -1), layer);
+    let  r: f32 = height_at(center + vec2<i32>( 1,  0), layer);
+    let br: f32 = height_at(center + vec2<i32>( 1,  1), layer);
 
     // Compute dx using Sobel
     let dX: f32 = tr + 2.0 * r + br - tl - 2.0 * l - bl;
@@ -24,13 +72,20 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let dY: f32 = bl + 2.0 * b + br - tl - 2.0 * t - tr;
 
     // Build the normalized normal
-    let N: vec3<f32> = normalize(vec3<f32>(dX, dY, 1.0));
+    var N: vec3<f32> = normalize(vec3<f32>(dX, dY, 1.0));
+    if (scale.physical != 0u) {
+        // Sobel sums 8 times the height step of one texel
+        let metres = texel_size(center.x, center.y, tile.face_size);
+        let gx = dX / 8.0 * scale.height_range / metres.x;
+        let gy = dY / 8.0 * scale.height_range / metres.y;
+        N = normalize(vec3<f32>(gx, gy, 1.0));
+    }
 
     // Convert (-1.0 , 1.0) to (0.0 , 1.0), if needed
     let N_mapped: vec4<f32> = vec4<f32>(N * 0.5 + 0.5, 1.0);
 
     // Write to the output texture
-    textureStore(texture, coord, N_mapped);
+    textureStore(texture, coord, layer, N_mapped);
 }
 
 
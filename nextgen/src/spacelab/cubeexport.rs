@@ -0,0 +1,430 @@
+// Single file cube maps (KTX2 and DDS) holding all six faces and their mipmaps
+
+use std::{path::Path, str::FromStr};
+
+use crate::{
+    cpu::texture::CpuTexture,
+    error::{check_size, Error, Result},
+};
+
+use super::blockcompress::{compress, BlockFormat};
+
+/// Texel format of an exported cube map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CubeFormat {
+    Rgba8,
+    Bc1,
+    Bc3,
+    Bc5,
+}
+
+impl FromStr for CubeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "rgba8" => Ok(CubeFormat::Rgba8),
+            "bc1" => Ok(CubeFormat::Bc1),
+            "bc3" => Ok(CubeFormat::Bc3),
+            "bc5" => Ok(CubeFormat::Bc5),
+            _ => Err(format!("Invalid cube map format {}, expected rgba8, bc1, bc3 or bc5", s)),
+        }
+    }
+}
+
+impl CubeFormat {
+    fn block_format(&self) -> Option<BlockFormat> {
+        match self {
+            CubeFormat::Rgba8 => None,
+            CubeFormat::Bc1 => Some(BlockFormat::Bc1),
+            CubeFormat::Bc3 => Some(BlockFormat::Bc3),
+            CubeFormat::Bc5 => Some(BlockFormat::Bc5),
+        }
+    }
+
+    // Bytes per texel for RGBA8, per 4x4 block otherwise
+    fn block_size(&self) -> usize {
+        self.block_format().map_or(4, |format| format.block_size())
+    }
+}
+
+/// How `export_cubemap` writes the faces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CubeExportOptions {
+    pub format: CubeFormat,
+    /// Write the whole mipmap chain down to 1x1
+    pub mipmaps: bool,
+    /// Tag the texels as sRGB encoded, for albedo maps. Only KTX2 keeps the tag.
+    pub srgb: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FaceTransform {
+    Rotate180,
+    FlipVertical,
+}
+
+// Cube map containers order the faces +X, -X, +Y, -Y, +Z, -Z and orient them the way
+// Vulkan and OpenGL sample cube maps, which turns some of ours around
+const CONTAINER_FACES: [(usize, FaceTransform); 6] = [
+    (5, FaceTransform::Rotate180),    // "right"
+    (4, FaceTransform::Rotate180),    // "left"
+    (3, FaceTransform::FlipVertical), // "up"
+    (2, FaceTransform::FlipVertical), // "down"
+    (1, FaceTransform::Rotate180),    // "back"
+    (0, FaceTransform::Rotate180),    // "front"
+];
+
+// One mipmap level, the encoded faces in container order
+struct Level {
+    size: u32,
+    faces: Vec<Vec<u8>>,
+}
+
+fn transform_face(face: &CpuTexture, transform: FaceTransform) -> Vec<u8> {
+    let (width, height) = (face.width() as usize, face.height() as usize);
+    let data = face.data();
+    let mut out = Vec::with_capacity(data.len());
+    for y in 0..height {
+        for x in 0..width {
+            let (sx, sy) = match transform {
+                FaceTransform::Rotate180 => (width - 1 - x, height - 1 - y),
+                FaceTransform::FlipVertical => (x, height - 1 - y),
+            };
+            let offset = (sy * width + sx) * 4;
+            out.extend_from_slice(&data[offset..offset + 4]);
+        }
+    }
+    out
+}
+
+// Next mipmap level of a square RGBA8 image, averaging 2x2 texels
+fn downsample(rgba: &[u8], size: u32) -> Vec<u8> {
+    let (size, half) = (size as usize, (size as usize / 2).max(1));
+    let mut out = Vec::with_capacity(half * half * 4);
+    for y in 0..half {
+        for x in 0..half {
+            for c in 0..4 {
+                let texel = |dx: usize, dy: usize| {
+                    let (sx, sy) = ((x * 2 + dx).min(size - 1), (y * 2 + dy).min(size - 1));
+                    rgba[(sy * size + sx) * 4 + c] as u32
+                };
+                out.push(((texel(0, 0) + texel(1, 0) + texel(0, 1) + texel(1, 1) + 2) / 4) as u8);
+            }
+        }
+    }
+    out
+}
+
+fn build_levels(faces: &[CpuTexture], options: &CubeExportOptions) -> Vec<Level> {
+    let mut size = faces[0].width();
+    let mut images: Vec<Vec<u8>> = CONTAINER_FACES
+        .iter()
+        .map(|(face_num, transform)| transform_face(&faces[*face_num], *transform))
+        .collect();
+
+    let mut levels = Vec::new();
+    loop {
+        let encoded = images
+            .iter()
+            .map(|image| match options.format.block_format() {
+                Some(format) => compress(format, size, size, image),
+                None => image.clone(),
+            })
+            .collect();
+        levels.push(Level { size, faces: encoded });
+        if !options.mipmaps || size == 1 {
+            return levels;
+        }
+        images = images.iter().map(|image| downsample(image, size)).collect();
+        size = (size / 2).max(1);
+    }
+}
+
+/// Writes the six faces of a cube map, in `CUBEMAP` order, to a `.ktx2` or `.dds` file.
+/// The faces must be square RGBA8 textures of the same, non zero size.
+pub fn export_cubemap(faces: &[CpuTexture], path: &Path, options: &CubeExportOptions) -> Result<()> {
+    if faces.len() != 6 {
+        return Err(Error::BadDefinition(format!("A cube map has 6 faces, got {}", faces.len())));
+    }
+    let size = faces[0].width();
+    if size == 0 {
+        return Err(Error::BadDefinition("Cube map faces are empty".to_owned()));
+    }
+    for face in faces {
+        check_size("Cube map face", (size, size), (face.width(), face.height()))?;
+        if face.format() != wgpu::TextureFormat::Rgba8Unorm {
+            return Err(Error::UnsupportedFormat(format!("cube map face {:?}", face.format())));
+        }
+    }
+    if options.srgb && options.format == CubeFormat::Bc5 {
+        return Err(Error::UnsupportedFormat("BC5 has no sRGB variant".to_owned()));
+    }
+
+    let data = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("ktx2") => write_ktx2(&build_levels(faces, options), options),
+        Some("dds") => write_dds(&build_levels(faces, options), options),
+        _ => {
+            return Err(Error::UnsupportedFormat(format!(
+                "cube map file {}, expected .ktx2 or .dds",
+                path.display()
+            )))
+        }
+    };
+    std::fs::write(path, data)?;
+    Ok(())
+}
+
+fn vk_format(options: &CubeExportOptions) -> u32 {
+    match (options.format, options.srgb) {
+        (CubeFormat::Rgba8, false) => 37, // VK_FORMAT_R8G8B8A8_UNORM
+        (CubeFormat::Rgba8, true) => 43,  // VK_FORMAT_R8G8B8A8_SRGB
+        (CubeFormat::Bc1, false) => 131,  // VK_FORMAT_BC1_RGB_UNORM_BLOCK
+        (CubeFormat::Bc1, true) => 132,   // VK_FORMAT_BC1_RGB_SRGB_BLOCK
+        (CubeFormat::Bc3, false) => 137,  // VK_FORMAT_BC3_UNORM_BLOCK
+        (CubeFormat::Bc3, true) => 138,   // VK_FORMAT_BC3_SRGB_BLOCK
+        (CubeFormat::Bc5, _) => 141,      // VK_FORMAT_BC5_UNORM_BLOCK
+    }
+}
+
+// Channel, bit offset, bit length, qualifiers, upper value
+type Sample = (u8, u16, u8, u8, u32);
+
+// Basic data format descriptor of the KTX2 file, see the Khronos Data Format specification
+fn data_format_descriptor(options: &CubeExportOptions) -> Vec<u8> {
+    const LINEAR: u8 = 0x80;
+    let (color_model, block_dimension, samples): (u8, u8, Vec<Sample>) =
+        match options.format {
+            // KHR_DF_MODEL_RGBSDA, alpha is always linear
+            CubeFormat::Rgba8 => (
+                1,
+                0,
+                vec![(0, 0, 7, 0, 255), (1, 8, 7, 0, 255), (2, 16, 7, 0, 255), (15, 24, 7, LINEAR, 255)],
+            ),
+            // KHR_DF_MODEL_BC1A
+            CubeFormat::Bc1 => (128, 3, vec![(0, 0, 63, 0, u32::MAX)]),
+            // KHR_DF_MODEL_BC3
+            CubeFormat::Bc3 => (130, 3, vec![(15, 0, 63, LINEAR, u32::MAX), (0, 64, 63, 0, u32::MAX)]),
+            // KHR_DF_MODEL_BC5
+            CubeFormat::Bc5 => (132, 3, vec![(0, 0, 63, 0, u32::MAX), (1, 64, 63, 0, u32::MAX)]),
+        };
+    let transfer = if options.srgb { 2 } else { 1 };
+    let block_size = 24 + 16 * samples.len();
+
+    let mut dfd = Vec::new();
+    dfd.extend_from_slice(&(4 + block_size as u32).to_le_bytes());
+    dfd.extend_from_slice(&0u32.to_le_bytes()); // Khronos vendor, basic descriptor type
+    dfd.extend_from_slice(&2u16.to_le_bytes()); // Version 1.3
+    dfd.extend_from_slice(&(block_size as u16).to_le_bytes());
+    dfd.extend_from_slice(&[color_model, 1, transfer, 0]); // BT.709 primaries, straight alpha
+    dfd.extend_from_slice(&[block_dimension, block_dimension, 0, 0]);
+    dfd.extend_from_slice(&[options.format.block_size() as u8, 0, 0, 0, 0, 0, 0, 0]);
+    for (channel, offset, length, qualifiers, upper) in samples {
+        // Alpha keeps its linear flag in sRGB files only
+        let qualifiers = if options.srgb { qualifiers } else { 0 };
+        dfd.extend_from_slice(&offset.to_le_bytes());
+        dfd.extend_from_slice(&[length, channel | qualifiers, 0, 0, 0, 0]);
+        dfd.extend_from_slice(&0u32.to_le_bytes());
+        dfd.extend_from_slice(&upper.to_le_bytes());
+    }
+    dfd
+}
+
+fn write_ktx2(levels: &[Level], options: &CubeExportOptions) -> Vec<u8> {
+    const IDENTIFIER: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+    let align = |offset: usize, alignment: usize| offset.div_ceil(alignment) * alignment;
+
+    let dfd = data_format_descriptor(options);
+    let mut kvd = Vec::new();
+    let entry = b"KTXwriter\0nextgen\0";
+    kvd.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+    kvd.extend_from_slice(entry);
+    kvd.resize(align(kvd.len(), 4), 0);
+
+    let dfd_offset = 80 + 24 * levels.len();
+    let kvd_offset = dfd_offset + dfd.len();
+    // Level data goes smallest first, every level aligned to the block size
+    let mut level_offsets = vec![0; levels.len()];
+    let mut offset = kvd_offset + kvd.len();
+    for (i, level) in levels.iter().enumerate().rev() {
+        offset = align(offset, options.format.block_size().max(4));
+        level_offsets[i] = offset;
+        offset += level.faces.iter().map(|face| face.len()).sum::<usize>();
+    }
+
+    let mut out = Vec::with_capacity(offset);
+    out.extend_from_slice(&IDENTIFIER);
+    let size = levels[0].size;
+    // vkFormat, typeSize, width, height, depth, layers, faces, levels, supercompression
+    for value in [vk_format(options), 1, size, size, 0, 0, 6, levels.len() as u32, 0] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [dfd_offset, dfd.len(), kvd_offset, kvd.len()] {
+        out.extend_from_slice(&(value as u32).to_le_bytes());
+    }
+    out.extend_from_slice(&[0; 16]); // No supercompression global data
+    for (level, level_offset) in levels.iter().zip(&level_offsets) {
+        let length = level.faces.iter().map(|face| face.len()).sum::<usize>() as u64;
+        out.extend_from_slice(&(*level_offset as u64).to_le_bytes());
+        out.extend_from_slice(&length.to_le_bytes());
+        out.extend_from_slice(&length.to_le_bytes());
+    }
+    out.extend_from_slice(&dfd);
+    out.extend_from_slice(&kvd);
+    for (level, level_offset) in levels.iter().zip(&level_offsets).rev() {
+        out.resize(*level_offset, 0);
+        for face in &level.faces {
+            out.extend_from_slice(face);
+        }
+    }
+    out
+}
+
+fn write_dds(levels: &[Level], options: &CubeExportOptions) -> Vec<u8> {
+    const DDSD_CAPS: u32 = 0x1;
+    const DDSD_HEIGHT: u32 = 0x2;
+    const DDSD_WIDTH: u32 = 0x4;
+    const DDSD_PITCH: u32 = 0x8;
+    const DDSD_PIXELFORMAT: u32 = 0x1000;
+    const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+    const DDSD_LINEARSIZE: u32 = 0x80000;
+    const DDPF_ALPHAPIXELS: u32 = 0x1;
+    const DDPF_FOURCC: u32 = 0x4;
+    const DDPF_RGB: u32 = 0x40;
+    const DDSCAPS_COMPLEX: u32 = 0x8;
+    const DDSCAPS_TEXTURE: u32 = 0x1000;
+    const DDSCAPS_MIPMAP: u32 = 0x400000;
+    const DDSCAPS2_CUBEMAP_ALLFACES: u32 = 0x200 | 0xFC00;
+
+    let size = levels[0].size;
+    let mut flags = DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT | DDSD_MIPMAPCOUNT;
+    let mut caps = DDSCAPS_COMPLEX | DDSCAPS_TEXTURE;
+    if levels.len() > 1 {
+        caps |= DDSCAPS_MIPMAP;
+    }
+    // The legacy header has no sRGB flag, but every reader knows these FourCCs
+    let (pitch, pixel_format): (u32, [u32; 7]) = match options.format {
+        CubeFormat::Rgba8 => {
+            flags |= DDSD_PITCH;
+            let masks = [0xFF, 0xFF00, 0xFF0000, 0xFF000000];
+            (size * 4, [DDPF_RGB | DDPF_ALPHAPIXELS, 0, 32, masks[0], masks[1], masks[2], masks[3]])
+        }
+        format => {
+            flags |= DDSD_LINEARSIZE;
+            let four_cc = match format {
+                CubeFormat::Bc1 => b"DXT1",
+                CubeFormat::Bc3 => b"DXT5",
+                _ => b"ATI2",
+            };
+            let four_cc = u32::from_le_bytes(*four_cc);
+            (levels[0].faces[0].len() as u32, [DDPF_FOURCC, four_cc, 0, 0, 0, 0, 0])
+        }
+    };
+
+    let mut out = Vec::new();
+    out.extend_from_slice(b"DDS ");
+    // size, flags, height, width, pitch or linear size, depth, mipmap count
+    for value in [124, flags, size, size, pitch, 0, levels.len() as u32] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    out.extend_from_slice(&[0; 44]);
+    out.extend_from_slice(&32u32.to_le_bytes());
+    for value in pixel_format {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [caps, DDSCAPS2_CUBEMAP_ALLFACES, 0, 0, 0] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    // Every face with its whole mipmap chain, one face after the other
+    for face in 0..6 {
+        for level in levels {
+            out.extend_from_slice(&level.faces[face]);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(data: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn faces(size: u32) -> Vec<CpuTexture> {
+        (0..6).map(|_| CpuTexture::new(size, size, wgpu::TextureFormat::Rgba8Unorm).unwrap()).collect()
+    }
+
+    fn options(format: CubeFormat) -> CubeExportOptions {
+        CubeExportOptions {
+            format,
+            mipmaps: true,
+            srgb: false,
+        }
+    }
+
+    #[test]
+    fn ktx2_offsets_point_at_their_sections() {
+        let options = options(CubeFormat::Bc1);
+        let levels = build_levels(&faces(8), &options);
+        assert_eq!(levels.iter().map(|level| level.size).collect::<Vec<_>>(), [8, 4, 2, 1]);
+        let data = write_ktx2(&levels, &options);
+
+        assert_eq!(&data[0..12], b"\xABKTX 20\xBB\r\n\x1A\n");
+        // vkFormat, typeSize, width, height, depth, layers, faces, levels, supercompression
+        let header: Vec<u32> = (0..9).map(|i| u32_at(&data, 12 + i * 4)).collect();
+        assert_eq!(header, [131, 1, 8, 8, 0, 0, 6, 4, 0]);
+
+        let (dfd_offset, dfd_length) = (u32_at(&data, 48) as usize, u32_at(&data, 52) as usize);
+        let (kvd_offset, kvd_length) = (u32_at(&data, 56) as usize, u32_at(&data, 60) as usize);
+        assert_eq!(dfd_offset, 80 + 24 * 4);
+        assert_eq!(u32_at(&data, dfd_offset) as usize, dfd_length);
+        assert_eq!(kvd_offset, dfd_offset + dfd_length);
+        assert_eq!(&data[kvd_offset + 4..kvd_offset + 14], b"KTXwriter\0");
+
+        // BC1 blocks of the 8x8, 4x4, 2x2 and 1x1 levels, six faces each
+        let mut end = data.len();
+        for (i, blocks) in [4, 1, 1, 1].iter().enumerate() {
+            let (offset, length) = (u64_at(&data, 80 + i * 24) as usize, u64_at(&data, 88 + i * 24) as usize);
+            assert_eq!(length, blocks * 8 * 6);
+            assert_eq!(u64_at(&data, 96 + i * 24) as usize, length);
+            assert_eq!(offset % 8, 0);
+            assert!(offset >= kvd_offset + kvd_length);
+            // Levels are stored smallest first, the largest one ends the file
+            if i == 0 {
+                assert_eq!(offset + length, end);
+            }
+            assert!(offset + length <= end);
+            end = offset;
+        }
+    }
+
+    #[test]
+    fn dds_header_is_128_bytes() {
+        let options = options(CubeFormat::Rgba8);
+        let levels = build_levels(&faces(4), &options);
+        let data = write_dds(&levels, &options);
+
+        assert_eq!(&data[0..4], b"DDS ");
+        assert_eq!(u32_at(&data, 4), 124);
+        assert_eq!(u32_at(&data, 76), 32);
+        // Six faces of 4x4, 2x2 and 1x1 RGBA8 texels follow the header
+        assert_eq!(data.len(), 128 + 6 * (16 + 4 + 1) * 4);
+        assert_eq!(u32_at(&data, 28), 3);
+    }
+
+    #[test]
+    fn empty_faces_are_rejected() {
+        let path = std::env::temp_dir().join("nextgen-empty-cubemap.dds");
+        assert!(matches!(
+            export_cubemap(&faces(0), &path, &options(CubeFormat::Bc1)),
+            Err(Error::BadDefinition(_))
+        ));
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,183 @@
+// Neighbour lookups across the six faces of the planet cube map
+
+use nalgebra as na;
+
+use crate::{
+    cpu::texture::CpuTexture,
+    error::{check_size, Error, Result},
+};
+
+use super::lutgen_cpu::compute_point;
+
+/// Face number and `(u, v)` in -1..1 of the face a direction goes through.
+/// Inverse of `compute_point`.
+pub fn project_to_face(p: na::Vector3<f32>) -> (u32, f32, f32) {
+    let (ax, ay, az) = (p.x.abs(), p.y.abs(), p.z.abs());
+    if az >= ax && az >= ay {
+        if p.z < 0.0 {
+            (0, p.x / az, p.y / az) // "front"
+        } else {
+            (1, -p.x / az, p.y / az) // "back"
+        }
+    } else if ay >= ax {
+        if p.y < 0.0 {
+            (2, p.x / ay, p.z / ay) // "down"
+        } else {
+            (3, p.x / ay, -p.z / ay) // "up"
+        }
+    } else if p.x < 0.0 {
+        (4, -p.z / ax, p.y / ax) // "left"
+    } else {
+        (5, p.z / ax, p.y / ax) // "right"
+    }
+}
+
+/// Face, x and y of the texel at `(x, y)` of a face. Coordinates outside of the face
+/// continue onto the adjacent face, turned the way the cube map is laid out.
+pub fn cube_texel(face_num: u32, x: i32, y: i32, width: u32, height: u32) -> (u32, u32, u32) {
+    if x >= 0 && y >= 0 && x < width as i32 && y < height as i32 {
+        return (face_num, x as u32, y as u32);
+    }
+
+    let u = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+    let v = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+    let (face, u, v) = project_to_face(compute_point(u, v, face_num));
+    let to_texel = |c: f32, size: u32| {
+        ((c + 1.0) * 0.5 * size as f32 - 0.5)
+            .round()
+            .clamp(0.0, size as f32 - 1.0)
+    };
+    (face, to_texel(u, width) as u32, to_texel(v, height) as u32)
+}
+
+/// Copy of a face with a ring of `border` texels around it taken from the adjacent faces,
+/// so filters reading past the edge see the rest of the planet instead of a seam.
+///
+/// `faces` holds the six faces in `CUBEMAP` order, all the same size.
+pub fn bordered_face(faces: &[CpuTexture], face_num: u32, border: u32) -> Result<CpuTexture> {
+    if faces.len() != 6 {
+        return Err(Error::BadDefinition(format!("A cube map has 6 faces, got {}", faces.len())));
+    }
+    let face = &faces[face_num as usize];
+    let (width, height) = (face.width(), face.height());
+    for other in faces {
+        check_size("Cube map face", (width, height), (other.width(), other.height()))?;
+    }
+
+    let mut texture = CpuTexture::new(width + 2 * border, height + 2 * border, face.format())?;
+    texture.fill(|x, y| {
+        let (x, y) = (x as i32 - border as i32, y as i32 - border as i32);
+        let (n, x, y) = cube_texel(face_num, x, y, width, height);
+        faces[n as usize].load(x, y)
+    });
+
+    Ok(texture)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: i32 = 8;
+
+    // Step out of each edge of a face: top, right, bottom, left
+    const OUTWARD: [(i32, i32); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+
+    // Texels along an edge of a face, in order
+    fn edge_texels(edge: usize) -> Vec<(i32, i32)> {
+        let last = SIZE - 1;
+        (0..SIZE)
+            .map(|i| match edge {
+                0 => (i, 0),
+                1 => (last, i),
+                2 => (i, last),
+                _ => (0, i),
+            })
+            .collect()
+    }
+
+    // Edge of a face a boundary texel is on, None for corners and inner texels
+    fn edge_of(x: i32, y: i32) -> Option<usize> {
+        let last = SIZE - 1;
+        let edges: Vec<usize> = [y == 0, x == last, y == last, x == 0]
+            .iter()
+            .enumerate()
+            .filter_map(|(edge, &on)| on.then_some(edge))
+            .collect();
+        (edges.len() == 1).then(|| edges[0])
+    }
+
+    fn step(face_num: u32, (x, y): (i32, i32), (dx, dy): (i32, i32)) -> (u32, i32, i32) {
+        let (n, x, y) = cube_texel(face_num, x + dx, y + dy, SIZE as u32, SIZE as u32);
+        (n, x as i32, y as i32)
+    }
+
+    #[test]
+    fn inside_texels_stay_on_their_face() {
+        assert_eq!(cube_texel(2, 0, 7, 8, 8), (2, 0, 7));
+        assert_eq!(cube_texel(5, 3, 4, 8, 8), (5, 3, 4));
+    }
+
+    #[test]
+    fn all_24_edges_continue_onto_their_neighbours() {
+        for face_num in 0..6 {
+            let mut neighbours = Vec::new();
+            for (edge, outward) in OUTWARD.iter().enumerate() {
+                let texels = edge_texels(edge);
+                let across: Vec<(u32, i32, i32)> =
+                    texels.iter().map(|&texel| step(face_num, texel, *outward)).collect();
+
+                // One adjacent face, neither this one nor the opposite one
+                let n = across[0].0;
+                assert!(across.iter().all(|t| t.0 == n), "face {} edge {}: {:?}", face_num, edge, across);
+                assert!(n != face_num && n != face_num ^ 1, "face {} edge {} -> {}", face_num, edge, n);
+                neighbours.push(n);
+
+                // The edge runs along one edge of the neighbour, texel after texel
+                let edges: Vec<Option<usize>> = across[1..across.len() - 1]
+                    .iter()
+                    .map(|&(_, x, y)| edge_of(x, y))
+                    .collect();
+                assert!(edges[0].is_some() && edges.iter().all(|e| *e == edges[0]));
+                for pair in across.windows(2) {
+                    let distance = (pair[0].1 - pair[1].1).abs() + (pair[0].2 - pair[1].2).abs();
+                    assert_eq!(distance, 1, "face {} edge {}: {:?}", face_num, edge, across);
+                }
+
+                // Stepping back out of the neighbour lands on the texel we came from
+                let back = OUTWARD[edges[0].unwrap()];
+                for (texel, &(_, x, y)) in texels.iter().zip(&across).skip(1).take(texels.len() - 2) {
+                    assert_eq!(step(n, (x, y), back), (face_num, texel.0, texel.1));
+                }
+            }
+            neighbours.sort();
+            neighbours.dedup();
+            assert_eq!(neighbours.len(), 4, "face {}", face_num);
+        }
+    }
+
+    #[test]
+    fn bordered_face_rings_the_face_with_its_neighbours() {
+        let size = SIZE as u32;
+        let faces: Vec<CpuTexture> = (0..6)
+            .map(|n| {
+                let mut face = CpuTexture::new(size, size, wgpu::TextureFormat::R32Float).unwrap();
+                face.fill(|x, y| [(n * 10000 + y * 100 + x) as f32, 0.0, 0.0, 0.0]);
+                face
+            })
+            .collect();
+
+        for face_num in 0..6 {
+            let bordered = bordered_face(&faces, face_num, 2).unwrap();
+            assert_eq!((bordered.width(), bordered.height()), (size + 4, size + 4));
+            for y in 0..bordered.height() {
+                for x in 0..bordered.width() {
+                    let (n, fx, fy) = cube_texel(face_num, x as i32 - 2, y as i32 - 2, size, size);
+                    assert_eq!(bordered.load(x, y), faces[n as usize].load(fx, fy));
+                }
+            }
+        }
+        assert!(bordered_face(&faces[..5], 0, 1).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+struct GeoLutGenParams {
+    // Face of the first layer, layer n is face `face_num + n`
+    face_num: u32,
+    // Size of the whole face
+    width: u32,
+    height: u32,
+    // Face texel under the first texel of the output
+    origin_x: u32,
+    origin_y: u32,
+};
+
+@group(0)
+@binding(0)
+var<uniform> params : GeoLutGenParams;
+
+// Signed latitude and longitude in degrees
+@group(0)
+@binding(1)
+var texture: texture_storage_2d_array<rg32float, write>;
+
+fn compute_point(u: f32, v: f32, face: i32) -> vec3<f32> {
+    switch (face) {
+        case 0:     { return vec3<f32>(u, v, -1.0);    } // "front"
+        case 1:     { return vec3<f32>(-u, v, 1.0);    } // "back"
+        case 2:     { return vec3<f32>(u, -1.0, v);    } // "down"
+        case 3:     { return vec3<f32>(u, 1.0, -v);    } // "up"
+        case 4:     { return vec3<f32>(-1.0, v, -u);   } // "left"
+        case 5:     { return vec3<f32>(1.0, v, u);     } // "right"
+        default:    { return vec3<f32>(0.0, 0.0, 0.0); } // "none"
+    }
+}
+
+const deg: f32 = 57.29577951308232;
+
+@compute
+@workgroup_size(8,8)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let X: f32 = f32(params.origin_x + global_id.x);
+    let Y: f32 = f32(params.origin_y + global_id.y);
+    let face_num: i32 = i32(params.face_num + global_id.z);
+    let width: f32 = f32(params.width);
+    let height: f32 = f32(params.height);
+
+    let u = (X + 0.5) / width * 2.0 - 1.0;
+    let v = (Y + 0.5) / height * 2.0 - 1.0;
+    let point_on_sphere = normalize(compute_point(u, v, face_num));
+
+    // Longitude 0 at the center of the front face, growing toward the right face
+    let latitude = asin(point_on_sphere.y) * deg;
+    let longitude = atan2(point_on_sphere.x, -point_on_sphere.z) * deg;
+    textureStore(texture, vec2<i32>(global_id.xy), i32(global_id.z), vec4<f32>(latitude, longitude, 0.0, 1.0));
+}
@@ -1,7 +1,12 @@
 struct LatLutGenParams {
+    // Face of the first layer, layer n is face `face_num + n`
     face_num: u32,
+    // Size of the whole face
     width: u32,
     height: u32,
+    // Face texel under the first texel of the output
+    origin_x: u32,
+    origin_y: u32,
 };
 
 @group(0)
@@ -10,7 +15,7 @@ var<uniform> params : LatLutGenParams;
 
 @group(0)
 @binding(1)
-var texture: texture_storage_2d<rgba8unorm, write>;
+var texture: texture_storage_2d_array<rgba8unorm, write>;
 
 fn compute_point(u: f32, v: f32, face: i32) -> vec3<f32> {
     switch (face) {
@@ -29,9 +34,9 @@ const rad: f32 = 1.5707963267948966;
 @compute
 @workgroup_size(8,8)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    let X: f32 = f32(global_id.x);
-    let Y: f32 = f32(global_id.y);
-    let face_num: i32 = i32(params.face_num);
+    let X: f32 = f32(params.origin_x + global_id.x);
+    let Y: f32 = f32(params.origin_y + global_id.y);
+    let face_num: i32 = i32(params.face_num + global_id.z);
     let width: f32 = f32(params.width);
     let height: f32 = f32(params.height);
 
@@ -43,5 +48,5 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let latitude = asin(point_on_sphere.y);
     let latitude_radian_norm = abs(latitude) / rad;
     let color = vec4<f32>(latitude_radian_norm, latitude_radian_norm, latitude_radian_norm, 1.0);
-    textureStore(texture, vec2<i32>(i32(X), i32(Y)), color);
+    textureStore(texture, vec2<i32>(global_id.xy), i32(global_id.z), color);
 }
\ No newline at end of file
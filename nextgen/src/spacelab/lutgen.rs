@@ -3,38 +3,85 @@ use std::{f32::consts::PI, fs, path::Path};
 use image::{ImageBuffer, Rgb};
 use nalgebra as na;
 
+use crate::error::Result;
+
+use super::{cubemap::project_to_face, lutgen_cpu::compute_point, lutgen_gpu::face_to_num};
+
 pub const CUBEMAP: [&str; 6] = ["front", "back", "down", "up", "left", "right"];
 pub const RAD2DEG: f32 = 360.0 / (PI * 2.0);
 
+/// Real size of the terrain of a heightmap, so slopes and normals come out in the same
+/// degrees the definition's material rules use.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainScale {
+    /// Planet radius in metres
+    pub radius: f32,
+    /// `HillParams` of the definition, heightmap values 0 and 1 lie at `radius * min` and
+    /// `radius * max` from the surface
+    pub hill_min: f32,
+    pub hill_max: f32,
+}
+
+impl TerrainScale {
+    /// Metres between heightmap values 0 and 1.
+    pub fn height_range(&self) -> f32 {
+        self.radius * (self.hill_max - self.hill_min)
+    }
+
+    /// Metres covered by texel `(x, y)` of a `width` x `height` face, along x and y. The cube
+    /// projection squeezes texels toward the face edges.
+    pub fn texel_size(&self, x: u32, y: u32, width: u32, height: u32) -> (f32, f32) {
+        let u = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+        let v = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+        let d = 1.0 + u * u + v * v;
+        (
+            self.radius * 2.0 / width as f32 * (1.0 + v * v).sqrt() / d,
+            self.radius * 2.0 / height as f32 * (1.0 + u * u).sqrt() / d,
+        )
+    }
+}
+
+/// Signed latitude and longitude in degrees of a direction from the planet center.
+///
+/// Latitude is positive toward the "up" face. Longitude is 0 at the center of the "front"
+/// face, 90 at "right", -90 at "left" and +-180 at "back".
+pub fn point_to_lat_lon(p: na::Vector3<f32>) -> (f32, f32) {
+    let p = p.normalize();
+    (p.y.asin().to_degrees(), p.x.atan2(-p.z).to_degrees())
+}
+
+/// Face number and `(u, v)` in -1..1 of the point at a latitude and longitude in degrees.
+/// Inverse of `point_to_lat_lon` and of the geo LUT.
+pub fn lat_lon_to_face(latitude: f32, longitude: f32) -> (u32, f32, f32) {
+    let (lat, lon) = (latitude.to_radians(), longitude.to_radians());
+    let point = na::Vector3::new(lat.cos() * lon.sin(), lat.sin(), -lat.cos() * lon.cos());
+    project_to_face(point)
+}
+
+/// Face number and texel of a `width` x `height` face at a latitude and longitude in degrees,
+/// for placing markers on the face textures.
+pub fn lat_lon_to_texel(latitude: f32, longitude: f32, width: u32, height: u32) -> (u32, u32, u32) {
+    let (face, u, v) = lat_lon_to_face(latitude, longitude);
+    let to_texel = |c: f32, size: u32| ((c + 1.0) * 0.5 * size as f32).clamp(0.0, size as f32 - 1.0);
+    (face, to_texel(u, width) as u32, to_texel(v, height) as u32)
+}
+
+/// Absolute latitude in whole degrees of texel `(x_pixel, y_pixel)`, the value the 8 bit
+/// latitude LUT stores. Both hemispheres count up from 0 at the equator.
 pub fn pixel_to_latitude(
     face: &str,
     x_pixel: u32,
     y_pixel: u32,
     face_texture_width: u32,
     face_texture_height: u32,
-) -> u8 {
+) -> Result<u8> {
     let u = (x_pixel as f32 + 0.5) / face_texture_width as f32 * 2.0 - 1.0;
     let v = (y_pixel as f32 + 0.5) / face_texture_height as f32 * 2.0 - 1.0;
-
-    let point: na::Vector3<f32>;
-    match face {
-        "up" => point = na::Vector3::new(u, 1.0, -v),
-        "down" => point = na::Vector3::new(u, -1.0, v),
-        "left" => point = na::Vector3::new(-1.0, v, -u),
-        "right" => point = na::Vector3::new(1.0, v, u),
-        "back" => point = na::Vector3::new(-u, v, 1.0),
-        "front" => point = na::Vector3::new(u, v, -1.0),
-        _ => panic!("Invalid face"),
-    }
-
-    let point_on_sphere = point.normalize();
-    let latitude = point_on_sphere.y.asin();
-    let latitude_degrees = latitude.to_degrees();
-
-    latitude_degrees as u8
+    let (latitude, _) = point_to_lat_lon(compute_point(u, v, face_to_num(face)?));
+    Ok(latitude.abs().round() as u8)
 }
 
-pub fn generate_latlut(face: String, width: u32, height: u32) {
+pub fn generate_latlut(face: String, width: u32, height: u32) -> Result<()> {
     let lat_lut_path = format!("luts/latlut_{}.png", face);
     if let Ok(_) = fs::metadata(&lat_lut_path) {
         println!("Cached LUT at {}", lat_lut_path);
@@ -44,25 +91,28 @@ pub fn generate_latlut(face: String, width: u32, height: u32) {
         let mut latlutimg: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::new(width, height);
 
         for (x, y, pixel) in latlutimg.enumerate_pixels_mut() {
-            let lat = pixel_to_latitude(face.as_str(), x, y, width, height);
+            let lat = pixel_to_latitude(face.as_str(), x, y, width, height)?;
             *pixel = Rgb([lat, lat, lat]);
         }
-        latlutimg
-            .save_with_format(&lat_lut_path, image::ImageFormat::Png)
-            .unwrap();
+        latlutimg.save_with_format(&lat_lut_path, image::ImageFormat::Png)?;
 
         // println!("Done!");
     }
+    Ok(())
 }
 
-pub fn precompute_slope(im: &ImageBuffer<Rgb<u8>, Vec<u8>>, p: &str, base_asset_path: &str) {
+pub fn precompute_slope(
+    im: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    p: &str,
+    base_asset_path: &str,
+) -> Result<()> {
     let width = im.width();
     let height = im.height();
     let slope_path = format!("{}{}_slope.png", base_asset_path, p);
 
     if Path::new(&slope_path).exists() {
         println!("Cached slope at {}", slope_path);
-        return;
+        return Ok(());
     }
 
     println!("Computing slope");
@@ -88,10 +138,29 @@ pub fn precompute_slope(im: &ImageBuffer<Rgb<u8>, Vec<u8>>, p: &str, base_asset_
         }
     }
 
-    sm.save_with_format(&slope_path, image::ImageFormat::Png)
-        .unwrap();
+    sm.save_with_format(&slope_path, image::ImageFormat::Png)?;
 
     println!("Done!");
+    Ok(())
 }
 
 // On GPU
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_to_latitude_covers_both_hemispheres() {
+        // Face centers and the texels next to the poles
+        assert_eq!(pixel_to_latitude("front", 50, 50, 101, 101).unwrap(), 0);
+        assert_eq!(pixel_to_latitude("up", 50, 50, 101, 101).unwrap(), 90);
+        assert_eq!(pixel_to_latitude("down", 50, 50, 101, 101).unwrap(), 90);
+        // Top and bottom rows of a side face mirror each other, 45 degrees at the edge
+        let top = pixel_to_latitude("front", 50, 0, 101, 101).unwrap();
+        let bottom = pixel_to_latitude("front", 50, 100, 101, 101).unwrap();
+        assert_eq!(top, bottom);
+        assert!((44..=45).contains(&top));
+        assert!(pixel_to_latitude("middle", 0, 0, 1, 1).is_err());
+    }
+}
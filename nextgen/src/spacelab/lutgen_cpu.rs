@@ -0,0 +1,190 @@
+// CPU versions of latlutgen.wgsl, geolutgen.wgsl and slopegen.wgsl
+
+use std::f32::consts::FRAC_PI_2;
+
+use nalgebra as na;
+
+use crate::{
+    cpu::texture::CpuTexture,
+    error::{check_size, Result},
+};
+
+use super::{
+    lutgen::{point_to_lat_lon, TerrainScale},
+    lutgen_gpu::face_to_num,
+    tiling::FaceTile,
+};
+
+pub(crate) fn compute_point(u: f32, v: f32, face_num: u32) -> na::Vector3<f32> {
+    match face_num {
+        0 => na::Vector3::new(u, v, -1.0),  // "front"
+        1 => na::Vector3::new(-u, v, 1.0),  // "back"
+        2 => na::Vector3::new(u, -1.0, v),  // "down"
+        3 => na::Vector3::new(u, 1.0, -v),  // "up"
+        4 => na::Vector3::new(-1.0, v, -u), // "left"
+        5 => na::Vector3::new(1.0, v, u),   // "right"
+        _ => na::Vector3::new(0.0, 0.0, 0.0),
+    }
+}
+
+pub fn cpu_generate_latlut(face: &str, tile: &FaceTile) -> Result<CpuTexture> {
+    let face_num = face_to_num(face)?;
+    let (width, height) = (tile.face_width, tile.face_height);
+    let mut texture = CpuTexture::new(tile.width, tile.height, wgpu::TextureFormat::Rgba8Unorm)?;
+
+    texture.fill(|x, y| {
+        let u = ((tile.x + x) as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+        let v = ((tile.y + y) as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+        let point_on_sphere = compute_point(u, v, face_num).normalize();
+        let latitude = point_on_sphere.y.asin();
+        let latitude_radian_norm = latitude.abs() / FRAC_PI_2;
+        [latitude_radian_norm, latitude_radian_norm, latitude_radian_norm, 1.0]
+    });
+
+    Ok(texture)
+}
+
+pub fn cpu_generate_geolut(face: &str, width: u32, height: u32) -> Result<CpuTexture> {
+    let face_num = face_to_num(face)?;
+    let mut texture = CpuTexture::new(width, height, wgpu::TextureFormat::Rg32Float)?;
+
+    texture.fill(|x, y| {
+        let u = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+        let v = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+        let (latitude, longitude) = point_to_lat_lon(compute_point(u, v, face_num));
+        [latitude, longitude, 0.0, 1.0]
+    });
+
+    Ok(texture)
+}
+
+pub fn cpu_generate_slope(
+    heightmap: &CpuTexture,
+    tile: &FaceTile,
+    scale: Option<&TerrainScale>,
+) -> Result<CpuTexture> {
+    check_size("Slope height map", tile.input_size(), (heightmap.width(), heightmap.height()))?;
+    let (width, height) = (tile.face_width, tile.face_height);
+    let mut texture = CpuTexture::new(tile.width, tile.height, wgpu::TextureFormat::Rgba8Unorm)?;
+    // Height of a texel of the face
+    let z = |x: u32, y: u32| heightmap.load(x + tile.halo - tile.x, y + tile.halo - tile.y)[0];
+
+    texture.fill(|x, y| {
+        let (x0, y0) = (tile.x + x, tile.y + y);
+        if let Some(scale) = scale {
+            // Forward differences, stepping back on the last column and row when there is no border.
+            // A face one texel across has no neighbour to step to, and no slope along that axis.
+            let xa = if !tile.seamless && x0 + 1 >= width { x0.saturating_sub(1) } else { x0 };
+            let ya = if !tile.seamless && y0 + 1 >= height { y0.saturating_sub(1) } else { y0 };
+            let dzx = if !tile.seamless && width == 1 { 0.0 } else { z(xa + 1, y0) - z(xa, y0) };
+            let dzy = if !tile.seamless && height == 1 { 0.0 } else { z(x0, ya + 1) - z(x0, ya) };
+
+            let (metres_x, metres_y) = scale.texel_size(x0, y0, width, height);
+            let gx = dzx * scale.height_range() / metres_x;
+            let gy = dzy * scale.height_range() / metres_y;
+            let normalized_a = (gx * gx + gy * gy).sqrt().atan() / FRAC_PI_2;
+            return [normalized_a, normalized_a, normalized_a, 1.0];
+        }
+
+        let (mut x1, mut y1) = (x0 + 1, y0 + 1);
+        let z1 = if tile.seamless {
+            z(x1, y1)
+        } else {
+            // Without a border, wrap onto the opposite edge of the face. A tile's halo
+            // already holds the wrapped texels.
+            let unwrapped = (x1, y1);
+            x1 %= width;
+            y1 %= height;
+            if tile.halo > 0 {
+                z(unwrapped.0, unwrapped.1)
+            } else {
+                z(x1, y1)
+            }
+        };
+        let z0 = z(x0, y0);
+
+        let delta_z = 255.0 * (z1 - z0);
+        let delta_x = x1 as f32 - x0 as f32;
+        let delta_y = y1 as f32 - y0 as f32;
+
+        let slope = (delta_z / (delta_x * delta_x + delta_y * delta_y + delta_z * delta_z).sqrt()).asin();
+        let normalized_a = slope.abs() / FRAC_PI_2;
+        [normalized_a, normalized_a, normalized_a, 1.0]
+    });
+
+    Ok(texture)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spacelab::tiling::split_face;
+
+    use super::*;
+
+    fn heightmap(size: u32, height: impl Fn(u32, u32) -> f32 + Sync) -> CpuTexture {
+        let mut texture = CpuTexture::new(size, size, wgpu::TextureFormat::R32Float).unwrap();
+        texture.fill(|x, y| [height(x, y), 0.0, 0.0, 0.0]);
+        texture
+    }
+
+    #[test]
+    fn latlut_runs_from_the_equator_to_the_poles() {
+        let front = cpu_generate_latlut("front", &FaceTile::whole(9, 9)).unwrap();
+        let up = cpu_generate_latlut("up", &FaceTile::whole(9, 9)).unwrap();
+        assert_eq!(front.load(4, 4)[0], 0.0);
+        assert_eq!(up.load(4, 4)[0], 1.0);
+        // Both hemispheres count up from the equator
+        assert_eq!(front.load(4, 0), front.load(4, 8));
+        assert!(cpu_generate_latlut("middle", &FaceTile::whole(1, 1)).is_err());
+    }
+
+    #[test]
+    fn latlut_tiles_match_the_whole_face() {
+        let whole = cpu_generate_latlut("left", &FaceTile::whole(12, 12)).unwrap();
+        for tile in split_face(12, 12, 7, false, 1).unwrap() {
+            let part = cpu_generate_latlut("left", &tile).unwrap();
+            for y in 0..tile.height {
+                for x in 0..tile.width {
+                    assert_eq!(part.load(x, y), whole.load(tile.x + x, tile.y + y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn geolut_holds_signed_latitude_and_longitude() {
+        let geolut = cpu_generate_geolut("right", 8, 8).unwrap();
+        for (x, y) in [(0, 0), (3, 4), (7, 7)] {
+            let u = (x as f32 + 0.5) / 8.0 * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / 8.0 * 2.0 - 1.0;
+            let (latitude, longitude) = point_to_lat_lon(compute_point(u, v, 5));
+            assert_eq!(&geolut.load(x, y)[0..2], &[latitude, longitude]);
+        }
+        let [latitude, longitude, _, _] = geolut.load(3, 4);
+        assert!(latitude.abs() < 10.0 && (longitude - 90.0).abs() < 10.0);
+    }
+
+    #[test]
+    fn slope_of_flat_ground_and_a_ramp() {
+        let tile = FaceTile::whole(6, 6);
+        let flat = cpu_generate_slope(&heightmap(6, |_, _| 0.4), &tile, None).unwrap();
+        assert_eq!(flat.load(2, 3)[0], 0.0);
+
+        // One 8 bit step per texel along the diagonal the unscaled slope looks at is 35 degrees
+        let ramp = heightmap(6, |x, _| x as f32 / 255.0);
+        let slope = cpu_generate_slope(&ramp, &tile, None).unwrap().load(2, 3)[0] * 90.0;
+        assert!((slope - 35.26).abs() < 0.5, "{}", slope);
+
+        let scale = TerrainScale {
+            radius: 1000.0,
+            hill_min: 0.0,
+            hill_max: 1.0,
+        };
+        let scaled = cpu_generate_slope(&ramp, &tile, Some(&scale)).unwrap().load(2, 3)[0];
+        assert!(scaled > 0.0);
+
+        // A single texel face has no neighbours to slope towards
+        let single = cpu_generate_slope(&heightmap(1, |_, _| 0.4), &FaceTile::whole(1, 1), Some(&scale));
+        assert_eq!(single.unwrap().load(0, 0)[0], 0.0);
+    }
+}
@@ -1,128 +1,94 @@
-use std::{borrow::Cow};
-
 use bytemuck::{Pod, Zeroable};
 use std::mem;
 use wgpu::util::DeviceExt;
 
-use crate::gpu::{gpu, texture::Texture};
+use crate::{
+    error::{check_size, Error, Result},
+    gpu::{gpu, texture::Texture},
+};
 
-const WORKGROUP_SIZE: (u32, u32) = (8, 8);
+use super::{
+    lutgen::TerrainScale,
+    pipeline_gpu::{output_binding, texture_binding, ComputeStage, PipelineContext},
+    tiling::FaceTile,
+};
 
-fn face_to_num(face: &str) -> u32 {
+pub(crate) fn face_to_num(face: &str) -> Result<u32> {
     match face {
-        "front" => 0,
-        "back" => 1,
-        "down" => 2,
-        "up" => 3,
-        "left" => 4,
-        "right" => 5,
-        _ => panic!("Invalid face"),
+        "front" => Ok(0),
+        "back" => Ok(1),
+        "down" => Ok(2),
+        "up" => Ok(3),
+        "left" => Ok(4),
+        "right" => Ok(5),
+        _ => Err(Error::InvalidFace(face.to_owned())),
     }
 }
 
-pub async fn gpu_generate_latlut(face: &str, width: u32, height: u32) -> Option<Texture> {
-    let gpu = gpu::open_default().await?;
-
-    gpu_generate_latlut_inner(&gpu, face, width, height).await
+/// Layer n of the output is the n-th face from `face` in `CUBEMAP` order.
+pub fn gpu_generate_latlut_inner(
+    gpu_device: &gpu::Gpu,
+    pipelines: &PipelineContext,
+    encoder: &mut wgpu::CommandEncoder,
+    face: &str,
+    layers: u32,
+    tile: &FaceTile,
+) -> Result<Texture> {
+    let format = wgpu::TextureFormat::Rgba8Unorm;
+    generate_face_lut(gpu_device, &pipelines.latlut, encoder, format, face, layers, tile)
 }
 
-pub async fn gpu_generate_latlut_inner(
+/// Signed latitude and longitude in degrees of every texel of a face, as a `Rg32Float`
+/// texture. See `lutgen::point_to_lat_lon` for the conventions.
+pub fn gpu_generate_geolut_inner(
     gpu_device: &gpu::Gpu,
+    pipelines: &PipelineContext,
+    encoder: &mut wgpu::CommandEncoder,
     face: &str,
     width: u32,
     height: u32,
-) -> Option<Texture> {
-    let device = &gpu_device.device;
-    let queue = &gpu_device.queue;
-
-    // Loads the shader from WGSL
-    let cs_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-        label: None,
-        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("latlutgen.wgsl"))),
-    });
+) -> Result<Texture> {
+    let format = wgpu::TextureFormat::Rg32Float;
+    let stage = pipelines.geolut.as_ref().ok_or_else(|| {
+        let adapter = gpu_device.adapter.get_info().name;
+        Error::UnsupportedFormat(format!("{:?} storage textures on {}", format, adapter))
+    })?;
+    let tile = FaceTile::whole(width, height);
+    generate_face_lut(gpu_device, stage, encoder, format, face, 1, &tile)
+}
 
-    let gen_params = LatLutGenParams::new(device, face_to_num(face), width, height);
-    let texture = Texture::new(
-        gpu_device,
-        width,
-        height,
-        wgpu::TextureFormat::Rgba8Unorm,
-        Some("LatLutGen Output"),
-    );
+/// Bindings of the shaders computing a texel from its position on the face alone.
+pub(crate) fn face_lut_bindings(format: wgpu::TextureFormat) -> [wgpu::BindingType; 2] {
+    [LatLutGenParams::binding_type(), output_binding(format)]
+}
 
-    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: Some("LatLutGen Bindings"),
-        entries: &[
-            // Face Number
-            wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: gen_params.binding_type(),
-                count: None,
-            },
-            // Output Texture
-            wgpu::BindGroupLayoutEntry {
-                binding: 1,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::StorageTexture {
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    format: wgpu::TextureFormat::Rgba8Unorm,
-                    access: wgpu::StorageTextureAccess::WriteOnly,
-                },
-                count: None,
-            },
-        ],
-    });
-    let compute_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: Some("latlutgen compute pipeline layout"),
-        bind_group_layouts: &[&bind_group_layout],
-        push_constant_ranges: &[],
-    });
-
-    let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-        label: None,
-        layout: Some(&compute_pipeline_layout),
-        module: &cs_module,
-        entry_point: "main",
-    });
-
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        layout: &bind_group_layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: gen_params.binding_resource(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: texture.binding_resource(),
-            },
-        ],
-    });
-
-    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-        label: Some("LatLutGen Encoder"),
-    });
-    {
-        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-            label: Some("LatLutGen Pass"),
-        });
-        cpass.set_pipeline(&compute_pipeline);
-        cpass.set_bind_group(0, &bind_group, &[]);
-        cpass.insert_debug_marker("compute latlutgen");
-        let xdim = width + WORKGROUP_SIZE.0 - 1;
-        let xgroups = xdim / WORKGROUP_SIZE.0;
-        let ydim = height + WORKGROUP_SIZE.1 - 1;
-        let ygroups = ydim / WORKGROUP_SIZE.1;
-
-        cpass.dispatch_workgroups(xgroups, ygroups, 1);
+// Runs a shader that computes a texel from its position on the face alone, over `tile`
+#[allow(clippy::too_many_arguments)]
+fn generate_face_lut(
+    gpu_device: &gpu::Gpu,
+    stage: &ComputeStage,
+    encoder: &mut wgpu::CommandEncoder,
+    format: wgpu::TextureFormat,
+    face: &str,
+    layers: u32,
+    tile: &FaceTile,
+) -> Result<Texture> {
+    let device = &gpu_device.device;
+    let face_num = face_to_num(face)?;
+    if face_num + layers > 6 {
+        return Err(Error::BadDefinition(format!("{} faces from {} run past the cube", layers, face)));
     }
+    let gen_params = LatLutGenParams::new(device, face_num, tile);
+    let texture = Texture::new_array(gpu_device, tile.width, tile.height, layers, format, Some("FaceLut Output"));
+
+    stage.dispatch(
+        device,
+        encoder,
+        &[gen_params.binding_resource(), texture.binding_resource()],
+        (tile.width, tile.height, layers),
+    );
 
-    queue.submit(Some(encoder.finish()));
-
-
-    Some(texture)
+    Ok(texture)
 }
 
 #[derive(Copy, Clone, Pod, Zeroable)]
@@ -131,6 +97,8 @@ struct _LatLutGenParams {
     face_num: u32,
     width: u32,
     height: u32,
+    origin_x: u32,
+    origin_y: u32,
 }
 
 struct LatLutGenParams {
@@ -138,11 +106,13 @@ struct LatLutGenParams {
 }
 
 impl LatLutGenParams {
-    pub fn new(device: &wgpu::Device, face_num: u32, width: u32, height: u32) -> Self {
+    pub fn new(device: &wgpu::Device, face_num: u32, tile: &FaceTile) -> Self {
         let params = _LatLutGenParams {
             face_num: face_num,
-            width: width,
-            height: height,
+            width: tile.face_width,
+            height: tile.face_height,
+            origin_x: tile.x,
+            origin_y: tile.y,
         };
         let param_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("parameters buffer"),
@@ -157,7 +127,7 @@ impl LatLutGenParams {
         self.param_buf.as_entire_binding()
     }
 
-    pub fn binding_type(&self) -> wgpu::BindingType {
+    pub fn binding_type() -> wgpu::BindingType {
         wgpu::BindingType::Buffer {
             ty: wgpu::BufferBindingType::Uniform,
             has_dynamic_offset: false,
@@ -166,102 +136,141 @@ impl LatLutGenParams {
     }
 }
 
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct _TerrainScaleParams {
+    physical: u32,
+    height_range: f32,
+    radius: f32,
+    pad: f32,
+}
+
+pub(crate) struct TerrainScaleParams {
+    param_buf: wgpu::Buffer,
+}
+
+impl TerrainScaleParams {
+    pub fn new(device: &wgpu::Device, scale: Option<&TerrainScale>) -> Self {
+        let params = _TerrainScaleParams {
+            physical: scale.is_some() as u32,
+            height_range: scale.map_or(0.0, |scale| scale.height_range()),
+            radius: scale.map_or(0.0, |scale| scale.radius),
+            pad: 0.0,
+        };
+        let param_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("TerrainScale parameters buffer"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        TerrainScaleParams { param_buf }
+    }
+
+    pub fn binding_resource(&self) -> wgpu::BindingResource<'_> {
+        self.param_buf.as_entire_binding()
+    }
+
+    pub fn binding_type() -> wgpu::BindingType {
+        wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: wgpu::BufferSize::new(mem::size_of::<_TerrainScaleParams>() as _),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct _FaceTileParams {
+    origin: [u32; 2],
+    face_size: [u32; 2],
+    halo: u32,
+    seamless: u32,
+}
+
+/// `tiling::FaceTile` for the stages reading a neighbourhood of the heightmap.
+pub(crate) struct FaceTileParams {
+    param_buf: wgpu::Buffer,
+}
+
+impl FaceTileParams {
+    pub fn new(device: &wgpu::Device, tile: &FaceTile) -> Self {
+        let params = _FaceTileParams {
+            origin: [tile.x, tile.y],
+            face_size: [tile.face_width, tile.face_height],
+            halo: tile.halo,
+            seamless: tile.seamless as u32,
+        };
+        let param_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("FaceTile parameters buffer"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        FaceTileParams { param_buf }
+    }
+
+    pub fn binding_resource(&self) -> wgpu::BindingResource<'_> {
+        self.param_buf.as_entire_binding()
+    }
+
+    pub fn binding_type() -> wgpu::BindingType {
+        wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: wgpu::BufferSize::new(mem::size_of::<_FaceTileParams>() as _),
+        }
+    }
+}
+
 // Slope Generation
 
-pub async fn gpu_generate_slope_inner(gpu_device: &gpu::Gpu, heightmap: &Texture) -> Option<Texture> {
+/// Bindings of `slopegen.wgsl`.
+pub(crate) fn slope_bindings() -> [wgpu::BindingType; 4] {
+    [
+        texture_binding(),
+        output_binding(wgpu::TextureFormat::Rgba8Unorm),
+        TerrainScaleParams::binding_type(),
+        FaceTileParams::binding_type(),
+    ]
+}
+
+/// Runs on `tile`, reading its halo around it from the heightmap. Without a scale heights are
+/// unitless, one step per texel.
+pub fn gpu_generate_slope_inner(
+    gpu_device: &gpu::Gpu,
+    pipelines: &PipelineContext,
+    encoder: &mut wgpu::CommandEncoder,
+    heightmap: &Texture,
+    tile: &FaceTile,
+    scale: Option<&TerrainScale>,
+) -> Result<Texture> {
     let device = &gpu_device.device;
-    let queue = &gpu_device.queue;
-    let width = heightmap.width();
-    let height = heightmap.height();
-    // Loads the shader from WGSL
-    let cs_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-        label: None,
-        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("slopegen.wgsl"))),
-    });
-
-    let texture = Texture::new(
+    check_size("Slope height map", tile.input_size(), (heightmap.width(), heightmap.height()))?;
+
+    let texture = Texture::new_array(
         gpu_device,
-        width,
-        height,
+        tile.width,
+        tile.height,
+        heightmap.layers(),
         wgpu::TextureFormat::Rgba8Unorm,
         Some("SlopeGen Output"),
     );
 
-    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: Some("SlopeGen Bindings"),
-        entries: &[
-            // Face Number
-            wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Texture {
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                    multisampled: false,
-                },
-                count: None,
-            },
-            // Output Texture
-            wgpu::BindGroupLayoutEntry {
-                binding: 1,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::StorageTexture {
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    format: wgpu::TextureFormat::Rgba8Unorm,
-                    access: wgpu::StorageTextureAccess::WriteOnly,
-                },
-                count: None,
-            },
-        ],
-    });
-
-    let compute_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: Some("slopegen compute pipeline layout"),
-        bind_group_layouts: &[&bind_group_layout],
-        push_constant_ranges: &[],
-    });
-
-    let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-        label: None,
-        layout: Some(&compute_pipeline_layout),
-        module: &cs_module,
-        entry_point: "main",
-    });
-
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        layout: &bind_group_layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: heightmap.binding_resource(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: texture.binding_resource(),
-            },
+    let scale_params = TerrainScaleParams::new(device, scale);
+    let tile_params = FaceTileParams::new(device, tile);
+
+    pipelines.slope.dispatch(
+        device,
+        encoder,
+        &[
+            heightmap.binding_resource(),
+            texture.binding_resource(),
+            scale_params.binding_resource(),
+            tile_params.binding_resource(),
         ],
-    });
-
-    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-        label: Some("SlopeGen Encoder"),
-    });
-    {
-        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-            label: Some("SlopeGen Pass"),
-        });
-        cpass.set_pipeline(&compute_pipeline);
-        cpass.set_bind_group(0, &bind_group, &[]);
-        cpass.insert_debug_marker("compute slopegen");
-        let xdim = width + WORKGROUP_SIZE.0 - 1;
-        let xgroups = xdim / WORKGROUP_SIZE.0;
-        let ydim = height + WORKGROUP_SIZE.1 - 1;
-        let ygroups = ydim / WORKGROUP_SIZE.1;
-
-        cpass.dispatch_workgroups(xgroups, ygroups, 1);
-    }
-
-    queue.submit(Some(encoder.finish()));
+        (tile.width, tile.height, heightmap.layers()),
+    );
 
-    Some(texture)
+    Ok(texture)
 }
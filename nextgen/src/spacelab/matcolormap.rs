@@ -1,6 +1,5 @@
 use std::collections::HashMap;
 
-use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 
 use super::{coloravg::MatColorAverage, matfile::MatFile};
@@ -8,34 +7,7 @@ use super::{coloravg::MatColorAverage, matfile::MatFile};
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct PlanetMaterials(pub HashMap<String, PlanetMaterial>);
 
-lazy_static! {
-    pub static ref ORE_COLORS: HashMap<String, (u8, u8, u8)> = {
-        let mut ore_colors = HashMap::new();
-        ore_colors.insert("Iron_01".to_string(), (255, 216, 0));
-        ore_colors.insert("Iron_02".to_string(), (255, 216, 0));
-        ore_colors.insert("Nickel_01".to_string(), (239, 166, 117));
-        ore_colors.insert("Silicon_01".to_string(), (216, 107, 128));
-        ore_colors.insert("Magnesium_01".to_string(), (0, 255, 255));
-        ore_colors.insert("Cobalt_01".to_string(), (181, 254, 0));
-        ore_colors.insert("Silver_01".to_string(), (145, 145, 145));
-        ore_colors.insert("Gold_01".to_string(), (255, 0, 220));
-        ore_colors.insert("Platinum_01".to_string(), (219, 249, 255));
-        ore_colors.insert("Uraninite_01".to_string(), (155, 114, 241));
-        ore_colors.insert("Copper".to_string(), (184, 115, 51));
-        ore_colors.insert("Bauxite".to_string(), (249, 166, 64));
-        ore_colors.insert("Coal".to_string(), (145, 145, 145));
-        ore_colors.insert("Titanium".to_string(), (81, 127, 84));
-        ore_colors.insert("OilSand".to_string(), (183, 0, 3));
-        ore_colors.insert("Sulfur".to_string(), (190, 167, 151));
-        ore_colors.insert("Lithium".to_string(), (172, 80, 141));
-        ore_colors.insert("Tantalum".to_string(), (255, 0, 0));
-        ore_colors.insert("Cronyx".to_string(), (184, 115, 51));
-        ore_colors.insert("Dorium".to_string(), (181, 254, 0));
-        ore_colors
-    };
-}
-
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct OreMap {
     #[serde(rename = "Value")]
     pub value: Option<u32>,
@@ -66,6 +38,12 @@ pub struct PlanetMaterial {
     pub ores: HashMap<String, OreMap>,
     #[serde(rename = "BaseFolder")]
     pub base_path: String,
+    /// `SurfaceDetail` transition in degrees
+    #[serde(rename = "Transition", default, skip_serializing_if = "Option::is_none")]
+    pub transition: Option<f32>,
+    /// `HillParams` min and max, as fractions of the radius
+    #[serde(rename = "HillParams", default, skip_serializing_if = "Option::is_none")]
+    pub hill_params: Option<[f32; 2]>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -82,6 +60,39 @@ pub struct MaterialLayer {
     pub depth: Option<u8>,
 }
 
+/// How the complex material rules are drawn on the albedo.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MaterialView {
+    /// Metres dug under the surface, 0 draws the surface layer
+    pub depth: f32,
+    /// Slope in degrees above which the first layer is worn away, showing what lies under it
+    pub exposed_slope: Option<f32>,
+    /// Blend neighbouring rules instead of cutting at their range edges
+    pub blend: Option<MaterialBlend>,
+}
+
+/// Widths of the transitions between neighbouring rules, centered on the range edges.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialBlend {
+    /// In heightmap units, 0 to 1
+    pub height: f32,
+    /// In degrees
+    pub latitude: f32,
+    /// In degrees
+    pub slope: f32,
+}
+
+impl MaterialBlend {
+    /// Uses the `SurfaceDetail` transition of the planet for the angles, like the game does.
+    pub fn from_transition(transition: f32, height: f32) -> Self {
+        MaterialBlend {
+            height,
+            latitude: transition,
+            slope: transition,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct VoxelMaterial {
     pub id: i32,
@@ -127,6 +138,22 @@ impl MaterialRule {
         None // No layers exist
     }
 
+    /// Layer found `depth` metres under the surface. Layer depths are thicknesses stacked
+    /// from the surface down, and the last layer goes on below them.
+    pub fn get_layer_at_depth(&self, depth: f32) -> Option<&MaterialLayer> {
+        if depth <= 0.0 {
+            return self.layers.first();
+        }
+        let mut bottom = 0.0;
+        for layer in &self.layers {
+            bottom += layer.depth.unwrap_or_default() as f32;
+            if depth < bottom {
+                return Some(layer);
+            }
+        }
+        self.layers.last()
+    }
+
     pub fn get_lowest_depth_color(&self) -> Option<(u8, u8, u8)> {
         let mut lowest_layer: Option<&MaterialLayer> = None;
         for layer in &self.layers {
@@ -258,3 +285,42 @@ impl PlanetMaterial {
         }
     }
 }
+
+/// Small planet for the stage tests: complex material 16 is grass low and stone high, 100
+/// is snow, every other id falls back to rock. Ores 200 and 220 are iron and nickel.
+#[cfg(test)]
+pub(crate) fn test_planet() -> PlanetMaterial {
+    let layer = |r: u8, g: u8, b: u8, material: &str, depth: u8| {
+        serde_json::json!({"R": r, "G": g, "B": b, "Material": material, "Depth": depth})
+    };
+    let rule = |heights: [f32; 2], slopes: [f32; 2], layers: Vec<serde_json::Value>| {
+        serde_json::json!({
+            "MinHeight": heights[0], "MaxHeight": heights[1],
+            "LatitudeMin": 0.0, "LatitudeMax": 90.0,
+            "SlopeMin": slopes[0], "SlopeMax": slopes[1],
+            "Layers": layers,
+        })
+    };
+    let ore = |value: u32, ore_type: &str| {
+        serde_json::json!({
+            "Value": value, "Type": ore_type, "Start": 3, "Depth": 7,
+            "TargetColor": [255, 255, 255], "ColorInfluence": 15,
+        })
+    };
+    let planet = serde_json::json!({
+        "Name": "Test",
+        "BaseFolder": "",
+        "DefaultMaterial": layer(90, 80, 70, "Rocks", 0),
+        "SimpleMaterials": {"100": layer(230, 235, 240, "Snow", 0)},
+        "ComplexMaterials": {"16": {"id": 16, "name": "Hills", "rules": [
+            rule(
+                [0.0, 0.5],
+                [0.0, 30.0],
+                vec![layer(40, 160, 40, "Grass", 1), layer(120, 90, 60, "Soil", 4)],
+            ),
+            rule([0.5, 1.0], [0.0, 90.0], vec![layer(150, 150, 150, "Stone", 3)]),
+        ]}},
+        "Ores": {"200": ore(200, "Iron_02"), "220": ore(220, "Nickel_01")},
+    });
+    serde_json::from_value(planet).unwrap()
+}
@@ -0,0 +1,189 @@
+// Rust version of tools/matcolormapgen.py
+
+use std::collections::HashMap;
+
+use crate::{
+    error::{Error, Result},
+    spaceengineers::{
+        material::{Layer, MaterialGroup, Rule},
+        oremapping::Ore,
+        planet_generator_definition::PlanetGeneratorDefinition,
+    },
+};
+
+use super::{
+    coloravg::MatColorAverage,
+    matcolormap::{MaterialLayer, MaterialRule, OreMap, PlanetMaterial, VoxelMaterial},
+    matfile::MatFile,
+};
+
+/// Builds the material color map entry of a planet, with every layer color
+/// looked up from the material files and their average colors.
+///
+/// `base_path` is the folder holding the planet heightmaps and material maps,
+/// the same value the Python tool stores as `BaseFolder`.
+pub fn planet_material_from_definition(
+    definition: &PlanetGeneratorDefinition,
+    base_path: &str,
+    matfiles: &MatFile,
+    matcoloravg: &MatColorAverage,
+) -> Result<PlanetMaterial> {
+    let name = definition.id.subtype_id.clone();
+
+    let mut complex_materials = HashMap::new();
+    for group in definition.complex_materials.material_groups.iter().flatten() {
+        let material = voxel_material(group)
+            .map_err(|e| Error::BadDefinition(format!("{} ComplexMaterials: {}", name, e)))?;
+        complex_materials.insert(material.id.to_string(), material);
+    }
+
+    let mut simple_materials = HashMap::new();
+    let custom_materials = definition
+        .custom_material_table
+        .iter()
+        .flat_map(|table| table.materials.iter().flatten());
+    for material in custom_materials {
+        let (value, material) = match (material.value, &material.material) {
+            (Some(value), Some(material)) => (value, material),
+            _ => {
+                return Err(Error::BadDefinition(format!(
+                    "{} CustomMaterialTable: Material needs Value and Material",
+                    name
+                )))
+            }
+        };
+        simple_materials.insert(value.to_string(), material_layer(material, Some(0)));
+    }
+
+    let default_material = match &definition.default_surface_material.material {
+        Some(material) => material_layer(material, Some(0)),
+        None => material_layer("", Some(0)),
+    };
+
+    let mut ores = HashMap::new();
+    for ore in definition.ore_mappings.ores.iter().flatten() {
+        let (value, ore) = ore_map(ore)
+            .map_err(|e| Error::BadDefinition(format!("{} OreMappings: {}", name, e)))?;
+        ores.insert(value.to_string(), ore);
+    }
+
+    let mut planet = PlanetMaterial {
+        name,
+        default_material,
+        simple_materials,
+        complex_materials,
+        ores,
+        base_path: base_path.to_owned(),
+        transition: Some(definition.surface_detail.transition as f32),
+        hill_params: Some([definition.hill_params.min, definition.hill_params.max]),
+    };
+    planet.cache(matfiles, matcoloravg);
+
+    Ok(planet)
+}
+
+fn voxel_material(group: &MaterialGroup) -> std::result::Result<VoxelMaterial, String> {
+    let id = group.value.ok_or("MaterialGroup without Value")?;
+    Ok(VoxelMaterial {
+        id: id as i32,
+        name: group.name.clone().unwrap_or_default(),
+        rules: group.rules.iter().flatten().map(material_rule).collect::<std::result::Result<_, _>>()?,
+    })
+}
+
+// Missing ranges fall back to the widest range the material map can express
+fn material_rule(rule: &Rule) -> std::result::Result<MaterialRule, String> {
+    let layers = rule
+        .layers
+        .iter()
+        .flat_map(|layers| layers.layers.iter().flatten())
+        .filter_map(|layer: &Layer| Some((layer.material.as_deref()?, layer.depth)))
+        .map(|(material, depth)| {
+            let depth = match depth {
+                Some(depth) => match u8::try_from(depth) {
+                    Ok(depth) => Some(depth),
+                    Err(_) => return Err(format!("Layer {} Depth {} over 255", material, depth)),
+                },
+                None => None,
+            };
+            Ok(material_layer(material, depth))
+        })
+        .collect::<std::result::Result<_, String>>()?;
+
+    Ok(MaterialRule {
+        layers,
+        min_height: rule.height.as_ref().map_or(0.0, |h| h.min),
+        max_height: rule.height.as_ref().map_or(1.0, |h| h.max),
+        latitude_min: rule.latitude.as_ref().map_or(0.0, |l| l.min),
+        latitude_max: rule.latitude.as_ref().map_or(90.0, |l| l.max),
+        slope_min: rule.slope.as_ref().map_or(0.0, |s| s.min),
+        slope_max: rule.slope.as_ref().map_or(90.0, |s| s.max),
+    })
+}
+
+// Colors are filled in by `PlanetMaterial::cache`
+fn material_layer(material: &str, depth: Option<u8>) -> MaterialLayer {
+    MaterialLayer {
+        r: 0,
+        g: 0,
+        b: 0,
+        material: material.to_owned(),
+        depth,
+    }
+}
+
+// Returns the ore value with its map entry
+fn ore_map(ore: &Ore) -> std::result::Result<(u32, OreMap), String> {
+    let value = ore.value.ok_or("Ore without Value")?;
+    if ore.ore_type.is_none() {
+        return Err(format!("Ore {} without Type", value));
+    }
+    let target_color = match &ore.target_color {
+        Some(color) => parse_color(color).ok_or(format!("Ore {} TargetColor {}", value, color))?,
+        None => [0, 0, 0],
+    };
+
+    let map = OreMap {
+        value: Some(value),
+        ore_type: ore.ore_type.clone(),
+        start: ore.start,
+        depth: ore.depth,
+        target_color: Some(target_color),
+        color_influence: Some(ore.color_influence.unwrap_or(0)),
+    };
+    Ok((value, map))
+}
+
+/// Parses a `#RRGGBB` color.
+fn parse_color(color: &str) -> Option<[u32; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u32::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spaceengineers::material::Layers;
+
+    use super::*;
+
+    fn rule(depths: &[Option<u32>]) -> Rule {
+        let layers = depths
+            .iter()
+            .map(|&depth| Layer { material: Some("Grass".to_owned()), depth })
+            .collect();
+        Rule { layers: Some(Layers { layers: Some(layers) }), height: None, latitude: None, slope: None }
+    }
+
+    #[test]
+    fn layer_depths_are_kept_or_rejected() {
+        let layers = material_rule(&rule(&[Some(3), None, Some(255)])).unwrap().layers;
+        let depths: Vec<Option<u8>> = layers.iter().map(|layer| layer.depth).collect();
+        assert_eq!(depths, [Some(3), None, Some(255)]);
+
+        assert!(material_rule(&rule(&[Some(256)])).is_err());
+    }
+}
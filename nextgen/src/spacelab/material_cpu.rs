@@ -0,0 +1,203 @@
+// CPU version of materialgen.wgsl
+
+use crate::{
+    cpu::texture::CpuTexture,
+    error::{check_size, Result},
+};
+
+use super::{
+    material_gpu::GPUMaterialRule,
+    matcolormap::{MaterialBlend, MaterialView, PlanetMaterial},
+};
+
+// Degrees of one 8 bit step of the slope map and latitude LUT, the same f32 as materialgen.wgsl
+const DEGREES_PER_STEP: f32 = 90.0 / 255.0;
+
+// Slopes and latitudes are compared as whole 8 bit steps, so both backends see the same degrees
+// and texels on the edge of a rule land on the same side of it.
+fn texel_degrees(value: f32) -> f32 {
+    (value * 255.0).round() * DEGREES_PER_STEP
+}
+
+fn material_match(rule: &GPUMaterialRule, height: f32, latitude: f32, slope: f32) -> bool {
+    if height < rule.height[0] || height > rule.height[1] {
+        return false;
+    }
+
+    if latitude < rule.latitude[0] || latitude > rule.latitude[1] {
+        return false;
+    }
+
+    if slope < rule.slope[0] || slope > rule.slope[1] {
+        return false;
+    }
+
+    true
+}
+
+// 1 inside the range, fading to 0 over a transition width centered on its edges. Edges at
+// the ends of the domain don't fade, since nothing lies past them.
+fn range_weight(x: f32, range: [f32; 2], width: f32, domain: [f32; 2]) -> f32 {
+    if width <= 0.0 {
+        return if x >= range[0] && x <= range[1] { 1.0 } else { 0.0 };
+    }
+    let mut weight = 1.0;
+    if range[0] > domain[0] {
+        weight *= ((x - range[0]) / width + 0.5).clamp(0.0, 1.0);
+    }
+    if range[1] < domain[1] {
+        weight *= ((range[1] - x) / width + 0.5).clamp(0.0, 1.0);
+    }
+    weight
+}
+
+fn rule_weight(rule: &GPUMaterialRule, blend: &MaterialBlend, height: f32, latitude: f32, slope: f32) -> f32 {
+    range_weight(height, rule.height, blend.height, [0.0, 1.0])
+        * range_weight(latitude, rule.latitude, blend.latitude, [0.0, 90.0])
+        * range_weight(slope, rule.slope, blend.slope, [0.0, 90.0])
+}
+
+pub fn generate_material_cpu(
+    materialmap: &CpuTexture,
+    heightmap: &CpuTexture,
+    latlut: &CpuTexture,
+    normalmap: &CpuTexture,
+    slopemap: &CpuTexture,
+    materials: &PlanetMaterial,
+    view: &MaterialView,
+) -> Result<CpuTexture> {
+    // LatLut, Heightmap, Slope Map and Material Map must be the same size
+    let width = heightmap.width();
+    let height = heightmap.height();
+    check_size("LatLut", (width, height), (latlut.width(), latlut.height()))?;
+    check_size("Normal Map", (width, height), (normalmap.width(), normalmap.height()))?;
+    check_size("Material Map", (width, height), (materialmap.width(), materialmap.height()))?;
+    check_size("Slope Map", (width, height), (slopemap.width(), slopemap.height()))?;
+
+    let complex_materials = materials.complex_materials_to_gpu(view);
+    let simple_materials = materials.simple_materials_to_gpu()?;
+    let default_materials = materials.default_material_to_gpu();
+
+    let mut texture = CpuTexture::new(width, height, wgpu::TextureFormat::Rgba8Unorm)?;
+
+    // Color of texels no complex rule covers
+    let fallback_color = |id: u32| {
+        if let Some(rule) = simple_materials.iter().find(|rule| rule.id == id) {
+            return rule.color;
+        }
+
+        match default_materials.first() {
+            Some(rule) => rule.color,
+            None => [0.0, 0.0, 0.0, 1.0], // Not found
+        }
+    };
+
+    texture.fill(|x, y| {
+        let id = (materialmap.load(x, y)[0] * 255.0).round() as u32;
+        let h = heightmap.load(x, y)[0];
+        let slope = texel_degrees(slopemap.load(x, y)[0]);
+        let lat = texel_degrees(latlut.load(x, y)[0]);
+
+        if let Some(blend) = &view.blend {
+            // Rules are layered in order, each covering its weight of what the earlier ones left
+            let mut color = [0.0; 4];
+            let mut remaining = 1.0;
+            for rule in complex_materials.iter().filter(|rule| rule.id == id) {
+                let weight = rule_weight(rule, blend, h, lat, slope) * remaining;
+                for (c, rule_c) in color.iter_mut().zip(rule.color) {
+                    *c += rule_c * weight;
+                }
+                remaining -= weight;
+            }
+            for (c, fallback_c) in color.iter_mut().zip(fallback_color(id)) {
+                *c += fallback_c * remaining;
+            }
+            return color;
+        }
+
+        if let Some(rule) = complex_materials
+            .iter()
+            .find(|rule| rule.id == id && material_match(rule, h, lat, slope))
+        {
+            return rule.color;
+        }
+
+        fallback_color(id)
+    });
+
+    Ok(texture)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spacelab::matcolormap::test_planet;
+
+    use super::*;
+
+    fn texture(format: wgpu::TextureFormat, texels: &[[f32; 4]]) -> CpuTexture {
+        let mut texture = CpuTexture::new(texels.len() as u32, 1, format).unwrap();
+        texture.fill(|x, _| texels[x as usize]);
+        texture
+    }
+
+    fn colors(texture: &CpuTexture) -> Vec<[u8; 3]> {
+        (0..texture.width())
+            .map(|x| texture.load(x, 0))
+            .map(|c| [c[0], c[1], c[2]].map(|c| (c * 255.0).round() as u8))
+            .collect()
+    }
+
+    fn material(view: &MaterialView) -> Vec<[u8; 3]> {
+        let rgba = wgpu::TextureFormat::Rgba8Unorm;
+        let id = |id: u32| [id as f32 / 255.0, 0.0, 0.0, 1.0];
+        let materialmap = texture(rgba, &[id(16), id(16), id(16), id(100), id(7)]);
+        let heights = [0.2, 0.2, 0.8, 0.2, 0.2].map(|h| [h, 0.0, 0.0, 0.0]);
+        let heightmap = texture(wgpu::TextureFormat::R32Float, &heights);
+        let latlut = texture(rgba, &[[0.5, 0.5, 0.5, 1.0]; 5]);
+        let normalmap = texture(rgba, &[[0.5, 0.5, 1.0, 1.0]; 5]);
+        // Slopes of 10 and 60 degrees
+        let slope = |degrees: f32| [degrees / 90.0, 0.0, 0.0, 1.0];
+        let slopemap = texture(rgba, &[slope(10.0), slope(60.0), slope(10.0), slope(10.0), slope(10.0)]);
+
+        let planet = test_planet();
+        let output = generate_material_cpu(
+            &materialmap,
+            &heightmap,
+            &latlut,
+            &normalmap,
+            &slopemap,
+            &planet,
+            view,
+        );
+        colors(&output.unwrap())
+    }
+
+    #[test]
+    fn rules_pick_their_first_layer() {
+        let grass = [40, 160, 40];
+        let rocks = [90, 80, 70];
+        let stone = [150, 150, 150];
+        let snow = [230, 235, 240];
+        // The steep grass texel matches no rule and falls back to the default material
+        assert_eq!(material(&MaterialView::default()), [grass, rocks, stone, snow, rocks]);
+    }
+
+    #[test]
+    fn digging_reaches_the_layers_under_the_surface() {
+        let view = MaterialView {
+            depth: 2.0,
+            ..MaterialView::default()
+        };
+        assert_eq!(material(&view)[0], [120, 90, 60]);
+        // Stone is 3 metres thick
+        assert_eq!(material(&view)[2], [150, 150, 150]);
+    }
+
+    #[test]
+    fn sizes_must_match() {
+        let small = CpuTexture::new(2, 2, wgpu::TextureFormat::Rgba8Unorm).unwrap();
+        let large = CpuTexture::new(4, 4, wgpu::TextureFormat::Rgba8Unorm).unwrap();
+        let (planet, view) = (test_planet(), MaterialView::default());
+        assert!(generate_material_cpu(&small, &large, &large, &large, &large, &planet, &view).is_err());
+    }
+}
@@ -1,18 +1,23 @@
-use std::{borrow::Cow, mem};
+use std::mem;
 
 use bevy::log;
 use bytemuck::{Pod, Zeroable};
 use wgpu::util::DeviceExt;
 
-use crate::gpu::{self, gpu::Gpu, texture::Texture};
+use crate::{
+    error::{check_layers, check_size, Error, Result},
+    gpu::{gpu::Gpu, texture::Texture},
+};
 
-use super::matcolormap::{PlanetMaterial, ORE_COLORS};
-
-const WORKGROUP_SIZE: (u32, u32) = (8, 8);
+use super::{
+    matcolormap::{MaterialBlend, MaterialLayer, MaterialRule, MaterialView, PlanetMaterial},
+    orepalette::OrePalette,
+    pipeline_gpu::{output_binding, texture_binding, PipelineContext},
+};
 
 #[derive(Copy, Clone, Pod, Zeroable, Default)]
 #[repr(C)]
-struct GPUMaterialRule {
+pub(crate) struct GPUMaterialRule {
     pub id: u32,
     pad0: [f32; 3], // vec4 is aligned at 16 byte boundary, so we
     pub color: [f32; 4],
@@ -25,34 +30,70 @@ struct GPUMaterialRule {
 
 #[derive(Copy, Clone, Pod, Zeroable, Default)]
 #[repr(C)]
-struct GPUOreMap {
+pub(crate) struct GPUOreMap {
     pub id: u32,
     pad0: [f32; 3], // vec4 is aligned at 16 byte boundary, so we
     pub color : [f32; 4],
 }
 
+fn rule_to_gpu(id: i32, rule: &MaterialRule, layer: &MaterialLayer, slope: [f32; 2]) -> GPUMaterialRule {
+    GPUMaterialRule {
+        id: id as u32,
+        color: [
+            (layer.r as f32) / 255.0,
+            (layer.g as f32) / 255.0,
+            (layer.b as f32) / 255.0,
+            1.0,
+        ],
+        height: [rule.min_height, rule.max_height],
+        latitude: [rule.latitude_min, rule.latitude_max],
+        slope,
+        ..GPUMaterialRule::default()
+    }
+}
+
 impl PlanetMaterial {
     // Generate a list of the complex materials for the GPU.
-    fn complex_materials_to_gpu(&self) -> Vec<GPUMaterialRule> {
+    //
+    // Layers are picked here, since the view is the same for the whole face. With an exposed
+    // slope a rule is split in two on the slope, the steep half showing the layer under the first.
+    pub(crate) fn complex_materials_to_gpu(&self, view: &MaterialView) -> Vec<GPUMaterialRule> {
         let mut gpu_materials = Vec::new();
-        for (_, material) in &self.complex_materials {
+        for material in self.complex_materials.values() {
             for rule in &material.rules {
-                gpu_materials.push(GPUMaterialRule {
-                    id: material.id as u32,
-                    color: [
-                        (rule.layers[0].r as f32) / 255.0,
-                        (rule.layers[0].g as f32) / 255.0,
-                        (rule.layers[0].b as f32) / 255.0,
-                        1.0,
-                    ],
-                    height: [rule.min_height, rule.max_height],
-                    latitude: [rule.latitude_min, rule.latitude_max],
-                    slope: [rule.slope_min, rule.slope_max],
-                    ..GPUMaterialRule::default()
-                });
+                let layer = match rule.get_layer_at_depth(view.depth) {
+                    Some(layer) => layer,
+                    None => continue, // Nothing to draw
+                };
+                match view.exposed_slope {
+                    Some(exposed) if exposed < rule.slope_max => {
+                        let worn = rule.layers[0].depth.unwrap_or_default() as f32;
+                        let under = rule.get_layer_at_depth(view.depth + worn).unwrap_or(layer);
+                        if exposed > rule.slope_min {
+                            gpu_materials.push(rule_to_gpu(
+                                material.id,
+                                rule,
+                                layer,
+                                [rule.slope_min, exposed],
+                            ));
+                        }
+                        gpu_materials.push(rule_to_gpu(
+                            material.id,
+                            rule,
+                            under,
+                            [exposed.max(rule.slope_min), rule.slope_max],
+                        ));
+                    }
+                    _ => gpu_materials.push(rule_to_gpu(
+                        material.id,
+                        rule,
+                        layer,
+                        [rule.slope_min, rule.slope_max],
+                    )),
+                }
             }
         }
-        if gpu_materials.len() == 0 {
+        if gpu_materials.is_empty() {
             // Add dummy so it doesn't break shaders
             gpu_materials.push(GPUMaterialRule {
                 id: 999, // Materials IDs are only up to 255
@@ -67,11 +108,14 @@ impl PlanetMaterial {
         gpu_materials
     }
 
-    fn simple_materials_to_gpu(&self) -> Vec<GPUMaterialRule> {
+    pub(crate) fn simple_materials_to_gpu(&self) -> Result<Vec<GPUMaterialRule>> {
         let mut gpu_materials = Vec::new();
         for (id, material) in &self.simple_materials {
+            let id = id.parse::<u32>().map_err(|_| {
+                Error::BadDefinition(format!("{}: simple material id {} is not a number", self.name, id))
+            })?;
             gpu_materials.push(GPUMaterialRule {
-                id: id.parse::<u32>().unwrap(),
+                id,
                 color: [
                     (material.r as f32) / 255.0,
                     (material.g as f32) / 255.0,
@@ -96,10 +140,10 @@ impl PlanetMaterial {
                 ..GPUMaterialRule::default()
             });
         }
-        gpu_materials
+        Ok(gpu_materials)
     }
 
-    fn default_material_to_gpu(&self) -> Vec<GPUMaterialRule> {
+    pub(crate) fn default_material_to_gpu(&self) -> Vec<GPUMaterialRule> {
         let mut gpu_materials = Vec::new();
         let material = &self.default_material;
         gpu_materials.push(GPUMaterialRule {
@@ -119,29 +163,40 @@ impl PlanetMaterial {
         gpu_materials
     }
 
-    fn ore_map_to_gpu(&self) -> Vec<GPUOreMap> {
+    pub(crate) fn ore_map_to_gpu(&self, palette: &OrePalette) -> Result<Vec<GPUOreMap>> {
         let mut gpu_ores = Vec::new();
 
         for (id, ore) in &self.ores {
-            let ore_name = ore.ore_type.clone().unwrap();
-            if !ORE_COLORS.contains_key(&ore_name) {
-                println!("Ore {} has no color mapping", ore_name);
-                continue;
-            }
-
-            let color = ORE_COLORS.get(&ore_name).unwrap();
+            let (ore_name, value) = match (&ore.ore_type, ore.value) {
+                (Some(ore_name), Some(value)) => (ore_name, value),
+                _ => {
+                    return Err(Error::BadDefinition(format!(
+                        "{}: ore {} has no type or value",
+                        self.name, id
+                    )))
+                }
+            };
+            let color = palette.get(ore_name).color;
             gpu_ores.push(GPUOreMap {
-                id: ore.value.unwrap() as u32,
+                id: value,
                 color: [
-                    (color.0 as f32) / 255.0,
-                    (color.1 as f32) / 255.0,
-                    (color.2 as f32) / 255.0,
+                    (color[0] as f32) / 255.0,
+                    (color[1] as f32) / 255.0,
+                    (color[2] as f32) / 255.0,
                     1.0,
                 ],
                 ..GPUOreMap::default()
             });
         }
-        gpu_ores
+        if gpu_ores.is_empty() {
+            // Add dummy so it doesn't break shaders
+            gpu_ores.push(GPUOreMap {
+                id: 999, // Ore values are only up to 255
+                color: [0.0, 0.0, 0.0, 0.0],
+                ..GPUOreMap::default()
+            });
+        }
+        Ok(gpu_ores)
     }
 }
 
@@ -167,7 +222,7 @@ impl MaterialRuleData {
         self.param_buf.as_entire_binding()
     }
 
-    pub fn binding_type(&self) -> wgpu::BindingType {
+    pub fn binding_type() -> wgpu::BindingType {
         wgpu::BindingType::Buffer {
             ty: wgpu::BufferBindingType::Storage { read_only: true },
             has_dynamic_offset: false,
@@ -177,8 +232,56 @@ impl MaterialRuleData {
 }
 
 
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+struct _MaterialGenParams {
+    blend: u32,
+    height_width: f32,
+    latitude_width: f32,
+    slope_width: f32,
+}
+
+struct MaterialGenParams {
+    param_buf: wgpu::Buffer,
+}
+
+impl MaterialGenParams {
+    pub fn new(device: &wgpu::Device, view: &MaterialView) -> Self {
+        let blend = view.blend.unwrap_or(MaterialBlend {
+            height: 0.0,
+            latitude: 0.0,
+            slope: 0.0,
+        });
+        let params = _MaterialGenParams {
+            blend: view.blend.is_some() as u32,
+            height_width: blend.height,
+            latitude_width: blend.latitude,
+            slope_width: blend.slope,
+        };
+        let param_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("MaterialGen parameters buffer"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        MaterialGenParams { param_buf }
+    }
+
+    pub fn binding_resource(&self) -> wgpu::BindingResource<'_> {
+        self.param_buf.as_entire_binding()
+    }
+
+    pub fn binding_type() -> wgpu::BindingType {
+        wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: wgpu::BufferSize::new(mem::size_of::<_MaterialGenParams>() as _),
+        }
+    }
+}
+
 #[derive(Debug)]
-struct OreMapData {
+pub(crate) struct OreMapData {
     param_buf: wgpu::Buffer,
 }
 
@@ -199,7 +302,7 @@ impl OreMapData {
         self.param_buf.as_entire_binding()
     }
 
-    pub fn binding_type(&self) -> wgpu::BindingType {
+    pub fn binding_type() -> wgpu::BindingType {
         wgpu::BindingType::Buffer {
             ty: wgpu::BufferBindingType::Storage { read_only: true },
             has_dynamic_offset: false,
@@ -209,42 +312,62 @@ impl OreMapData {
 }
 
 
-pub async fn generate_material_gpu(
+/// Bindings of `materialgen.wgsl`.
+pub(crate) fn material_bindings() -> [wgpu::BindingType; 11] {
+    [
+        // Default, simple and complex materials
+        MaterialRuleData::binding_type(),
+        MaterialRuleData::binding_type(),
+        MaterialRuleData::binding_type(),
+        OreMapData::binding_type(),
+        // Material map, height map, latlut, normal and slope
+        texture_binding(),
+        texture_binding(),
+        texture_binding(),
+        texture_binding(),
+        texture_binding(),
+        output_binding(wgpu::TextureFormat::Rgba8Unorm),
+        MaterialGenParams::binding_type(),
+    ]
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn generate_material_gpu(
     gpu_device: &Gpu,
+    pipelines: &PipelineContext,
+    encoder: &mut wgpu::CommandEncoder,
     materialmap: &Texture,
     heightmap: &Texture,
     latlut: &Texture,
     normalmap: &Texture,
     slopemap: &Texture,
     materials: &PlanetMaterial,
-) -> Option<Texture> {
+    view: &MaterialView,
+) -> Result<Texture> {
     let device = &gpu_device.device;
-    let queue = &gpu_device.queue;
 
+    // LatLut, Heightmap, Slope Map and Material Map must be the same size
     let width = heightmap.width();
     let height = heightmap.height();
-    if latlut.width() != width
-        || latlut.height() != height
-        || normalmap.width() != width
-        || normalmap.height() != height
-        || materialmap.width() != width
-        || materialmap.height() != height
-        || slopemap.width() != width
-        || slopemap.height() != height
-    {
-        panic!("LatLut, Heightmap, Slope Map and Material Map must be the same size!");
+    check_size("LatLut", (width, height), (latlut.width(), latlut.height()))?;
+    check_size("Normal Map", (width, height), (normalmap.width(), normalmap.height()))?;
+    check_size("Material Map", (width, height), (materialmap.width(), materialmap.height()))?;
+    check_size("Slope Map", (width, height), (slopemap.width(), slopemap.height()))?;
+    let layers = heightmap.layers();
+    for (name, texture) in [
+        ("LatLut", latlut),
+        ("Normal Map", normalmap),
+        ("Material Map", materialmap),
+        ("Slope Map", slopemap),
+    ] {
+        check_layers(name, layers, texture.layers())?;
     }
 
-    // Loads the shader from WGSL
-    let cs_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-        label: None,
-        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("materialgen.wgsl"))),
-    });
-
-    let texture = Texture::new(
+    let texture = Texture::new_array(
         gpu_device,
         width,
         height,
+        layers,
         wgpu::TextureFormat::Rgba8Unorm,
         Some("PlanetMaterial Generator Output"),
     );
@@ -252,194 +375,39 @@ pub async fn generate_material_gpu(
     let complex_materials = MaterialRuleData::new(
         device,
         "ComplexMaterials",
-        materials.complex_materials_to_gpu(),
+        materials.complex_materials_to_gpu(view),
     );
     let simple_materials =
-        MaterialRuleData::new(device, "SimpleMaterials", materials.simple_materials_to_gpu());
+        MaterialRuleData::new(device, "SimpleMaterials", materials.simple_materials_to_gpu()?);
     let default_materials =
         MaterialRuleData::new(device, "DefaultMaterials", materials.default_material_to_gpu());
 
-    let ore_mapping = OreMapData::new(device, "OreMapping", materials.ore_map_to_gpu());
-
-    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: Some("PlanetMaterial Generator Bindings"),
-        entries: &[
-            // Default Materials
-            wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: default_materials.binding_type(),
-                count: None,
-            },
-            // Simple Materials
-            wgpu::BindGroupLayoutEntry {
-                binding: 1,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: simple_materials.binding_type(),
-                count: None,
-            },
-            // Simple Materials
-            wgpu::BindGroupLayoutEntry {
-                binding: 2,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: complex_materials.binding_type(),
-                count: None,
-            },
-            // Ore Mapping
-            wgpu::BindGroupLayoutEntry {
-                binding: 3,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: ore_mapping.binding_type(),
-                count: None,
-            },
-            // Material Map
-            wgpu::BindGroupLayoutEntry {
-                binding: 4,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Texture {
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                    multisampled: false,
-                },
-                count: None,
-            },
-            // Height Map
-            wgpu::BindGroupLayoutEntry {
-                binding: 5,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Texture {
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                    multisampled: false,
-                },
-                count: None,
-            },
-            // LatLut
-            wgpu::BindGroupLayoutEntry {
-                binding: 6,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Texture {
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                    multisampled: false,
-                },
-                count: None,
-            },
-            // normal
-            wgpu::BindGroupLayoutEntry {
-                binding: 7,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Texture {
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                    multisampled: false,
-                },
-                count: None,
-            },
-            // slope
-            wgpu::BindGroupLayoutEntry {
-                binding: 8,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Texture {
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                    multisampled: false,
-                },
-                count: None,
-            },
-            // Output Texture
-            wgpu::BindGroupLayoutEntry {
-                binding: 9,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::StorageTexture {
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    format: wgpu::TextureFormat::Rgba8Unorm,
-                    access: wgpu::StorageTextureAccess::WriteOnly,
-                },
-                count: None,
-            },
-        ],
-    });
-
-    let compute_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: Some("PlanetMaterial Generator pipeline layout"),
-        bind_group_layouts: &[&bind_group_layout],
-        push_constant_ranges: &[],
-    });
-
-    let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-        label: None,
-        layout: Some(&compute_pipeline_layout),
-        module: &cs_module,
-        entry_point: "main",
-    });
-
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        layout: &bind_group_layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: default_materials.binding_resource(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: simple_materials.binding_resource(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 2,
-                resource: complex_materials.binding_resource(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 3,
-                resource: ore_mapping.binding_resource(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 4,
-                resource: materialmap.binding_resource(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 5,
-                resource: heightmap.binding_resource(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 6,
-                resource: latlut.binding_resource(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 7,
-                resource: normalmap.binding_resource(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 8,
-                resource: slopemap.binding_resource(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 9,
-                resource: texture.binding_resource(),
-            },
-        ],
-    });
-
-    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-        label: Some("PlanetMaterial Generator Encoder"),
-    });
-    {
-        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-            label: Some("PlanetMaterial Generator Pass"),
-        });
-        cpass.set_pipeline(&compute_pipeline);
-        cpass.set_bind_group(0, &bind_group, &[]);
-        cpass.insert_debug_marker("compute planet material");
-        let xdim = width + WORKGROUP_SIZE.0 - 1;
-        let xgroups = xdim / WORKGROUP_SIZE.0;
-        let ydim = height + WORKGROUP_SIZE.1 - 1;
-        let ygroups = ydim / WORKGROUP_SIZE.1;
-
-        cpass.dispatch_workgroups(xgroups, ygroups, 1);
-    }
+    // Ores are drawn on their own overlay, the shader only keeps the binding
+    let ore_mapping = OreMapData::new(
+        device,
+        "OreMapping",
+        materials.ore_map_to_gpu(&OrePalette::default())?,
+    );
+    let params = MaterialGenParams::new(device, view);
 
-    queue.submit(Some(encoder.finish()));
+    pipelines.material.dispatch(
+        device,
+        encoder,
+        &[
+            default_materials.binding_resource(),
+            simple_materials.binding_resource(),
+            complex_materials.binding_resource(),
+            ore_mapping.binding_resource(),
+            materialmap.binding_resource(),
+            heightmap.binding_resource(),
+            latlut.binding_resource(),
+            normalmap.binding_resource(),
+            slopemap.binding_resource(),
+            texture.binding_resource(),
+            params.binding_resource(),
+        ],
+        (width, height, layers),
+    );
 
-    Some(texture)
+    Ok(texture)
 }
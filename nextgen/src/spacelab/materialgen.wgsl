@@ -16,20 +16,37 @@ struct RuleArray {
     data: array<GPUMaterialRule>,
 }
 
+struct MaterialGenParams {
+    blend: u32,
+    // Transition widths, in heightmap units and degrees
+    height_width: f32,
+    latitude_width: f32,
+    slope_width: f32,
+};
+
 @group(0) @binding(0) var<storage> default_materials: array<GPUMaterialRule>;
 @group(0) @binding(1) var<storage> simple_materials: array<GPUMaterialRule>;
 @group(0) @binding(2) var<storage> complex_materials: array<GPUMaterialRule>;
 @group(0) @binding(3) var<storage> ore_mapping: array<GPUOre>;
 
-@group(0) @binding(4) var material_map: texture_2d<f32>;
-@group(0) @binding(5) var height_map: texture_2d<f32>;
-@group(0) @binding(6) var latlut: texture_2d<f32>;
-@group(0) @binding(7) var normal_map: texture_2d<f32>;
-@group(0) @binding(8) var slope_map: texture_2d<f32>;
-@group(0) @binding(9) var texture: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(4) var material_map: texture_2d_array<f32>;
+@group(0) @binding(5) var height_map: texture_2d_array<f32>;
+@group(0) @binding(6) var latlut: texture_2d_array<f32>;
+@group(0) @binding(7) var normal_map: texture_2d_array<f32>;
+@group(0) @binding(8) var slope_map: texture_2d_array<f32>;
+@group(0) @binding(9) var texture: texture_storage_2d_array<rgba8unorm, write>;
+@group(0) @binding(10) var<uniform> params: MaterialGenParams;
 
 const rad2deg: f32 = 57.29577951308232;  // approximately equal to 360/pi*2
 const rad: f32 = 1.5707963267948966;
+// Degrees of one 8 bit step of the slope map and latitude LUT, the f32 nearest 90/255
+const degrees_per_step: f32 = 0.35294118;
+
+// Slopes and latitudes are compared as whole 8 bit steps, so both backends see the same degrees
+// and texels on the edge of a rule land on the same side of it.
+fn texel_degrees(value: f32) -> f32 {
+    return round(value * 255.0) * degrees_per_step;
+}
 
 fn material_match(rule: GPUMaterialRule, height: f32, latitude: f32, slope: f32) -> bool {
     if (height < rule.height.x || height > rule.height.y) {
@@ -47,8 +64,47 @@ fn material_match(rule: GPUMaterialRule, height: f32, latitude: f32, slope: f32)
     return true;
 }
 
-fn slope(x: i32, y: i32) -> f32 {
-    let normal = textureLoad(normal_map, vec2<i32>(x, y), 0).rgb;
+// 1 inside the range, fading to 0 over a transition width centered on its edges. Edges at
+// the ends of the domain don't fade, since nothing lies past them.
+fn range_weight(x: f32, range: vec2<f32>, width: f32, domain: vec2<f32>) -> f32 {
+    if (width <= 0.0) {
+        return select(0.0, 1.0, x >= range.x && x <= range.y);
+    }
+    var weight = 1.0;
+    if (range.x > domain.x) {
+        weight = weight * clamp((x - range.x) / width + 0.5, 0.0, 1.0);
+    }
+    if (range.y < domain.y) {
+        weight = weight * clamp((range.y - x) / width + 0.5, 0.0, 1.0);
+    }
+    return weight;
+}
+
+fn rule_weight(rule: GPUMaterialRule, height: f32, latitude: f32, slope: f32) -> f32 {
+    return range_weight(height, rule.height, params.height_width, vec2<f32>(0.0, 1.0))
+        * range_weight(latitude, rule.latitude, params.latitude_width, vec2<f32>(0.0, 90.0))
+        * range_weight(slope, rule.slope, params.slope_width, vec2<f32>(0.0, 90.0));
+}
+
+// Color of texels no complex rule covers
+fn fallback_color(id: u32) -> vec4<f32> {
+    for (var i = 0u; i < arrayLength(&simple_materials); i = i + 1u) {
+        if (simple_materials[i].id == id) {
+            return simple_materials[i].color;
+        }
+    }
+    if (arrayLength(&default_materials) > 0u) {
+        return default_materials[0].color;
+    }
+
+    // TODO: Since it can be interpolated, check for closest match material?
+
+    // Not found, shouldnt happen since all materials has default color.
+    return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+}
+
+fn slope(x: i32, y: i32, layer: i32) -> f32 {
+    let normal = textureLoad(normal_map, vec2<i32>(x, y), layer, 0).rgb;
     // Since the normal is normalized, the z component is the cosine of the angle
     return (acos(normal.z) * rad2deg);
 }
@@ -58,45 +114,47 @@ fn slope(x: i32, y: i32) -> f32 {
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let X: i32 = i32(global_id.x);
     let Y: i32 = i32(global_id.y);
-    let id = u32(textureLoad(material_map, vec2<i32>(X, Y), 0).r * 255.0);
-    let ore = u32(textureLoad(material_map, vec2<i32>(X, Y), 0).b * 255.0);
-    let height = textureLoad(height_map, vec2<i32>(X, Y), 0).r;
+    // Every layer is a face of its own
+    let layer = i32(global_id.z);
+    let id = u32(round(textureLoad(material_map, vec2<i32>(X, Y), layer, 0).r * 255.0));
+    let ore = u32(round(textureLoad(material_map, vec2<i32>(X, Y), layer, 0).b * 255.0));
+    let height = textureLoad(height_map, vec2<i32>(X, Y), layer, 0).r;
 
-    let slope = textureLoad(slope_map, vec2<i32>(X, Y), 0).r * 90.0; // slope(X,Y);
-    let lat = textureLoad(latlut, vec2<i32>(X, Y), 0).r * 90.0;
+    let slope = texel_degrees(textureLoad(slope_map, vec2<i32>(X, Y), layer, 0).r); // slope(X, Y, layer);
+    let lat = texel_degrees(textureLoad(latlut, vec2<i32>(X, Y), layer, 0).r);
 
     // Works, but doesnt look good
     // for (var i = 0u; i < arrayLength(&ore_mapping); i = i + 1u) {
     //     if (ore_mapping[i].id == ore) {
     //         var color = ore_mapping[i].color;
-    //         textureStore(texture, vec2<i32>(X, Y), color);
+    //         textureStore(texture, vec2<i32>(X, Y), layer, color);
     //         return;
     //     }
     // }
 
-    for (var i = 0u; i < arrayLength(&complex_materials); i = i + 1u) {
-        if (complex_materials[i].id == id && material_match(complex_materials[i], height, lat, slope)) {
-            var color = complex_materials[i].color;
-            textureStore(texture, vec2<i32>(X, Y), color);
-            return;
+    if (params.blend != 0u) {
+        // Rules are layered in order, each covering its weight of what the earlier ones left
+        var color = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+        var remaining = 1.0;
+        for (var i = 0u; i < arrayLength(&complex_materials); i = i + 1u) {
+            if (complex_materials[i].id == id) {
+                let weight = rule_weight(complex_materials[i], height, lat, slope) * remaining;
+                color = color + complex_materials[i].color * weight;
+                remaining = remaining - weight;
+            }
         }
+        color = color + fallback_color(id) * remaining;
+        textureStore(texture, vec2<i32>(X, Y), layer, color);
+        return;
     }
 
-    for (var i = 0u; i < arrayLength(&simple_materials); i = i + 1u) {
-        if (simple_materials[i].id == id) {
-            var color = simple_materials[i].color;
-            textureStore(texture, vec2<i32>(X, Y), color);
+    for (var i = 0u; i < arrayLength(&complex_materials); i = i + 1u) {
+        if (complex_materials[i].id == id && material_match(complex_materials[i], height, lat, slope)) {
+            var color = complex_materials[i].color;
+            textureStore(texture, vec2<i32>(X, Y), layer, color);
             return;
         }
     }
-    if (arrayLength(&default_materials) > 0u) {
-            var color = default_materials[0].color;
-            textureStore(texture, vec2<i32>(X, Y), color);
-            return;
-    }
 
-    // TODO: Since it can be interpolated, check for closest match material?
-
-    // Not found, shouldnt happen since all materials has default color.
-    textureStore(texture, vec2<i32>(X, Y), vec4<f32>(0.0, 0.0, 0.0, 1.0)); // Not found
-}
\ No newline at end of file
+    textureStore(texture, vec2<i32>(X, Y), layer, fallback_color(id));
+}
@@ -1,7 +1,25 @@
+pub mod backend;
+pub mod blockcompress;
+pub mod buildcache;
 pub mod coloravg;
+pub mod cubeexport;
+pub mod cubemap;
 pub mod lutgen;
+pub mod lutgen_cpu;
 pub mod matcolormap;
+pub mod matcolormapgen;
 pub mod matfile;
 pub mod lutgen_gpu;
+pub mod material_cpu;
 pub mod material_gpu;
-pub mod normal;
\ No newline at end of file
+pub mod normal;
+pub mod normal_cpu;
+pub mod oreoverlay_cpu;
+pub mod oreoverlay_gpu;
+pub mod orepalette;
+pub mod pipeline_gpu;
+pub mod planetmeta;
+pub mod profile;
+pub mod projection;
+pub mod tiles;
+pub mod tiling;
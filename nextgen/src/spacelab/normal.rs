@@ -1,103 +1,61 @@
-use std::borrow::Cow;
-
-use crate::gpu::{gpu, texture::Texture};
-
-const WORKGROUP_SIZE: (u32, u32) = (8, 8);
-
-pub async fn gpu_generate_normal_inner(gpu_device: &gpu::Gpu, heightmap: &Texture) -> Option<Texture> {
+use crate::{
+    error::{check_size, Result},
+    gpu::{gpu, texture::Texture},
+};
+
+use super::{
+    lutgen::TerrainScale,
+    lutgen_gpu::{FaceTileParams, TerrainScaleParams},
+    pipeline_gpu::{output_binding, texture_binding, PipelineContext},
+    tiling::FaceTile,
+};
+
+/// Bindings of `compute_normal.wgsl`.
+pub(crate) fn normal_bindings() -> [wgpu::BindingType; 4] {
+    [
+        texture_binding(),
+        output_binding(wgpu::TextureFormat::Rgba8Unorm),
+        TerrainScaleParams::binding_type(),
+        FaceTileParams::binding_type(),
+    ]
+}
+
+/// Runs on `tile`, reading its halo around it from the heightmap. Without a scale heights are
+/// unitless, one step per texel.
+pub fn gpu_generate_normal_inner(
+    gpu_device: &gpu::Gpu,
+    pipelines: &PipelineContext,
+    encoder: &mut wgpu::CommandEncoder,
+    heightmap: &Texture,
+    tile: &FaceTile,
+    scale: Option<&TerrainScale>,
+) -> Result<Texture> {
     let device = &gpu_device.device;
-    let queue = &gpu_device.queue;
-    let width = heightmap.width();
-    let height = heightmap.height();
-    // Loads the shader from WGSL
-    let cs_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-        label: None,
-        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("compute_normal.wgsl"))),
-    });
+    check_size("Normal height map", tile.input_size(), (heightmap.width(), heightmap.height()))?;
 
-    let texture = Texture::new(
+    let texture = Texture::new_array(
         gpu_device,
-        width,
-        height,
+        tile.width,
+        tile.height,
+        heightmap.layers(),
         wgpu::TextureFormat::Rgba8Unorm,
         Some("Normal Generator Output"),
     );
 
-    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: Some("Normal Generator Bindings"),
-        entries: &[
-            // Face Number
-            wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Texture {
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                    multisampled: false,
-                },
-                count: None,
-            },
-            // Output Texture
-            wgpu::BindGroupLayoutEntry {
-                binding: 1,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::StorageTexture {
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    format: wgpu::TextureFormat::Rgba8Unorm,
-                    access: wgpu::StorageTextureAccess::WriteOnly,
-                },
-                count: None,
-            },
+    let scale_params = TerrainScaleParams::new(device, scale);
+    let tile_params = FaceTileParams::new(device, tile);
+
+    pipelines.normal.dispatch(
+        device,
+        encoder,
+        &[
+            heightmap.binding_resource(),
+            texture.binding_resource(),
+            scale_params.binding_resource(),
+            tile_params.binding_resource(),
         ],
-    });
-
-    let compute_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: Some("Normal Generator compute pipeline layout"),
-        bind_group_layouts: &[&bind_group_layout],
-        push_constant_ranges: &[],
-    });
-
-    let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-        label: None,
-        layout: Some(&compute_pipeline_layout),
-        module: &cs_module,
-        entry_point: "main",
-    });
-
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        layout: &bind_group_layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: heightmap.binding_resource(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: texture.binding_resource(),
-            },
-        ],
-    });
-
-    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-        label: Some("Normal Generator Encoder"),
-    });
-    {
-        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-            label: Some("Normal Generator Pass"),
-        });
-        cpass.set_pipeline(&compute_pipeline);
-        cpass.set_bind_group(0, &bind_group, &[]);
-        cpass.insert_debug_marker("compute Normal Generator");
-        let xdim = width + WORKGROUP_SIZE.0 - 1;
-        let xgroups = xdim / WORKGROUP_SIZE.0;
-        let ydim = height + WORKGROUP_SIZE.1 - 1;
-        let ygroups = ydim / WORKGROUP_SIZE.1;
-
-        cpass.dispatch_workgroups(xgroups, ygroups, 1);
-    }
-
-    queue.submit(Some(encoder.finish()));
+        (tile.width, tile.height, heightmap.layers()),
+    );
 
-    Some(texture)
-}
\ No newline at end of file
+    Ok(texture)
+}
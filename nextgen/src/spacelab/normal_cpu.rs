@@ -0,0 +1,98 @@
+// CPU version of compute_normal.wgsl
+
+use crate::{
+    cpu::texture::CpuTexture,
+    error::{check_size, Result},
+};
+
+use super::{lutgen::TerrainScale, tiling::FaceTile};
+
+pub fn cpu_generate_normal(
+    heightmap: &CpuTexture,
+    tile: &FaceTile,
+    scale: Option<&TerrainScale>,
+) -> Result<CpuTexture> {
+    check_size("Normal height map", tile.input_size(), (heightmap.width(), heightmap.height()))?;
+    let (width, height) = (tile.face_width as i32, tile.face_height as i32);
+    let mut texture = CpuTexture::new(tile.width, tile.height, wgpu::TextureFormat::Rgba8Unorm)?;
+
+    texture.fill(|x, y| {
+        let (x0, y0) = (tile.x + x, tile.y + y);
+        // Height of the texel at an offset, clamped to the face edges without the adjacent faces
+        let h = |dx: i32, dy: i32| {
+            let (mut x, mut y) = (x0 as i32 + dx, y0 as i32 + dy);
+            if !tile.seamless {
+                x = x.clamp(0, width - 1);
+                y = y.clamp(0, height - 1);
+            }
+            let halo = tile.halo as i32;
+            heightmap.load((x - tile.x as i32 + halo) as u32, (y - tile.y as i32 + halo) as u32)[0].abs()
+        };
+
+        // Sampling around the current texel for Sobel filter
+        let tl = h(-1, -1);
+        let l = h(-1, 0);
+        let bl = h(-1, 1);
+        let t = h(0, -1);
+        let b = h(0, 1);
+        let tr = h(1, -1);
+        let r = h(1, 0);
+        let br = h(1, 1);
+
+        let dx = tr + 2.0 * r + br - tl - 2.0 * l - bl;
+        let dy = bl + 2.0 * b + br - tl - 2.0 * t - tr;
+
+        let n = match scale {
+            Some(scale) => {
+                // Sobel sums 8 times the height step of one texel
+                let (metres_x, metres_y) = scale.texel_size(x0, y0, tile.face_width, tile.face_height);
+                let gx = dx / 8.0 * scale.height_range() / metres_x;
+                let gy = dy / 8.0 * scale.height_range() / metres_y;
+                nalgebra::Vector3::new(gx, gy, 1.0).normalize()
+            }
+            None => nalgebra::Vector3::new(dx, dy, 1.0).normalize(),
+        };
+        [n.x * 0.5 + 0.5, n.y * 0.5 + 0.5, n.z * 0.5 + 0.5, 1.0]
+    });
+
+    Ok(texture)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heightmap(size: u32, height: impl Fn(u32, u32) -> f32 + Sync) -> CpuTexture {
+        let mut texture = CpuTexture::new(size, size, wgpu::TextureFormat::R32Float).unwrap();
+        texture.fill(|x, y| [height(x, y), 0.0, 0.0, 0.0]);
+        texture
+    }
+
+    #[test]
+    fn flat_ground_points_up() {
+        let normal = cpu_generate_normal(&heightmap(4, |_, _| 0.3), &FaceTile::whole(4, 4), None).unwrap();
+        for y in 0..4 {
+            for x in 0..4 {
+                assert_eq!(normal.load(x, y), [128.0 / 255.0, 128.0 / 255.0, 1.0, 1.0]);
+            }
+        }
+    }
+
+    #[test]
+    fn slopes_tilt_the_normal_and_scale_flattens_it() {
+        let ramp = heightmap(6, |x, _| x as f32 * 0.01);
+        let tile = FaceTile::whole(6, 6);
+        let unscaled = cpu_generate_normal(&ramp, &tile, None).unwrap().load(2, 2);
+        assert!(unscaled[0] > 0.5);
+        assert_eq!(unscaled[1], 128.0 / 255.0);
+
+        // 0.01 of a 200 m height range is 2 m per texel of 10 km, close to flat
+        let scale = TerrainScale {
+            radius: 30000.0,
+            hill_min: 0.0,
+            hill_max: 200.0 / 30000.0,
+        };
+        let scaled = cpu_generate_normal(&ramp, &tile, Some(&scale)).unwrap().load(2, 2);
+        assert!(scaled[0] > 0.5 && scaled[0] < unscaled[0]);
+    }
+}
@@ -0,0 +1,101 @@
+
+struct GPUOre {
+    id: u32,
+    color: vec4<f32>,
+}
+
+struct OreGenParams {
+    // Size of the whole face, see `tiling::FaceTile`
+    width: u32,
+    height: u32,
+    outline: u32,
+    glow: u32,
+    // Face texel under the first texel of the output
+    origin_x: u32,
+    origin_y: u32,
+    // Texels of the material map around the output
+    halo: u32,
+    pad: u32,
+};
+
+@group(0) @binding(0) var<uniform> params: OreGenParams;
+@group(0) @binding(1) var<storage> ore_mapping: array<GPUOre>;
+@group(0) @binding(2) var material_map: texture_2d_array<f32>;
+@group(0) @binding(3) var texture: texture_storage_2d_array<rgba8unorm, write>;
+
+// Layer of the invocation, every layer is a face of its own
+var<private> layer: i32;
+
+fn ore_at(x: i32, y: i32) -> u32 {
+    let texel = vec2<i32>(x - i32(params.origin_x), y - i32(params.origin_y)) + i32(params.halo);
+    return u32(round(textureLoad(material_map, texel, layer, 0).b * 255.0));
+}
+
+fn in_bounds(x: i32, y: i32) -> bool {
+    return x >= 0 && y >= 0 && x < i32(params.width) && y < i32(params.height);
+}
+
+// Index into ore_mapping, or -1 when the texel has no mapped ore
+fn ore_index(x: i32, y: i32) -> i32 {
+    if (!in_bounds(x, y)) {
+        return -1;
+    }
+    let ore = ore_at(x, y);
+    for (var i = 0u; i < arrayLength(&ore_mapping); i = i + 1u) {
+        if (ore_mapping[i].id == ore) {
+            return i32(i);
+        }
+    }
+    return -1;
+}
+
+@compute
+@workgroup_size(8,8)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let X: i32 = i32(params.origin_x + global_id.x);
+    let Y: i32 = i32(params.origin_y + global_id.y);
+    let out = vec2<i32>(global_id.xy);
+    layer = i32(global_id.z);
+    let index = ore_index(X, Y);
+
+    if (index >= 0) {
+        let color = ore_mapping[index].color;
+        if (params.outline != 0u) {
+            // Deposit texels bordering anything else are darkened
+            for (var dy = -1; dy <= 1; dy = dy + 1) {
+                for (var dx = -1; dx <= 1; dx = dx + 1) {
+                    if (in_bounds(X + dx, Y + dy) && ore_index(X + dx, Y + dy) != index) {
+                        textureStore(texture, out, layer, vec4<f32>(color.rgb * 0.5, 1.0));
+                        return;
+                    }
+                }
+            }
+        }
+        textureStore(texture, out, layer, color);
+        return;
+    }
+
+    // Glow, fading out with the distance to the closest deposit texel
+    let radius = i32(params.glow);
+    var closest = -1;
+    var closest_distance = radius * radius + 1;
+    for (var dy = -radius; dy <= radius; dy = dy + 1) {
+        for (var dx = -radius; dx <= radius; dx = dx + 1) {
+            let distance = dx * dx + dy * dy;
+            if (distance < closest_distance) {
+                let neighbour = ore_index(X + dx, Y + dy);
+                if (neighbour >= 0) {
+                    closest = neighbour;
+                    closest_distance = distance;
+                }
+            }
+        }
+    }
+    if (closest >= 0) {
+        let alpha = 1.0 - sqrt(f32(closest_distance)) / f32(radius + 1);
+        textureStore(texture, out, layer, vec4<f32>(ore_mapping[closest].color.rgb, alpha));
+        return;
+    }
+
+    textureStore(texture, out, layer, vec4<f32>(0.0, 0.0, 0.0, 0.0));
+}
@@ -0,0 +1,124 @@
+// CPU version of oregen.wgsl
+
+use crate::{
+    cpu::texture::CpuTexture,
+    error::{check_size, Result},
+};
+
+use super::{
+    matcolormap::PlanetMaterial, oreoverlay_gpu::OreOverlayStyle, orepalette::OrePalette,
+    tiling::FaceTile,
+};
+
+pub fn generate_ore_overlay_cpu(
+    materialmap: &CpuTexture,
+    tile: &FaceTile,
+    materials: &PlanetMaterial,
+    palette: &OrePalette,
+    style: &OreOverlayStyle,
+) -> Result<CpuTexture> {
+    check_size("Ore material map", tile.input_size(), (materialmap.width(), materialmap.height()))?;
+    let (width, height) = (tile.face_width, tile.face_height);
+    let ore_mapping = materials.ore_map_to_gpu(palette)?;
+    let mut texture = CpuTexture::new(tile.width, tile.height, wgpu::TextureFormat::Rgba8Unorm)?;
+
+    let in_bounds = |x: i32, y: i32| x >= 0 && y >= 0 && x < width as i32 && y < height as i32;
+    // Index into ore_mapping, or None when the texel has no mapped ore
+    let ore_index = |x: i32, y: i32| {
+        if !in_bounds(x, y) {
+            return None;
+        }
+        let (x, y) = (x - tile.x as i32 + tile.halo as i32, y - tile.y as i32 + tile.halo as i32);
+        let ore = (materialmap.load(x as u32, y as u32)[2] * 255.0).round() as u32;
+        ore_mapping.iter().position(|mapping| mapping.id == ore)
+    };
+
+    texture.fill(|x, y| {
+        let (x, y) = ((tile.x + x) as i32, (tile.y + y) as i32);
+
+        if let Some(index) = ore_index(x, y) {
+            let color = ore_mapping[index].color;
+            if style.outline {
+                // Deposit texels bordering anything else are darkened
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        if in_bounds(x + dx, y + dy) && ore_index(x + dx, y + dy) != Some(index) {
+                            return [color[0] * 0.5, color[1] * 0.5, color[2] * 0.5, 1.0];
+                        }
+                    }
+                }
+            }
+            return color;
+        }
+
+        // Glow, fading out with the distance to the closest deposit texel
+        let radius = style.glow as i32;
+        let mut closest = None;
+        let mut closest_distance = radius * radius + 1;
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let distance = dx * dx + dy * dy;
+                if distance < closest_distance {
+                    if let Some(neighbour) = ore_index(x + dx, y + dy) {
+                        closest = Some(neighbour);
+                        closest_distance = distance;
+                    }
+                }
+            }
+        }
+        if let Some(index) = closest {
+            let alpha = 1.0 - (closest_distance as f32).sqrt() / (radius + 1) as f32;
+            let color = ore_mapping[index].color;
+            return [color[0], color[1], color[2], alpha];
+        }
+
+        [0.0, 0.0, 0.0, 0.0]
+    });
+
+    Ok(texture)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spacelab::matcolormap::test_planet;
+
+    use super::*;
+
+    // 5x5 material map with an iron deposit on its center texel
+    fn deposit() -> CpuTexture {
+        let mut materialmap = CpuTexture::new(5, 5, wgpu::TextureFormat::Rgba8Unorm).unwrap();
+        materialmap.fill(|x, y| {
+            let ore = if (x, y) == (2, 2) { 200.0 / 255.0 } else { 0.0 };
+            [0.0, 0.0, ore, 1.0]
+        });
+        materialmap
+    }
+
+    fn overlay(style: &OreOverlayStyle) -> CpuTexture {
+        let palette = OrePalette::default();
+        generate_ore_overlay_cpu(&deposit(), &FaceTile::whole(5, 5), &test_planet(), &palette, style).unwrap()
+    }
+
+    #[test]
+    fn deposits_take_the_palette_color() {
+        let iron = OrePalette::default().get("Iron_02").color.map(|c| c as f32 / 255.0);
+        let texture = overlay(&OreOverlayStyle::default());
+        let center = texture.load(2, 2);
+        for c in 0..3 {
+            assert!((center[c] - iron[c]).abs() < 1.0 / 255.0);
+        }
+        assert_eq!(center[3], 1.0);
+        assert_eq!(texture.load(1, 2), [0.0; 4]);
+    }
+
+    #[test]
+    fn glow_fades_with_distance() {
+        let texture = overlay(&OreOverlayStyle { outline: true, glow: 2 });
+        let alpha = |x: u32, y: u32| texture.load(x, y)[3];
+        // A lone texel is all edge, so the outline darkens it
+        assert!(texture.load(2, 2)[0] < overlay(&OreOverlayStyle::default()).load(2, 2)[0]);
+        assert!(alpha(1, 2) > alpha(0, 2) && alpha(0, 2) > 0.0);
+        assert!(alpha(1, 1) < alpha(1, 2));
+        assert_eq!(alpha(0, 0), 0.0);
+    }
+}
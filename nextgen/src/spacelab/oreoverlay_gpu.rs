@@ -0,0 +1,127 @@
+use std::mem;
+
+use wgpu::util::DeviceExt;
+
+use crate::{
+    error::{check_size, Result},
+    gpu::{gpu::Gpu, texture::Texture},
+};
+
+use super::{
+    material_gpu::OreMapData,
+    matcolormap::PlanetMaterial,
+    orepalette::OrePalette,
+    pipeline_gpu::{output_binding, texture_binding, PipelineContext},
+    tiling::FaceTile,
+};
+
+/// How deposits are drawn on the ore overlay.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OreOverlayStyle {
+    /// Darken the edge texels of every deposit
+    pub outline: bool,
+    /// Radius in texels of the halo drawn around deposits, 0 for none
+    pub glow: u32,
+}
+
+impl OreOverlayStyle {
+    /// Texels around a texel of the overlay the style reads, for tiled runs.
+    pub fn reach(&self) -> u32 {
+        self.glow.max(self.outline as u32)
+    }
+}
+
+// `OreGenParams` of oregen.wgsl, its fields in order: the face width and height, outline,
+// glow, the tile origin x and y, halo and padding
+type OreGenParamsData = [u32; 8];
+
+struct OreGenParams {
+    param_buf: wgpu::Buffer,
+}
+
+impl OreGenParams {
+    pub fn new(device: &wgpu::Device, tile: &FaceTile, style: &OreOverlayStyle) -> Self {
+        let params: OreGenParamsData = [
+            tile.face_width,
+            tile.face_height,
+            style.outline as u32,
+            style.glow,
+            tile.x,
+            tile.y,
+            tile.halo,
+            0,
+        ];
+        let param_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("OreGen parameters buffer"),
+            contents: bytemuck::cast_slice(&params),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        OreGenParams { param_buf }
+    }
+
+    pub fn binding_resource(&self) -> wgpu::BindingResource<'_> {
+        self.param_buf.as_entire_binding()
+    }
+
+    pub fn binding_type() -> wgpu::BindingType {
+        wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: wgpu::BufferSize::new(mem::size_of::<OreGenParamsData>() as _),
+        }
+    }
+}
+
+/// Bindings of `oregen.wgsl`.
+pub(crate) fn ore_overlay_bindings() -> [wgpu::BindingType; 4] {
+    [
+        OreGenParams::binding_type(),
+        OreMapData::binding_type(),
+        texture_binding(),
+        output_binding(wgpu::TextureFormat::Rgba8Unorm),
+    ]
+}
+
+/// Draws the ore deposits of a material map on a transparent texture, so they can be
+/// shown on top of the albedo instead of being baked into it.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_ore_overlay_gpu(
+    gpu_device: &Gpu,
+    pipelines: &PipelineContext,
+    encoder: &mut wgpu::CommandEncoder,
+    materialmap: &Texture,
+    tile: &FaceTile,
+    materials: &PlanetMaterial,
+    palette: &OrePalette,
+    style: &OreOverlayStyle,
+) -> Result<Texture> {
+    let device = &gpu_device.device;
+    check_size("Ore material map", tile.input_size(), (materialmap.width(), materialmap.height()))?;
+
+    let texture = Texture::new_array(
+        gpu_device,
+        tile.width,
+        tile.height,
+        materialmap.layers(),
+        wgpu::TextureFormat::Rgba8Unorm,
+        Some("OreGen Output"),
+    );
+
+    let params = OreGenParams::new(device, tile, style);
+    let ore_mapping = OreMapData::new(device, "OreMapping", materials.ore_map_to_gpu(palette)?);
+
+    pipelines.ore_overlay.dispatch(
+        device,
+        encoder,
+        &[
+            params.binding_resource(),
+            ore_mapping.binding_resource(),
+            materialmap.binding_resource(),
+            texture.binding_resource(),
+        ],
+        (tile.width, tile.height, materialmap.layers()),
+    );
+
+    Ok(texture)
+}
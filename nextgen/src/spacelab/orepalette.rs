@@ -0,0 +1,130 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// How an ore type is shown: overlay color, element symbol and display name.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OreInfo {
+    pub color: [u8; 3],
+    pub symbol: String,
+    pub name: String,
+}
+
+/// Ore entry of a palette file, every field is optional and falls back to the
+/// built-in or generated value.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OreEntry {
+    color: Option<[u8; 3]>,
+    symbol: Option<String>,
+    name: Option<String>,
+}
+
+/// Ore types to how they are shown, keyed by the `Type` of the ore mapping.
+///
+/// Palette files are JSON or TOML, picked by extension, with one table per ore type:
+///
+/// ```toml
+/// [Cronyx]
+/// color = [184, 115, 51]
+/// symbol = "Cr"
+/// name = "Cronyx"
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrePalette(pub HashMap<String, OreInfo>);
+
+// The palette the Python tool ships, also the reference for palette files
+const BUILTIN_ORES: &str = include_str!("../../../luts/orepalette.json");
+
+impl Default for OrePalette {
+    fn default() -> Self {
+        OrePalette(serde_json::from_str(BUILTIN_ORES).expect("luts/orepalette.json is a full palette"))
+    }
+}
+
+impl OrePalette {
+    /// Built-in palette with the entries of a palette file laid over it.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let entries: HashMap<String, OreEntry> = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&contents)
+                .map_err(|e| Error::BadDefinition(format!("{}: {}", path.display(), e)))?,
+            _ => serde_json::from_str(&contents)?,
+        };
+
+        let mut palette = OrePalette::default();
+        for (ore_type, entry) in entries {
+            let base = palette.get(&ore_type);
+            palette.0.insert(
+                ore_type,
+                OreInfo {
+                    color: entry.color.unwrap_or(base.color),
+                    symbol: entry.symbol.unwrap_or(base.symbol),
+                    name: entry.name.unwrap_or(base.name),
+                },
+            );
+        }
+        Ok(palette)
+    }
+
+    /// Looks an ore type up. Unknown ores get a color derived from their name, so
+    /// they keep the same color across runs and planets.
+    pub fn get(&self, ore_type: &str) -> OreInfo {
+        if let Some(info) = self.0.get(ore_type) {
+            return info.clone();
+        }
+
+        // Iron_01 -> Iron
+        let name = match ore_type.rsplit_once('_') {
+            Some((name, suffix)) if suffix.chars().all(|c| c.is_ascii_digit()) => name,
+            _ => ore_type,
+        };
+        OreInfo {
+            color: generated_color(ore_type),
+            symbol: name.chars().take(2).collect(),
+            name: name.to_owned(),
+        }
+    }
+}
+
+// FNV-1a hash of the name picks the hue, saturation and value are fixed so the
+// colors stay readable on the overlay
+fn generated_color(ore_type: &str) -> [u8; 3] {
+    let hash = ore_type
+        .bytes()
+        .fold(0x811c9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193));
+    let hue = (hash % 360) as f32 / 60.0;
+    let (saturation, value) = (0.75, 0.95);
+
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = value - chroma;
+    let to_u8 = |c: f32| ((c + m) * 255.0 + 0.5) as u8;
+    [to_u8(r), to_u8(g), to_u8(b)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_palette_parses_with_unique_ores() {
+        let palette = OrePalette::default();
+        assert_eq!(palette.get("Iron_01").symbol, "Fe");
+        assert_eq!(palette.get("Uraninite_01").name, "Uranium");
+
+        // serde_json keeps the last of repeated keys, so count the entries of the file instead
+        let entries = BUILTIN_ORES.lines().filter(|line| line.trim_start().starts_with('"')).count();
+        assert_eq!(entries, palette.0.len());
+    }
+}
@@ -0,0 +1,194 @@
+// Compute pipelines of every GPU stage, compiled once per device
+
+use std::borrow::Cow;
+
+use crate::{
+    error::Result,
+    gpu::gpu::{self, Gpu},
+};
+
+use super::{
+    lutgen_gpu::{face_lut_bindings, slope_bindings},
+    material_gpu::material_bindings,
+    normal::normal_bindings,
+    oreoverlay_gpu::ore_overlay_bindings,
+};
+
+const WORKGROUP_SIZE: (u32, u32) = (8, 8);
+
+/// A sampled, non filterable 2D texture array binding, how every stage reads its input
+/// textures. Layer n of every texture is the same face.
+pub(crate) fn texture_binding() -> wgpu::BindingType {
+    wgpu::BindingType::Texture {
+        view_dimension: wgpu::TextureViewDimension::D2Array,
+        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+        multisampled: false,
+    }
+}
+
+/// A write only 2D storage texture array binding, how every stage writes its output.
+pub(crate) fn output_binding(format: wgpu::TextureFormat) -> wgpu::BindingType {
+    wgpu::BindingType::StorageTexture {
+        view_dimension: wgpu::TextureViewDimension::D2Array,
+        format,
+        access: wgpu::StorageTextureAccess::WriteOnly,
+    }
+}
+
+/// A compiled compute shader, with the layout of its bindings.
+pub(crate) struct ComputeStage {
+    label: &'static str,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl ComputeStage {
+    /// Compiles the `main` entry point of `shader`. Binding `n` of the shader has type `bindings[n]`.
+    async fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        shader: &'static str,
+        bindings: &[wgpu::BindingType],
+    ) -> Result<Self> {
+        let cs_module = gpu::validated(device, || {
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(label),
+                source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(shader)),
+            })
+        })
+        .await?;
+
+        let entries: Vec<wgpu::BindGroupLayoutEntry> = bindings
+            .iter()
+            .enumerate()
+            .map(|(binding, ty)| wgpu::BindGroupLayoutEntry {
+                binding: binding as u32,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: *ty,
+                count: None,
+            })
+            .collect();
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(&format!("{} Bindings", label)),
+            entries: &entries,
+        });
+        let compute_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format!("{} pipeline layout", label)),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = gpu::validated(device, || {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&compute_pipeline_layout),
+                module: &cs_module,
+                entry_point: "main",
+            })
+        })
+        .await?;
+
+        Ok(ComputeStage {
+            label,
+            bind_group_layout,
+            pipeline,
+        })
+    }
+
+    /// Records a pass running the shader over `width` x `height` texels of `layers` layers,
+    /// `resources[n]` bound to binding `n`. The layer is the z of the invocation id.
+    pub fn dispatch(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        resources: &[wgpu::BindingResource],
+        (width, height, layers): (u32, u32, u32),
+    ) {
+        let entries: Vec<wgpu::BindGroupEntry> = resources
+            .iter()
+            .enumerate()
+            .map(|(binding, resource)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: resource.clone(),
+            })
+            .collect();
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(self.label),
+            layout: &self.bind_group_layout,
+            entries: &entries,
+        });
+
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&format!("{} Pass", self.label)),
+        });
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, &bind_group, &[]);
+        cpass.insert_debug_marker(self.label);
+        cpass.dispatch_workgroups(width.div_ceil(WORKGROUP_SIZE.0), height.div_ceil(WORKGROUP_SIZE.1), layers);
+    }
+}
+
+/// Every stage of the planet pipeline, compiled for one `Gpu`.
+///
+/// The stages only record their passes into a command encoder, so a whole face can be
+/// submitted at once and read back while the next one runs. They take texture arrays, one
+/// face per layer, so the six faces of a planet can also go through in a single dispatch.
+pub struct PipelineContext {
+    pub(crate) latlut: ComputeStage,
+    /// None when the adapter can't store `Rg32Float` texels, as on OpenGL
+    pub(crate) geolut: Option<ComputeStage>,
+    pub(crate) slope: ComputeStage,
+    pub(crate) normal: ComputeStage,
+    pub(crate) material: ComputeStage,
+    pub(crate) ore_overlay: ComputeStage,
+}
+
+impl PipelineContext {
+    pub async fn new(gpu_device: &Gpu) -> Result<Self> {
+        let device = &gpu_device.device;
+        let geolut_format = wgpu::TextureFormat::Rg32Float;
+        let geolut_storage = gpu_device
+            .adapter
+            .get_texture_format_features(geolut_format)
+            .allowed_usages
+            .contains(wgpu::TextureUsages::STORAGE_BINDING);
+        Ok(PipelineContext {
+            latlut: ComputeStage::new(
+                device,
+                "LatLutGen",
+                include_str!("latlutgen.wgsl"),
+                &face_lut_bindings(wgpu::TextureFormat::Rgba8Unorm),
+            )
+            .await?,
+            geolut: if geolut_storage {
+                let bindings = face_lut_bindings(geolut_format);
+                Some(ComputeStage::new(device, "GeoLutGen", include_str!("geolutgen.wgsl"), &bindings).await?)
+            } else {
+                None
+            },
+            slope: ComputeStage::new(device, "SlopeGen", include_str!("slopegen.wgsl"), &slope_bindings())
+                .await?,
+            normal: ComputeStage::new(
+                device,
+                "Normal Generator",
+                include_str!("compute_normal.wgsl"),
+                &normal_bindings(),
+            )
+            .await?,
+            material: ComputeStage::new(
+                device,
+                "PlanetMaterial Generator",
+                include_str!("materialgen.wgsl"),
+                &material_bindings(),
+            )
+            .await?,
+            ore_overlay: ComputeStage::new(
+                device,
+                "OreGen",
+                include_str!("oregen.wgsl"),
+                &ore_overlay_bindings(),
+            )
+            .await?,
+        })
+    }
+}
@@ -0,0 +1,207 @@
+// Rust version of tools/metamaker.py
+
+use std::{collections::HashMap, path::Path};
+
+use image::RgbImage;
+use serde::Serialize;
+
+use crate::error::Result;
+
+use super::{
+    lutgen::{point_to_lat_lon, CUBEMAP},
+    lutgen_cpu::compute_point,
+    lutgen_gpu::face_to_num,
+    matcolormap::{OreMap, PlanetMaterial},
+};
+
+/// Contents of `planetmeta/<name>_planetmeta.json`.
+#[derive(Debug, Serialize)]
+pub struct PlanetMeta {
+    pub name: String,
+    /// Declared ores that have at least one site, keyed by ore value
+    pub ores: HashMap<String, FoundOre>,
+    pub declared_ores: HashMap<String, OreMap>,
+    /// `[x, y, ore]` of every site, in material map texels
+    pub oresites: Vec<(f64, f64, u32)>,
+    /// Every site with its face and position on the planet
+    pub sites: Vec<OreSite>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FoundOre {
+    #[serde(flatten)]
+    pub ore: OreMap,
+    pub sites: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OreSite {
+    pub face: String,
+    pub ore: u32,
+    /// Centroid in material map texels
+    pub x: f64,
+    pub y: f64,
+    pub texels: u32,
+    /// Degrees, using the same face orientation as the latitude LUT
+    pub latitude: f32,
+    pub longitude: f32,
+    /// Centroid on the unit sphere
+    pub position: [f32; 3],
+}
+
+/// A connected group of ore texels on a single material map.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OreBlob {
+    pub x: f64,
+    pub y: f64,
+    /// Most frequent ore value of the blob
+    pub ore: u8,
+    pub texels: u32,
+}
+
+/// Labels the ore texels of a material map with 8-connectivity.
+///
+/// Ores live in the blue channel, where 0 is no ore and 255 is unused. Blobs are
+/// returned in raster order of their first texel, like OpenCV labels them.
+pub fn find_ore_blobs(materialmap: &RgbImage) -> Vec<OreBlob> {
+    let (width, height) = materialmap.dimensions();
+    let ore_at = |x: u32, y: u32| materialmap.get_pixel(x, y).0[2];
+    let is_ore = |x: u32, y: u32| (1..255).contains(&ore_at(x, y));
+
+    let mut visited = vec![false; width as usize * height as usize];
+    let mut stack = Vec::new();
+    let mut blobs = Vec::new();
+
+    for y0 in 0..height {
+        for x0 in 0..width {
+            let index = (y0 * width + x0) as usize;
+            if visited[index] || !is_ore(x0, y0) {
+                continue;
+            }
+
+            visited[index] = true;
+            stack.push((x0, y0));
+            let mut histogram = [0u32; 256];
+            let (mut sum_x, mut sum_y, mut texels) = (0u64, 0u64, 0u32);
+
+            while let Some((x, y)) = stack.pop() {
+                histogram[ore_at(x, y) as usize] += 1;
+                sum_x += x as u64;
+                sum_y += y as u64;
+                texels += 1;
+
+                for dy in -1i32..=1 {
+                    for dx in -1i32..=1 {
+                        let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                        if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+                            continue;
+                        }
+                        let (nx, ny) = (nx as u32, ny as u32);
+                        let neighbour = (ny * width + nx) as usize;
+                        if !visited[neighbour] && is_ore(nx, ny) {
+                            visited[neighbour] = true;
+                            stack.push((nx, ny));
+                        }
+                    }
+                }
+            }
+
+            // Ties go to the lowest value, like numpy's argmax
+            let ore = (0..256).rev().max_by_key(|&v| histogram[v]).unwrap() as u8;
+            blobs.push(OreBlob {
+                x: sum_x as f64 / texels as f64,
+                y: sum_y as f64 / texels as f64,
+                ore,
+                texels,
+            });
+        }
+    }
+
+    blobs
+}
+
+/// Latitude and longitude in degrees, and the unit sphere position, of a texel
+/// center on a face.
+pub fn texel_to_sphere(face_num: u32, x: f64, y: f64, width: u32, height: u32) -> (f32, f32, [f32; 3]) {
+    let u = ((x + 0.5) / width as f64 * 2.0 - 1.0) as f32;
+    let v = ((y + 0.5) / height as f64 * 2.0 - 1.0) as f32;
+    let p = compute_point(u, v, face_num).normalize();
+    let (latitude, longitude) = point_to_lat_lon(p);
+    (latitude, longitude, [p.x, p.y, p.z])
+}
+
+/// Finds the ore sites on the six `<face>_mat.png` of a planet.
+pub fn generate_planet_meta(planet: &PlanetMaterial, texture_folder: &Path) -> Result<PlanetMeta> {
+    let mut meta = PlanetMeta {
+        name: planet.name.clone(),
+        ores: HashMap::new(),
+        declared_ores: planet
+            .ores
+            .iter()
+            .map(|(value, ore)| (value.clone(), ore.clone()))
+            .collect(),
+        oresites: Vec::new(),
+        sites: Vec::new(),
+    };
+
+    for face in CUBEMAP.iter() {
+        let face_num = face_to_num(face)?;
+        let path = texture_folder.join(format!("{}_mat.png", face));
+        println!("    Processing {}...", path.display());
+        let materialmap = image::open(&path)?.to_rgb8();
+        let (width, height) = materialmap.dimensions();
+
+        for blob in find_ore_blobs(&materialmap) {
+            let ore = blob.ore as u32;
+            let (latitude, longitude, position) = texel_to_sphere(face_num, blob.x, blob.y, width, height);
+            meta.oresites.push((blob.x, blob.y, ore));
+            meta.sites.push(OreSite {
+                face: face.to_string(),
+                ore,
+                x: blob.x,
+                y: blob.y,
+                texels: blob.texels,
+                latitude,
+                longitude,
+                position,
+            });
+
+            if let Some(declared) = planet.ores.get(&ore.to_string()) {
+                let found = meta.ores.entry(ore.to_string()).or_insert_with(|| {
+                    println!("        Found {}", declared.ore_type.as_deref().unwrap_or("?"));
+                    FoundOre {
+                        ore: declared.clone(),
+                        sites: 0,
+                    }
+                });
+                found.sites += 1;
+            }
+        }
+    }
+
+    Ok(meta)
+}
+
+/// `Agaris II` is written to `agaris_ii_planetmeta.json`.
+pub fn planet_meta_file_name(name: &str) -> String {
+    format!("{}_planetmeta.json", name.replace(' ', "_").to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spacelab::lutgen::lat_lon_to_texel;
+
+    use super::*;
+
+    #[test]
+    fn texel_to_sphere_inverts_lat_lon_to_texel() {
+        for face_num in 0..6 {
+            for (x, y) in [(3, 5), (32, 32), (60, 1)] {
+                let (latitude, longitude, position) = texel_to_sphere(face_num, x as f64, y as f64, 64, 64);
+                let length = position.iter().map(|c| c * c).sum::<f32>().sqrt();
+                assert!((length - 1.0).abs() < 1e-5);
+                assert_eq!(lat_lon_to_texel(latitude, longitude, 64, 64), (face_num, x, y));
+            }
+        }
+    }
+}
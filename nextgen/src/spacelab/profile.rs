@@ -0,0 +1,126 @@
+// Per stage, per face timings of a generate run, written as a JSON report with `--profile`
+
+use std::{cell::RefCell, path::Path, time::Duration, time::Instant};
+
+use serde::Serialize;
+
+use crate::error::Result;
+
+/// Time the device spent on one stage call, measured with timestamp queries.
+#[derive(Debug, Clone)]
+pub struct DeviceTime {
+    pub faces: String,
+    pub stage: &'static str,
+    pub milliseconds: f64,
+}
+
+/// Time and bytes spent in one stage on a group of faces, summed over its tiles.
+#[derive(Debug, Clone, Serialize)]
+pub struct StageTiming {
+    /// Faces going through the stage together, comma separated
+    pub faces: String,
+    pub stage: &'static str,
+    pub calls: u32,
+    /// Wall time of the calls. GPU stages only record their work here, it runs when flushed.
+    pub cpu_ms: f64,
+    /// Time the stage ran on the device, when the adapter supports timestamp queries
+    pub gpu_ms: Option<f64>,
+    pub bytes_uploaded: u64,
+    pub bytes_read_back: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlanetProfile {
+    pub planet: String,
+    pub total_ms: f64,
+    pub stages: Vec<StageTiming>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfileReport {
+    pub backend: &'static str,
+    /// Name of the GPU adapter, None on the CPU backend
+    pub adapter: Option<String>,
+    /// Whether `gpu_ms` was measured
+    pub gpu_timestamps: bool,
+    pub planets: Vec<PlanetProfile>,
+}
+
+impl ProfileReport {
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Collects the stage timings of one planet, in the order the stages first ran.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    stages: RefCell<Vec<StageTiming>>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `f`, adding its wall time to `stage` of `faces`.
+    pub fn time<T>(&self, faces: &str, stage: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let value = f();
+        let elapsed = start.elapsed();
+        self.update(faces, stage, |timing| {
+            timing.calls += 1;
+            timing.cpu_ms += milliseconds(elapsed);
+        });
+        value
+    }
+
+    pub fn add_uploaded(&self, faces: &str, stage: &'static str, bytes: usize) {
+        self.update(faces, stage, |timing| timing.bytes_uploaded += bytes as u64);
+    }
+
+    pub fn add_read_back(&self, faces: &str, stage: &'static str, bytes: usize) {
+        self.update(faces, stage, |timing| timing.bytes_read_back += bytes as u64);
+    }
+
+    pub fn add_device_times(&self, times: Vec<DeviceTime>) {
+        for time in times {
+            self.update(&time.faces, time.stage, |timing| {
+                *timing.gpu_ms.get_or_insert(0.0) += time.milliseconds;
+            });
+        }
+    }
+
+    pub fn finish(self, planet: &str, total: Duration) -> PlanetProfile {
+        PlanetProfile {
+            planet: planet.to_owned(),
+            total_ms: milliseconds(total),
+            stages: self.stages.into_inner(),
+        }
+    }
+
+    fn update(&self, faces: &str, stage: &'static str, f: impl FnOnce(&mut StageTiming)) {
+        let mut stages = self.stages.borrow_mut();
+        match stages.iter_mut().find(|timing| timing.faces == faces && timing.stage == stage) {
+            Some(timing) => f(timing),
+            None => {
+                let mut timing = StageTiming {
+                    faces: faces.to_owned(),
+                    stage,
+                    calls: 0,
+                    cpu_ms: 0.0,
+                    gpu_ms: None,
+                    bytes_uploaded: 0,
+                    bytes_read_back: 0,
+                };
+                f(&mut timing);
+                stages.push(timing);
+            }
+        }
+    }
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,160 @@
+// Single image maps of the whole planet, sampled from the six cube faces
+
+use std::{f32::consts::PI, str::FromStr};
+
+use crate::{
+    cpu::texture::CpuTexture,
+    error::{check_size, Error, Result},
+};
+
+use super::{cubemap::cube_texel, lutgen::lat_lon_to_face};
+
+/// Map projections `reproject` can write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Projection {
+    /// Longitude and latitude mapped linearly, twice as wide as high
+    Equirectangular,
+    /// Square Web Mercator, cut at +-85.05 degrees latitude like slippy maps
+    Mercator,
+    /// Azimuthal equidistant view of the northern hemisphere, pole in the middle and the
+    /// equator on the edge of the disk
+    PolarNorth,
+    /// Same for the southern hemisphere
+    PolarSouth,
+}
+
+impl FromStr for Projection {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "equirectangular" | "equirect" => Ok(Projection::Equirectangular),
+            "mercator" => Ok(Projection::Mercator),
+            "polar-north" => Ok(Projection::PolarNorth),
+            "polar-south" => Ok(Projection::PolarSouth),
+            _ => Err(format!(
+                "Invalid projection {}, expected equirectangular, mercator, polar-north or polar-south",
+                s
+            )),
+        }
+    }
+}
+
+impl Projection {
+    /// Height of a map `width` texels wide.
+    pub fn height(&self, width: u32) -> u32 {
+        match self {
+            Projection::Equirectangular => (width / 2).max(1),
+            _ => width,
+        }
+    }
+
+    /// Latitude and longitude in degrees at `(s, t)`, both 0..1 from the top left corner of
+    /// the map. None outside of the mapped area.
+    pub fn lat_lon(&self, s: f32, t: f32) -> Option<(f32, f32)> {
+        match self {
+            Projection::Equirectangular => Some((90.0 - t * 180.0, s * 360.0 - 180.0)),
+            Projection::Mercator => {
+                let latitude = (PI * (1.0 - 2.0 * t)).sinh().atan().to_degrees();
+                Some((latitude, s * 360.0 - 180.0))
+            }
+            Projection::PolarNorth | Projection::PolarSouth => {
+                let (dx, dy) = (s * 2.0 - 1.0, t * 2.0 - 1.0);
+                let r = (dx * dx + dy * dy).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                // Longitude 0 points down on the north map and up on the south one, so both
+                // maps have east on the right
+                if *self == Projection::PolarNorth {
+                    Some((90.0 - r * 90.0, dx.atan2(dy).to_degrees()))
+                } else {
+                    Some((r * 90.0 - 90.0, dx.atan2(-dy).to_degrees()))
+                }
+            }
+        }
+    }
+}
+
+/// How the faces are read between texel centers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// Closest texel, for maps holding ids like the material maps
+    Nearest,
+    /// Bilinear blend of the four closest texels, across face edges too
+    Bilinear,
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "nearest" => Ok(Filter::Nearest),
+            "bilinear" => Ok(Filter::Bilinear),
+            _ => Err(format!("Invalid filter {}, expected nearest or bilinear", s)),
+        }
+    }
+}
+
+/// Reads the cube map at `(u, v)` in -1..1 of a face.
+pub fn sample_cube(faces: &[CpuTexture], face_num: u32, u: f32, v: f32, filter: Filter) -> [f32; 4] {
+    let (width, height) = (faces[0].width(), faces[0].height());
+    let x = (u + 1.0) * 0.5 * width as f32 - 0.5;
+    let y = (v + 1.0) * 0.5 * height as f32 - 0.5;
+    let load = |x: i32, y: i32| {
+        let (n, x, y) = cube_texel(face_num, x, y, width, height);
+        faces[n as usize].load(x, y)
+    };
+
+    match filter {
+        Filter::Nearest => load(x.round() as i32, y.round() as i32),
+        Filter::Bilinear => {
+            let (x0, y0) = (x.floor(), y.floor());
+            let (fx, fy) = (x - x0, y - y0);
+            let (x0, y0) = (x0 as i32, y0 as i32);
+            let (t00, t10) = (load(x0, y0), load(x0 + 1, y0));
+            let (t01, t11) = (load(x0, y0 + 1), load(x0 + 1, y0 + 1));
+            let mut texel = [0.0; 4];
+            for (c, value) in texel.iter_mut().enumerate() {
+                let top = t00[c] + (t10[c] - t00[c]) * fx;
+                let bottom = t01[c] + (t11[c] - t01[c]) * fx;
+                *value = top + (bottom - top) * fy;
+            }
+            texel
+        }
+    }
+}
+
+/// Draws the six faces of a cube map, in `CUBEMAP` order, as one `width` texels wide map.
+/// Texels outside of the mapped area are left transparent.
+pub fn reproject(
+    faces: &[CpuTexture],
+    projection: Projection,
+    width: u32,
+    filter: Filter,
+) -> Result<CpuTexture> {
+    if faces.len() != 6 {
+        return Err(Error::BadDefinition(format!("A cube map has 6 faces, got {}", faces.len())));
+    }
+    let size = (faces[0].width(), faces[0].height());
+    for face in faces {
+        check_size("Cube map face", size, (face.width(), face.height()))?;
+    }
+
+    let height = projection.height(width);
+    let mut texture = CpuTexture::new(width, height, faces[0].format())?;
+    texture.fill(|x, y| {
+        let s = (x as f32 + 0.5) / width as f32;
+        let t = (y as f32 + 0.5) / height as f32;
+        match projection.lat_lon(s, t) {
+            Some((latitude, longitude)) => {
+                let (face_num, u, v) = lat_lon_to_face(latitude, longitude);
+                sample_cube(faces, face_num, u, v, filter)
+            }
+            None => [0.0; 4],
+        }
+    });
+
+    Ok(texture)
+}
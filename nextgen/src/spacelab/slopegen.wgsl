@@ -1,27 +1,117 @@
 @group(0)
 @binding(0)
-var height_map: texture_2d<f32>;
+var height_map: texture_2d_array<f32>;
 
 @group(0)
 @binding(1)
-var texture: texture_storage_2d<rgba8unorm, write>;
+var texture: texture_storage_2d_array<rgba8unorm, write>;
+
+struct TerrainScale {
+    physical: u32,
+    // Metres between heightmap values 0 and 1
+    height_range: f32,
+    radius: f32,
+    pad: f32,
+};
+
+@group(0)
+@binding(2)
+var<uniform> scale: TerrainScale;
+
+// Where the output sits on the face, see `tiling::FaceTile`
+struct FaceTile {
+    origin: vec2<u32>,
+    face_size: vec2<u32>,
+    // Texels of the height map around the output
+    halo: u32,
+    // The halo holds the adjacent faces, rather than wrapping around the face
+    seamless: u32,
+};
+
+@group(0)
+@binding(3)
+var<uniform> tile: FaceTile;
 
 const rad: f32 = 1.5707963267948966;
 
+// Metres covered by a texel of the face along x and y. The cube projection squeezes
+// texels toward the face edges.
+fn texel_size(x: i32, y: i32, size: vec2<u32>) -> vec2<f32> {
+    let u = (f32(x) + 0.5) / f32(size.x) * 2.0 - 1.0;
+    let v = (f32(y) + 0.5) / f32(size.y) * 2.0 - 1.0;
+    let d = 1.0 + u * u + v * v;
+    return vec2<f32>(
+        scale.radius * 2.0 / f32(size.x) * sqrt(1.0 + v * v) / d,
+        scale.radius * 2.0 / f32(size.y) * sqrt(1.0 + u * u) / d,
+    );
+}
+
+// Height of a texel of the face in a layer of the height map
+fn height_at(x: i32, y: i32, layer: i32) -> f32 {
+    let halo = i32(tile.halo);
+    let texel = vec2<i32>(x, y) - vec2<i32>(tile.origin) + vec2<i32>(halo, halo);
+    return textureLoad(height_map, texel, layer, 0).r;
+}
+
 @compute
 @workgroup_size(8,8)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    let width = u32(textureDimensions(height_map).x);
-    let height = u32(textureDimensions(height_map).y);
+    let width = i32(tile.face_size.x);
+    let height = i32(tile.face_size.y);
+    let seamless = tile.seamless != 0u;
+    // Every layer is a face of its own
+    let layer = i32(global_id.z);
+
+    let x0 = i32(tile.origin.x) + i32(global_id.x);
+    let y0 = i32(tile.origin.y) + i32(global_id.y);
+
+    if (scale.physical != 0u) {
+        // Forward differences, stepping back on the last column and row when there is no border.
+        // A face one texel across has no neighbour to step to, and no slope along that axis.
+        var xa = x0;
+        var ya = y0;
+        if (!seamless && x0 + 1 >= width) {
+            xa = max(x0 - 1, 0);
+        }
+        if (!seamless && y0 + 1 >= height) {
+            ya = max(y0 - 1, 0);
+        }
+        var dzx = height_at(xa + 1, y0, layer) - height_at(xa, y0, layer);
+        var dzy = height_at(x0, ya + 1, layer) - height_at(x0, ya, layer);
+        if (!seamless && width == 1) {
+            dzx = 0.0;
+        }
+        if (!seamless && height == 1) {
+            dzy = 0.0;
+        }
 
-    let x0 = i32(global_id.x);
-    let y0 = i32(global_id.y);
+        let metres = texel_size(x0, y0, tile.face_size);
+        let gx = dzx * scale.height_range / metres.x;
+        let gy = dzy * scale.height_range / metres.y;
+        let normalized_a = atan(sqrt(gx * gx + gy * gy)) / rad;
+        textureStore(texture, vec2<i32>(global_id.xy), layer, vec4<f32>(normalized_a, normalized_a, normalized_a, 1.0));
+        return;
+    }
 
-    let x1 = i32(u32(x0 + 1) % width);
-    let y1 = i32(u32(y0 + 1) % height);
+    var x1 = x0 + 1;
+    var y1 = y0 + 1;
+    var z1: f32;
+    if (seamless) {
+        z1 = height_at(x1, y1, layer);
+    } else {
+        // Without a border, wrap onto the opposite edge of the face. A tile's halo already
+        // holds the wrapped texels.
+        let unwrapped = vec2<i32>(x1, y1);
+        x1 = x1 % width;
+        y1 = y1 % height;
+        if (tile.halo > 0u) {
+            z1 = height_at(unwrapped.x, unwrapped.y, layer);
+        } else {
+            z1 = height_at(x1, y1, layer);
+        }
+    }
 
-    let z0 = textureLoad(height_map, vec2<i32>(x0, y0), 0).r;
-    let z1 = textureLoad(height_map, vec2<i32>(x1, y1), 0).r;
+    let z0 = height_at(x0, y0, layer);
 
     let delta_z = 255.0 * (z1 - z0);
     let delta_x = (f32(x1) - f32(x0)); // /f32(width);
@@ -32,5 +122,5 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
 
     let normalized_a = a / rad; // Normalized radians
 
-    textureStore(texture, vec2<i32>(x0, y0), vec4<f32>(normalized_a, normalized_a, normalized_a, 1.0));
-}
\ No newline at end of file
+    textureStore(texture, vec2<i32>(global_id.xy), layer, vec4<f32>(normalized_a, normalized_a, normalized_a, 1.0));
+}
@@ -0,0 +1,293 @@
+// z/x/y tile pyramids of the planet maps, for zoomable web maps
+
+use std::{path::Path, str::FromStr};
+
+use image::imageops;
+use serde::Serialize;
+
+use crate::{
+    cpu::texture::CpuTexture,
+    error::{check_size, Error, Result},
+};
+
+use super::{
+    lutgen::{lat_lon_to_face, CUBEMAP},
+    projection::{sample_cube, Filter, Projection},
+};
+
+/// How the planet is cut into tiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileScheme {
+    /// A quadtree per cube face, `{face}/{z}/{x}/{y}`
+    Face,
+    /// Standard web Mercator tiles sampled from the cube faces, `{z}/{x}/{y}`
+    Mercator,
+}
+
+impl FromStr for TileScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "face" => Ok(TileScheme::Face),
+            "mercator" => Ok(TileScheme::Mercator),
+            _ => Err(format!("Invalid tile scheme {}, expected face or mercator", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileOptions {
+    /// Width and height of a tile in texels
+    pub tile_size: u32,
+    /// Deepest zoom level. Defaults to the level where tiles reach the source resolution.
+    pub max_zoom: Option<u32>,
+    /// Image format of the tiles, png, jpg or webp
+    pub format: String,
+}
+
+/// Contents of the `manifest.json` written next to the tiles.
+#[derive(Debug, Serialize)]
+pub struct TileManifest {
+    /// "face", "mercator" or "map"
+    pub scheme: String,
+    /// Path of a tile relative to the manifest
+    pub url: String,
+    pub format: String,
+    pub tile_size: u32,
+    pub min_zoom: u32,
+    pub max_zoom: u32,
+    /// Tiles across and down at zoom 0, every level doubles both
+    pub root_tiles: [u32; 2],
+    /// Faces that have tiles, in `CUBEMAP` order. Only for the face scheme.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub faces: Vec<String>,
+    /// Size in texels of the source faces or map
+    pub source_size: [u32; 2],
+}
+
+// Zoom levels needed for `texels` of source to fit in tiles at full resolution
+fn zoom_levels(texels: u32, tile_size: u32) -> u32 {
+    let mut zoom = 0;
+    while tile_size.checked_shl(zoom).is_some_and(|size| size < texels) {
+        zoom += 1;
+    }
+    zoom
+}
+
+fn resized(texture: &CpuTexture, width: u32, height: u32) -> Result<CpuTexture> {
+    if (width, height) == (texture.width(), texture.height()) {
+        return Ok(texture.clone());
+    }
+    let image = texture.to_image()?.to_rgba8();
+    let image = imageops::resize(&image, width, height, imageops::FilterType::Triangle);
+    CpuTexture::from_data(width, height, wgpu::TextureFormat::Rgba8Unorm, image.into_raw())
+}
+
+fn crop(texture: &CpuTexture, x: u32, y: u32, tile_size: u32) -> Result<CpuTexture> {
+    let mut tile = CpuTexture::new(tile_size, tile_size, texture.format())?;
+    tile.fill(|tx, ty| texture.load(x * tile_size + tx, y * tile_size + ty));
+    Ok(tile)
+}
+
+fn save_tile(tile: &CpuTexture, folder: &Path, x: u32, y: u32, options: &TileOptions) -> Result<()> {
+    let folder = folder.join(x.to_string());
+    std::fs::create_dir_all(&folder)?;
+    tile.save_to_file(&folder.join(format!("{}.{}", y, options.format)).to_string_lossy())
+}
+
+fn check_options(options: &TileOptions) -> Result<()> {
+    if options.tile_size == 0 {
+        return Err(Error::BadDefinition("Tiles are empty, the tile size is 0".to_owned()));
+    }
+    match options.format.as_str() {
+        "png" | "jpg" | "jpeg" => Ok(()),
+        // The image crate only encodes WebP when built with libwebp, behind the `webp` feature
+        "webp" if cfg!(feature = "webp") => Ok(()),
+        "webp" => Err(Error::UnsupportedFormat(
+            "WebP tiles, build with `--features webp` to encode WebP".to_owned(),
+        )),
+        format => Err(Error::UnsupportedFormat(format!("tile format {}, expected png, jpg or webp", format))),
+    }
+}
+
+// Cuts an image into `root_tiles` tiles at zoom 0, doubling every level
+fn write_pyramid(
+    source: &CpuTexture,
+    folder: &Path,
+    root_tiles: [u32; 2],
+    max_zoom: u32,
+    options: &TileOptions,
+) -> Result<()> {
+    for zoom in 0..=max_zoom {
+        let (tiles_x, tiles_y) = (root_tiles[0] << zoom, root_tiles[1] << zoom);
+        let level = resized(source, tiles_x * options.tile_size, tiles_y * options.tile_size)?;
+        let level_folder = folder.join(zoom.to_string());
+        for x in 0..tiles_x {
+            for y in 0..tiles_y {
+                save_tile(&crop(&level, x, y, options.tile_size)?, &level_folder, x, y, options)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the tiles of the six faces of a cube map, in `CUBEMAP` order, under `output`.
+pub fn tile_faces(
+    faces: &[CpuTexture],
+    output: &Path,
+    scheme: TileScheme,
+    options: &TileOptions,
+) -> Result<TileManifest> {
+    check_options(options)?;
+    if faces.len() != 6 {
+        return Err(Error::BadDefinition(format!("A cube map has 6 faces, got {}", faces.len())));
+    }
+    let size = faces[0].width();
+    for face in faces {
+        check_size("Cube map face", (size, size), (face.width(), face.height()))?;
+    }
+
+    let tile_size = options.tile_size;
+    let max_zoom = match scheme {
+        TileScheme::Face => {
+            let max_zoom = options.max_zoom.unwrap_or_else(|| zoom_levels(size, tile_size));
+            for (face, texture) in CUBEMAP.iter().zip(faces) {
+                write_pyramid(texture, &output.join(face), [1, 1], max_zoom, options)?;
+            }
+            max_zoom
+        }
+        TileScheme::Mercator => {
+            // Four faces wrap around the equator
+            let max_zoom = options.max_zoom.unwrap_or_else(|| zoom_levels(size * 4, tile_size));
+            for zoom in 0..=max_zoom {
+                let tiles = 1 << zoom;
+                let world = (tiles * tile_size) as f32;
+                // Sample faces about as detailed as the level, so far out levels don't alias
+                let face_size = (tiles * tile_size / 4).clamp(1, size);
+                let level_faces = faces
+                    .iter()
+                    .map(|face| resized(face, face_size, face_size))
+                    .collect::<Result<Vec<_>>>()?;
+                let level_folder = output.join(zoom.to_string());
+                for x in 0..tiles {
+                    for y in 0..tiles {
+                        let mut tile = CpuTexture::new(tile_size, tile_size, faces[0].format())?;
+                        tile.fill(|tx, ty| {
+                            let s = ((x * tile_size + tx) as f32 + 0.5) / world;
+                            let t = ((y * tile_size + ty) as f32 + 0.5) / world;
+                            let (latitude, longitude) = Projection::Mercator.lat_lon(s, t).unwrap();
+                            let (face_num, u, v) = lat_lon_to_face(latitude, longitude);
+                            sample_cube(&level_faces, face_num, u, v, Filter::Bilinear)
+                        });
+                        save_tile(&tile, &level_folder, x, y, options)?;
+                    }
+                }
+            }
+            max_zoom
+        }
+    };
+
+    let (scheme, url, faces) = match scheme {
+        TileScheme::Face => ("face", "{face}/{z}/{x}/{y}", CUBEMAP.iter().map(|f| f.to_string()).collect()),
+        TileScheme::Mercator => ("mercator", "{z}/{x}/{y}", Vec::new()),
+    };
+    Ok(TileManifest {
+        scheme: scheme.to_owned(),
+        url: format!("{}.{}", url, options.format),
+        format: options.format.clone(),
+        tile_size,
+        min_zoom: 0,
+        max_zoom,
+        root_tiles: [1, 1],
+        faces,
+        source_size: [size, size],
+    })
+}
+
+/// Writes the tiles of a single map, like the output of `projection::reproject`. Maps twice
+/// as wide as high (equirectangular) start with two tiles side by side.
+pub fn tile_map(map: &CpuTexture, output: &Path, options: &TileOptions) -> Result<TileManifest> {
+    check_options(options)?;
+    let (width, height) = (map.width(), map.height());
+    if width % height != 0 {
+        return Err(Error::SizeMismatch {
+            name: "Tiled map".to_owned(),
+            expected: (height * (width / height).max(1), height),
+            found: (width, height),
+        });
+    }
+
+    let root_tiles = [width / height, 1];
+    let max_zoom = options.max_zoom.unwrap_or_else(|| zoom_levels(height, options.tile_size));
+    write_pyramid(map, output, root_tiles, max_zoom, options)?;
+
+    Ok(TileManifest {
+        scheme: "map".to_owned(),
+        url: format!("{{z}}/{{x}}/{{y}}.{}", options.format),
+        format: options.format.clone(),
+        tile_size: options.tile_size,
+        min_zoom: 0,
+        max_zoom,
+        root_tiles,
+        faces: Vec::new(),
+        source_size: [width, height],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(format: &str) -> TileOptions {
+        TileOptions {
+            tile_size: 2,
+            max_zoom: None,
+            format: format.to_owned(),
+        }
+    }
+
+    #[test]
+    fn zoom_levels_reach_full_resolution() {
+        assert_eq!(zoom_levels(1, 256), 0);
+        assert_eq!(zoom_levels(256, 256), 0);
+        assert_eq!(zoom_levels(257, 256), 1);
+        assert_eq!(zoom_levels(1024, 256), 2);
+        assert_eq!(zoom_levels(1025, 256), 3);
+    }
+
+    #[test]
+    fn tile_map_roots_follow_the_aspect_ratio() {
+        let output = std::env::temp_dir().join(format!("nextgen-tiles-{}", std::process::id()));
+        let map = CpuTexture::new(8, 4, wgpu::TextureFormat::Rgba8Unorm).unwrap();
+
+        let manifest = tile_map(&map, &output, &options("png")).unwrap();
+        assert_eq!(manifest.root_tiles, [2, 1]);
+        assert_eq!(manifest.max_zoom, 1);
+        assert!(output.join("0/1/0.png").exists());
+        assert!(output.join("1/3/1.png").exists());
+        assert!(!output.join("1/4").exists());
+        std::fs::remove_dir_all(&output).unwrap();
+
+        let map = CpuTexture::new(6, 4, wgpu::TextureFormat::Rgba8Unorm).unwrap();
+        match tile_map(&map, &output, &options("png")) {
+            Err(Error::SizeMismatch { expected, found, .. }) => {
+                assert_eq!(expected, (4, 4));
+                assert_eq!(found, (6, 4));
+            }
+            other => panic!("expected a size mismatch, got {:?}", other.map(|m| m.root_tiles)),
+        }
+    }
+
+    #[test]
+    fn webp_needs_the_feature() {
+        assert!(check_options(&options("png")).is_ok());
+        assert!(check_options(&options("tga")).is_err());
+        assert_eq!(check_options(&options("webp")).is_ok(), cfg!(feature = "webp"));
+        let empty = TileOptions {
+            tile_size: 0,
+            ..options("png")
+        };
+        assert!(check_options(&empty).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+// Splitting faces into tiles, for faces larger than the backend's textures
+
+use crate::{
+    cpu::texture::CpuTexture,
+    error::{check_size, Error, Result},
+};
+
+use super::cubemap::cube_texel;
+
+/// The part of a face a stage runs on.
+///
+/// Stages read their inputs `halo` texels past every side of the tile, for the neighbourhoods
+/// of the slope, normal and ore filters, and write only the tile. Texels past the face edges
+/// come from the adjacent faces when `seamless`, otherwise the halo wraps around the face.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaceTile {
+    /// Face texel under the top left texel of the tile
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub face_width: u32,
+    pub face_height: u32,
+    pub halo: u32,
+    pub seamless: bool,
+}
+
+impl FaceTile {
+    /// The whole face, without a halo.
+    pub fn whole(width: u32, height: u32) -> Self {
+        FaceTile {
+            x: 0,
+            y: 0,
+            width,
+            height,
+            face_width: width,
+            face_height: height,
+            halo: 0,
+            seamless: false,
+        }
+    }
+
+    /// The whole face of an input with `border` texels of the adjacent faces around it, like
+    /// `cubemap::bordered_face` makes.
+    pub fn bordered(input_width: u32, input_height: u32, border: u32) -> Self {
+        FaceTile {
+            halo: border,
+            seamless: border > 0,
+            ..FaceTile::whole(input_width - 2 * border, input_height - 2 * border)
+        }
+    }
+
+    /// Same tile, with another halo.
+    pub fn with_halo(&self, halo: u32) -> Self {
+        FaceTile { halo, ..*self }
+    }
+
+    /// Size of the inputs of a stage run on the tile.
+    pub fn input_size(&self) -> (u32, u32) {
+        (self.width + 2 * self.halo, self.height + 2 * self.halo)
+    }
+
+    /// Whether the tile covers the face.
+    pub fn is_whole(&self) -> bool {
+        (self.width, self.height) == (self.face_width, self.face_height)
+    }
+}
+
+/// Splits a `width` x `height` face into tiles whose inputs fit in `max_size` texels.
+///
+/// The tiles have the one texel halo the slope and normal stages need, and leave room for
+/// `reach` texels of halo for the other stages. A face that fits in a single pass is a single
+/// tile, with a halo only when `seamless` like `FaceTile::bordered`.
+pub fn split_face(width: u32, height: u32, max_size: u32, seamless: bool, reach: u32) -> Result<Vec<FaceTile>> {
+    let border = seamless as u32;
+    if width + 2 * border <= max_size && height + 2 * border <= max_size {
+        return Ok(vec![FaceTile::bordered(width + 2 * border, height + 2 * border, border)]);
+    }
+    let halo = reach.max(1);
+    if max_size <= 2 * halo {
+        return Err(Error::BadDefinition(format!(
+            "Tiles of {} texels leave no room inside a halo of {}",
+            max_size, halo
+        )));
+    }
+
+    let size = max_size - 2 * halo;
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(size as usize) {
+        for x in (0..width).step_by(size as usize) {
+            tiles.push(FaceTile {
+                x,
+                y,
+                width: size.min(width - x),
+                height: size.min(height - y),
+                face_width: width,
+                face_height: height,
+                halo: 1,
+                seamless,
+            });
+        }
+    }
+    Ok(tiles)
+}
+
+/// The texels of `faces[face_num]` under the tile and its halo. `faces` holds the six faces
+/// in `CUBEMAP` order when the tile is seamless, otherwise only the face the tile is on.
+pub fn tile_input(faces: &[CpuTexture], face_num: u32, tile: &FaceTile) -> Result<CpuTexture> {
+    let face = if tile.seamless {
+        if faces.len() != 6 {
+            return Err(Error::BadDefinition(format!("A cube map has 6 faces, got {}", faces.len())));
+        }
+        &faces[face_num as usize]
+    } else {
+        &faces[0]
+    };
+    for other in faces {
+        check_size("Tiled face", (tile.face_width, tile.face_height), (other.width(), other.height()))?;
+    }
+
+    let (width, height) = tile.input_size();
+    let mut texture = CpuTexture::new(width, height, face.format())?;
+    texture.fill(|x, y| {
+        let x = (tile.x + x) as i32 - tile.halo as i32;
+        let y = (tile.y + y) as i32 - tile.halo as i32;
+        if tile.seamless {
+            let (n, x, y) = cube_texel(face_num, x, y, tile.face_width, tile.face_height);
+            faces[n as usize].load(x, y)
+        } else {
+            let x = x.rem_euclid(tile.face_width as i32) as u32;
+            let y = y.rem_euclid(tile.face_height as i32) as u32;
+            face.load(x, y)
+        }
+    });
+    Ok(texture)
+}
+
+/// The texels of a single face under the tile and `halo` texels around it, wrapping around
+/// the face past its edges.
+pub fn crop(face: &CpuTexture, tile: &FaceTile, halo: u32) -> Result<CpuTexture> {
+    let tile = FaceTile {
+        halo,
+        seamless: false,
+        ..*tile
+    };
+    tile_input(std::slice::from_ref(face), 0, &tile)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spacelab::cubemap::bordered_face;
+
+    use super::*;
+
+    // Faces whose texels hold their face number and position
+    fn labelled_faces(size: u32) -> Vec<CpuTexture> {
+        (0..6)
+            .map(|n| {
+                let mut face = CpuTexture::new(size, size, wgpu::TextureFormat::R32Float).unwrap();
+                face.fill(|x, y| [(n * 10000 + y * 100 + x) as f32, 0.0, 0.0, 0.0]);
+                face
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tiles_cover_the_face_once() {
+        for (width, height, max_size, seamless, reach) in [
+            (10, 10, 16, true, 0),
+            (10, 10, 12, false, 2),
+            (37, 23, 10, true, 1),
+            (37, 23, 9, false, 3),
+            (64, 64, 18, true, 2),
+        ] {
+            let tiles = split_face(width, height, max_size, seamless, reach).unwrap();
+            let mut covered = vec![0; (width * height) as usize];
+            for tile in &tiles {
+                assert_eq!((tile.face_width, tile.face_height, tile.seamless), (width, height, seamless));
+                let (input_width, input_height) = tile.input_size();
+                assert!(input_width <= max_size && input_height <= max_size, "{:?}", tile);
+                if tiles.len() > 1 {
+                    let (reach_width, reach_height) = tile.with_halo(reach.max(1)).input_size();
+                    assert!(reach_width <= max_size && reach_height <= max_size, "{:?}", tile);
+                }
+                for y in tile.y..tile.y + tile.height {
+                    for x in tile.x..tile.x + tile.width {
+                        covered[(y * width + x) as usize] += 1;
+                    }
+                }
+            }
+            assert!(covered.iter().all(|&count| count == 1), "{}x{} in {}", width, height, max_size);
+        }
+        assert!(split_face(100, 100, 4, true, 2).is_err());
+    }
+
+    #[test]
+    fn seamless_halos_line_up_with_the_bordered_face() {
+        let faces = labelled_faces(12);
+        for face_num in 0..6 {
+            let bordered = bordered_face(&faces, face_num, 2).unwrap();
+            for tile in split_face(12, 12, 9, true, 2).unwrap() {
+                let tile = tile.with_halo(2);
+                let input = tile_input(&faces, face_num, &tile).unwrap();
+                assert_eq!((input.width(), input.height()), tile.input_size());
+                for y in 0..input.height() {
+                    for x in 0..input.width() {
+                        assert_eq!(input.load(x, y), bordered.load(tile.x + x, tile.y + y), "{:?}", tile);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn halos_wrap_around_a_single_face() {
+        let face = &labelled_faces(12)[3];
+        for tile in split_face(12, 12, 9, false, 2).unwrap() {
+            let input = crop(face, &tile, 2).unwrap();
+            for y in 0..input.height() {
+                for x in 0..input.width() {
+                    let fx = (tile.x + x + 12 - 2) % 12;
+                    let fy = (tile.y + y + 12 - 2) % 12;
+                    assert_eq!(input.load(x, y), face.load(fx, fy), "{:?}", tile);
+                }
+            }
+        }
+    }
+}
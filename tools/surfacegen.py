@@ -21,48 +21,11 @@ baseAssetPath = "assets/Vanilla/PlanetDataFiles/EarthLike/"
 # planetData = "assets/PlanetGeneratorDefinitions.sbc"
 # planetName = "Europa"
 # baseAssetPath = "assets/Europa/"
-oreElementShort = {
-    "Iron_01": "Fe",
-    "Nickel_01": "Ni",
-    "Silicon_01": "Si",
-    "Magnesium_01": "Mg",
-    "Cobalt_01": "Co",
-    "Silver_01": "Ag",
-    "Gold_01": "Au",
-    "Platinum_01": "Pt",
-    "Uraninite_01": "U",
-    "Copper": "Cu",
-    "Bauxite": "Al",
-    "Coal": "C",
-    "Titanium": "Ti",
-    "OilSand": "Oil",
-    "Sulfur": "S",
-    "Lithium": "Li",
-    "Tantalum": "Ta",
-    "Cronyx": "Cr",
-    "Dorium": "Dor"
-}
-oreColors = {
-    "Iron_01": (255,216,0),
-    "Nickel_01": (239,166,117),
-    "Silicon_01": (216,107,128),
-    "Magnesium_01": (0, 255, 255),
-    "Cobalt_01": (181,254,0),
-    "Silver_01": (145,145,145),
-    "Gold_01": (255,0,220),
-    "Platinum_01": (219,249,255),
-    "Uraninite_01": (155,114,241),
-    "Copper": (184,115,51),
-    "Bauxite": (249,166,64),
-    "Coal": (145,145,145),
-    "Titanium": (81,127,84),
-    "OilSand": (183,0,3),
-    "Sulfur": (190,167,151),
-    "Lithium": (172,80,141),
-    "Tantalum": (255,0,0),
-    "Cronyx": (184,115,51),
-    "Dorium": (181,254,0)
-}
+# Ore colors and element symbols, shared with nextgen
+with open("./luts/orepalette.json") as f:
+    orePalette = json.loads(f.read())
+oreElementShort = {oreType: ore["symbol"] for oreType, ore in orePalette.items()}
+oreColors = {oreType: tuple(ore["color"]) for oreType, ore in orePalette.items()}
 
 if __name__ == "__main__":
     freeze_support()